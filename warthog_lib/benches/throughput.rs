@@ -0,0 +1,306 @@
+//! Throughput and latency of the auth server for different workloads and concurrency levels.
+//!
+//! Every run starts an in-process server on an ephemeral port using the in-memory providers.
+//!
+//! Run all benchmarks with `cargo bench -p warthog_lib --bench throughput`,
+//! or only some by adding their names, e.g. `cargo bench -p warthog_lib --bench throughput -- logon reconnect`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use warthog_lib::memory::{
+    MemoryCredentialProvider, MemoryGameFileProvider, MemoryKeyStorage, MemoryPatchProvider,
+    MemoryRealmListProvider,
+};
+use warthog_lib::{
+    start_auth_server_with_listener, Options, PatchFile, Population, Realm, RealmCategory,
+    RealmType, Realm_RealmFlag,
+};
+use wow_client::{
+    connect_and_authenticate, download_patch, reconnect, request_realm_list,
+    CMD_AUTH_LOGON_CHALLENGE_Client, CMD_AUTH_RECONNECT_CHALLENGE_Client, Locale, Os, Platform,
+    ProtocolVersion, Version,
+};
+
+const CONCURRENCY_LEVELS: &[usize] = &[1, 8, 64, 256];
+/// Total amount of operations for each concurrency level.
+const OPERATIONS: usize = 1024;
+const MAX_USERS: usize = 256;
+const PASSWORD: &str = "PASSWORD";
+
+const CLIENT_BUILD: u16 = 5875;
+/// Clients with this build are sent a patch.
+const PATCH_BUILD: u16 = 5302;
+const PATCH_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Scenario {
+    Logon,
+    Reconnect,
+    RealmList,
+    Transfer,
+}
+
+impl Scenario {
+    const fn name(&self) -> &'static str {
+        match self {
+            Scenario::Logon => "logon",
+            Scenario::Reconnect => "reconnect",
+            Scenario::RealmList => "realm_list",
+            Scenario::Transfer => "transfer",
+        }
+    }
+}
+
+fn username(i: usize) -> String {
+    format!("BENCH{i}")
+}
+
+fn challenge(account_name: String, build: u16) -> CMD_AUTH_LOGON_CHALLENGE_Client {
+    CMD_AUTH_LOGON_CHALLENGE_Client {
+        protocol_version: ProtocolVersion::Three,
+        version: Version {
+            major: 1,
+            minor: 12,
+            patch: 1,
+            build,
+        },
+        platform: Platform::X86,
+        os: Os::Windows,
+        locale: Locale::EnGb,
+        utc_timezone_offset: 60,
+        client_ip_address: Ipv4Addr::new(127, 0, 0, 1),
+        account_name,
+    }
+}
+
+fn reconnect_challenge(account_name: String) -> CMD_AUTH_RECONNECT_CHALLENGE_Client {
+    let c = challenge(account_name, CLIENT_BUILD);
+
+    CMD_AUTH_RECONNECT_CHALLENGE_Client {
+        protocol_version: c.protocol_version,
+        version: c.version,
+        platform: c.platform,
+        os: c.os,
+        locale: c.locale,
+        utc_timezone_offset: c.utc_timezone_offset,
+        client_ip_address: c.client_ip_address,
+        account_name: c.account_name,
+    }
+}
+
+struct Server {
+    address: SocketAddr,
+    should_run: Arc<AtomicBool>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.should_run.store(false, Ordering::SeqCst);
+    }
+}
+
+async fn start_server(max_concurrent_crypto_operations: u32) -> Server {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let credentials = MemoryCredentialProvider::new();
+    for i in 0..MAX_USERS {
+        let name = username(i);
+        let c = MemoryCredentialProvider::credentials_from_password(&name, PASSWORD, None, None)
+            .unwrap();
+        credentials.insert_user(&name, c);
+    }
+
+    let patches = MemoryPatchProvider::new();
+    patches.insert_patch(
+        PATCH_BUILD,
+        PatchFile::new(vec![0xAB; PATCH_SIZE].into()).unwrap(),
+    );
+
+    let realms = MemoryRealmListProvider::new(vec![Realm {
+        realm_type: RealmType::try_from(0).unwrap(),
+        locked: false,
+        flag: Realm_RealmFlag::new(0, None),
+        name: "Bench Realm".to_string(),
+        address: "localhost:8085".to_string(),
+        population: Population::from(200.0),
+        number_of_characters_on_realm: 0,
+        category: RealmCategory::try_from(0).unwrap(),
+        realm_id: 0,
+    }]);
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let options = Options {
+        address,
+        randomize_pin_grid: false,
+        max_concurrent_users: 10_000,
+        max_concurrent_crypto_operations,
+        unknown_account_secret: None,
+    };
+
+    tokio::spawn(start_auth_server_with_listener(
+        listener,
+        credentials,
+        MemoryKeyStorage::new(),
+        patches,
+        MemoryGameFileProvider::new(),
+        realms,
+        should_run.clone(),
+        options,
+    ));
+
+    Server {
+        address,
+        should_run,
+    }
+}
+
+async fn run_task(
+    scenario: Scenario,
+    address: SocketAddr,
+    user: usize,
+    iterations: usize,
+) -> Vec<Duration> {
+    let name = username(user);
+    let mut latencies = Vec::with_capacity(iterations);
+
+    match scenario {
+        Scenario::Logon => {
+            for _ in 0..iterations {
+                let start = Instant::now();
+                connect_and_authenticate(
+                    challenge(name.clone(), CLIENT_BUILD),
+                    address,
+                    PASSWORD,
+                    None,
+                )
+                .await
+                .unwrap();
+                latencies.push(start.elapsed());
+            }
+        }
+        Scenario::Reconnect => {
+            let (client, _, _) = connect_and_authenticate(
+                challenge(name.clone(), CLIENT_BUILD),
+                address,
+                PASSWORD,
+                None,
+            )
+            .await
+            .unwrap();
+
+            for _ in 0..iterations {
+                let start = Instant::now();
+                reconnect(reconnect_challenge(name.clone()), address, &client)
+                    .await
+                    .unwrap();
+                latencies.push(start.elapsed());
+            }
+        }
+        Scenario::RealmList => {
+            let c = challenge(name.clone(), CLIENT_BUILD);
+            let protocol_version = c.protocol_version;
+            let (_, _, mut stream) = connect_and_authenticate(c, address, PASSWORD, None)
+                .await
+                .unwrap();
+
+            for _ in 0..iterations {
+                let start = Instant::now();
+                request_realm_list(&mut stream, protocol_version)
+                    .await
+                    .unwrap();
+                latencies.push(start.elapsed());
+            }
+        }
+        Scenario::Transfer => {
+            for _ in 0..iterations {
+                let start = Instant::now();
+                let (data, _) = download_patch(challenge(name.clone(), PATCH_BUILD), address)
+                    .await
+                    .unwrap();
+                assert_eq!(data.len(), PATCH_SIZE);
+                latencies.push(start.elapsed());
+            }
+        }
+    }
+
+    latencies
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i]
+}
+
+async fn bench(scenario: Scenario, max_concurrent_crypto_operations: u32) {
+    let server = start_server(max_concurrent_crypto_operations).await;
+
+    for &concurrency in CONCURRENCY_LEVELS {
+        let iterations = (OPERATIONS / concurrency).max(1);
+
+        let start = Instant::now();
+        let tasks = (0..concurrency)
+            .map(|i| {
+                tokio::spawn(run_task(
+                    scenario,
+                    server.address,
+                    i % MAX_USERS,
+                    iterations,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let mut latencies = Vec::with_capacity(concurrency * iterations);
+        for task in tasks {
+            latencies.extend(task.await.unwrap());
+        }
+        let elapsed = start.elapsed();
+
+        latencies.sort();
+        let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+
+        println!(
+            "{:<11} crypto={:<3} concurrency={:<4} {:>9.1} ops/s  p50={:>9.2?} p90={:>9.2?} p99={:>9.2?} max={:>9.2?}",
+            scenario.name(),
+            max_concurrent_crypto_operations,
+            concurrency,
+            throughput,
+            percentile(&latencies, 0.50),
+            percentile(&latencies, 0.90),
+            percentile(&latencies, 0.99),
+            latencies[latencies.len() - 1],
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let filters = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with('-'))
+        .collect::<Vec<_>>();
+
+    let cpus = std::thread::available_parallelism()
+        .map(|a| a.get() as u32)
+        .unwrap_or(4);
+
+    for scenario in [
+        Scenario::Logon,
+        Scenario::Reconnect,
+        Scenario::RealmList,
+        Scenario::Transfer,
+    ] {
+        if !filters.is_empty() && !filters.iter().any(|f| scenario.name().contains(f.as_str())) {
+            continue;
+        }
+
+        if scenario == Scenario::Logon {
+            // Compare SRP calculations on the async workers with the blocking pool
+            bench(scenario, 0).await;
+        }
+        bench(scenario, cpus).await;
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Runs CPU heavy cryptography on the blocking pool so that it does not starve the async workers.
+///
+/// At most `size` operations run at the same time, with the rest waiting asynchronously for a permit.
+/// A size of 0 runs the operations directly on the calling task instead.
+#[derive(Debug, Clone)]
+pub(crate) struct CryptoPool {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl CryptoPool {
+    pub(crate) fn new(size: u32) -> Self {
+        let permits = if size == 0 {
+            None
+        } else {
+            Some(Arc::new(Semaphore::new(size as usize)))
+        };
+
+        Self { permits }
+    }
+
+    pub(crate) async fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let Some(permits) = &self.permits else {
+            return f();
+        };
+
+        let _permit = permits
+            .acquire()
+            .await
+            .expect("crypto semaphore is never closed");
+
+        let span = tracing::Span::current();
+        match tokio::task::spawn_blocking(move || span.in_scope(f)).await {
+            Ok(v) => v,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
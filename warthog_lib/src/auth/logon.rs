@@ -1,10 +1,14 @@
+use crate::auth::crypto::CryptoPool;
 use crate::auth::send_realm_list;
 use crate::{
     CredentialProvider, Credentials, GameFileProvider, KeyStorage, Options, RealmListProvider,
+    UnknownAccountSecret,
 };
+use sha2::{Digest, Sha256};
 use std::io;
+use std::net::{IpAddr, Ipv4Addr};
 use tokio::net::TcpStream;
-use tracing::{error, trace};
+use tracing::{error, info, trace};
 use wow_login_messages::all::CMD_AUTH_LOGON_CHALLENGE_Client;
 use wow_login_messages::helper::tokio_expect_client_message_protocol;
 use wow_login_messages::version_8::{
@@ -16,7 +20,7 @@ use wow_login_messages::version_8::{
 use wow_login_messages::CollectiveMessage;
 use wow_srp::matrix_card::{get_matrix_card_seed, verify_matrix_card_hash};
 use wow_srp::normalized_string::NormalizedString;
-use wow_srp::pin::{get_pin_grid_seed, get_pin_salt};
+use wow_srp::pin::{get_pin_grid_seed, get_pin_salt, PinCode};
 use wow_srp::server::{SrpServer, SrpVerifier};
 use wow_srp::{PublicKey, GENERATOR, LARGE_SAFE_PRIME_LITTLE_ENDIAN};
 
@@ -26,7 +30,8 @@ use wow_srp::{PublicKey, GENERATOR, LARGE_SAFE_PRIME_LITTLE_ENDIAN};
     game_file_provider,
     realm_list_provider,
     options,
-    stream
+    stream,
+    crypto
 ))]
 pub(crate) async fn logon(
     mut provider: impl CredentialProvider,
@@ -36,9 +41,14 @@ pub(crate) async fn logon(
     mut stream: TcpStream,
     c: CMD_AUTH_LOGON_CHALLENGE_Client,
     options: &Options,
+    crypto: CryptoPool,
 ) -> io::Result<()> {
     trace!("connection received");
     let protocol_version = c.protocol_version;
+    // Read before the handshake since the peer can disconnect at any point
+    let address = stream
+        .peer_addr()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |a| a.ip());
 
     let Ok(username) = NormalizedString::new(&c.account_name) else {
         CMD_AUTH_LOGON_CHALLENGE_Server::FailUnknownAccount
@@ -49,13 +59,21 @@ pub(crate) async fn logon(
         return Ok(());
     };
 
-    let Some(credentials) = provider.get_user(&c).await else {
-        CMD_AUTH_LOGON_CHALLENGE_Server::FailUnknownAccount
-            .tokio_write_protocol(&mut stream, protocol_version)
-            .await?;
+    let (credentials, fake_account) = match provider.get_user(&c).await {
+        Some(credentials) => (credentials, false),
+        None => {
+            let Some(secret) = &options.unknown_account_secret else {
+                CMD_AUTH_LOGON_CHALLENGE_Server::FailUnknownAccount
+                    .tokio_write_protocol(&mut stream, protocol_version)
+                    .await?;
 
-        error!("username not found");
-        return Ok(());
+                info!("username not found");
+                return Ok(());
+            };
+
+            info!("username not found, sending fake challenge");
+            (fake_credentials(secret, &c), true)
+        }
     };
 
     let verifier = SrpVerifier::from_database_values(
@@ -63,7 +81,7 @@ pub(crate) async fn logon(
         credentials.password_verifier,
         credentials.salt,
     );
-    let proof = verifier.into_proof();
+    let proof = crypto.run(move || verifier.into_proof()).await;
 
     let crc_salt = wow_srp::integrity::get_salt_value();
 
@@ -127,7 +145,14 @@ pub(crate) async fn logon(
             return Ok(());
         }
     };
-    let Ok((server, server_proof)) = proof.into_server(client_public_key, s.client_proof) else {
+    let client_proof = s.client_proof;
+    // Fake accounts always fail here, just like a real account with the wrong password would
+    let Some((server, server_proof)) = crypto
+        .run(move || proof.into_server(client_public_key, client_proof))
+        .await
+        .ok()
+        .filter(|_| !fake_account)
+    else {
         CMD_AUTH_LOGON_PROOF_Server::FailIncorrectPassword
             .tokio_write_protocol(&mut stream, protocol_version)
             .await?;
@@ -137,12 +162,18 @@ pub(crate) async fn logon(
     };
 
     if let Some(game_files) = game_file_provider.get_game_files(&c).await {
-        if wow_srp::integrity::login_integrity_check_generic(
-            &game_files,
-            &crc_salt,
-            &s.client_public_key,
-        ) != s.crc_hash
-        {
+        let client_public_key = s.client_public_key;
+        let crc_hash = crypto
+            .run(move || {
+                wow_srp::integrity::login_integrity_check_generic(
+                    &game_files,
+                    &crc_salt,
+                    &client_public_key,
+                )
+            })
+            .await;
+
+        if crc_hash != s.crc_hash {
             CMD_AUTH_LOGON_PROOF_Server::FailVersionInvalid
                 .tokio_write_protocol(&mut stream, protocol_version)
                 .await?;
@@ -152,17 +183,25 @@ pub(crate) async fn logon(
         }
     }
 
-    if !check_2fa_login_details(
-        c.clone(),
-        credentials,
-        pin_grid_seed,
-        &pin_salt,
-        seed,
-        &s,
-        &server,
-    )
-    .await
-    {
+    let account_flag = credentials.account_flag;
+    let account_name = c.account_name.clone();
+    let (success, server) = crypto
+        .run(move || {
+            let success = check_2fa_login_details(
+                &account_name,
+                credentials,
+                pin_grid_seed,
+                &pin_salt,
+                seed,
+                &s,
+                &server,
+            );
+
+            (success, server)
+        })
+        .await;
+
+    if !success {
         CMD_AUTH_LOGON_PROOF_Server::FailIncorrectPassword
             .tokio_write_protocol(&mut stream, protocol_version)
             .await?;
@@ -170,11 +209,13 @@ pub(crate) async fn logon(
         return Ok(());
     }
 
-    storage.add_key(c.account_name.clone(), server).await;
+    storage
+        .add_key_with_address(c.account_name.clone(), server, address)
+        .await;
     trace!("authenticated user");
 
     CMD_AUTH_LOGON_PROOF_Server::Success {
-        account_flag: AccountFlag::empty(),
+        account_flag,
         hardware_survey_id: 0,
         server_proof,
         unknown: 0,
@@ -187,9 +228,46 @@ pub(crate) async fn logon(
     Ok(())
 }
 
+/// Deterministically derives a salt and verifier for an account that does not exist,
+/// so that repeated challenges for the same name look like those of a real account.
+///
+/// The PIN and matrix card are only sent to clients that support them, like
+/// [`MemoryCredentialProvider`](crate::memory::MemoryCredentialProvider) does for real accounts.
+fn fake_credentials(
+    secret: &UnknownAccountSecret,
+    c: &CMD_AUTH_LOGON_CHALLENGE_Client,
+) -> Credentials {
+    let derive = |label: &[u8]| -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(secret.as_bytes());
+        hasher.update(label);
+        hasher.update(c.account_name.to_uppercase().as_bytes());
+        hasher.finalize().into()
+    };
+
+    // The value does not matter since the password is never accepted
+    let pin = if secret.pin() && c.version.supports_pin() {
+        PinCode::from_u64(1234).ok()
+    } else {
+        None
+    };
+    let matrix_card = secret
+        .matrix_card()
+        .filter(|_| c.version.supports_matrix_card())
+        .cloned();
+
+    Credentials {
+        password_verifier: derive(b"verifier"),
+        salt: derive(b"salt"),
+        pin,
+        matrix_card,
+        account_flag: AccountFlag::empty(),
+    }
+}
+
 #[tracing::instrument]
-async fn check_2fa_login_details(
-    c: CMD_AUTH_LOGON_CHALLENGE_Client,
+fn check_2fa_login_details(
+    account_name: &str,
     credentials: Credentials,
     pin_grid_seed: u32,
     pin_salt: &[u8; 16],
@@ -231,7 +309,7 @@ async fn check_2fa_login_details(
                 error!("invalid matrix card");
                 return false;
             } else {
-                println!("{} passed matrix card.", c.account_name);
+                trace!(account_name, "passed matrix card");
             }
         } else {
             error!("matrix card not sent");
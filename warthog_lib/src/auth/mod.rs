@@ -1,7 +1,9 @@
+pub(crate) mod crypto;
 mod logon;
 mod reconnect;
-mod transfer;
+pub(crate) mod transfer;
 
+use crate::auth::crypto::CryptoPool;
 use crate::auth::logon::logon;
 use crate::{
     CredentialProvider, GameFileProvider, KeyStorage, Options, PatchProvider, RealmListProvider,
@@ -23,7 +25,8 @@ use wow_login_messages::CollectiveMessage;
     patch_provider,
     game_file_provider,
     realm_list_provider,
-    options
+    options,
+    crypto
 ))]
 pub(crate) async fn auth(
     mut stream: TcpStream,
@@ -33,6 +36,7 @@ pub(crate) async fn auth(
     game_file_provider: impl GameFileProvider,
     realm_list_provider: impl RealmListProvider,
     options: &Options,
+    crypto: CryptoPool,
 ) {
     trace!("connected");
     let c = match tokio_read_initial_message(&mut stream).await {
@@ -63,6 +67,7 @@ pub(crate) async fn auth(
                 stream,
                 c,
                 options,
+                crypto,
             )
             .await
             {
@@ -70,7 +75,9 @@ pub(crate) async fn auth(
             }
         }
         InitialMessage::Reconnect(c) => {
-            if let Err(e) = reconnect::reconnect(storage, realm_list_provider, stream, c).await {
+            if let Err(e) =
+                reconnect::reconnect(storage, realm_list_provider, stream, c, options).await
+            {
                 error!(?e, "io error during reconnect")
             }
         }
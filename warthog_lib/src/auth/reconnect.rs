@@ -1,8 +1,10 @@
 use crate::auth::send_realm_list;
-use crate::{KeyStorage, RealmListProvider};
+use crate::{KeyStorage, Options, RealmListProvider, UnknownAccountSecret};
+use sha2::{Digest, Sha256};
 use std::io;
+use std::time::SystemTime;
 use tokio::net::TcpStream;
-use tracing::{error, trace};
+use tracing::{error, info, trace};
 use wow_login_messages::all::{
     CMD_AUTH_LOGON_CHALLENGE_Client, CMD_AUTH_RECONNECT_CHALLENGE_Client,
 };
@@ -13,20 +15,26 @@ use wow_login_messages::version_8::{
 };
 use wow_login_messages::CollectiveMessage;
 
-#[tracing::instrument(skip(realm_list_provider, storage, stream))]
+#[tracing::instrument(skip(realm_list_provider, storage, stream, options))]
 pub(crate) async fn reconnect(
     mut storage: impl KeyStorage,
     realm_list_provider: impl RealmListProvider,
     mut stream: TcpStream,
     c: CMD_AUTH_RECONNECT_CHALLENGE_Client,
+    options: &Options,
 ) -> io::Result<()> {
     trace!("connected");
     let Some(mut server) = storage.get_key_for_user(&c.account_name).await else {
+        if let Some(secret) = &options.unknown_account_secret {
+            info!("user without session attempted reconnect, sending fake challenge");
+            return fake_reconnect(stream, c, secret).await;
+        }
+
         CMD_AUTH_RECONNECT_CHALLENGE_Server::FailUnknownAccount
             .tokio_write_protocol(&mut stream, c.protocol_version)
             .await?;
 
-        error!("invalid user attempted reconnect");
+        info!("user without session attempted reconnect");
         return Ok(());
     };
 
@@ -88,3 +96,55 @@ pub(crate) async fn reconnect(
 
     Ok(())
 }
+
+/// Goes through the same steps as a real reconnect with an incorrect proof,
+/// so that it is not possible to find out which accounts have a session.
+async fn fake_reconnect(
+    mut stream: TcpStream,
+    c: CMD_AUTH_RECONNECT_CHALLENGE_Client,
+    secret: &UnknownAccountSecret,
+) -> io::Result<()> {
+    // Real challenge data is random, so this only has to be unpredictable
+    let challenge_data: [u8; 32] = {
+        let mut hasher = Sha256::new();
+        hasher.update(secret.as_bytes());
+        hasher.update(b"reconnect");
+        hasher.update(c.account_name.to_uppercase().as_bytes());
+        if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.update(time.as_nanos().to_le_bytes());
+        }
+        hasher.finalize().into()
+    };
+
+    CMD_AUTH_RECONNECT_CHALLENGE_Server::Success {
+        challenge_data: challenge_data[..16].try_into().unwrap(),
+        // Unused on 1.12
+        checksum_salt: [0; 16],
+    }
+    .tokio_write_protocol(&mut stream, c.protocol_version)
+    .await?;
+
+    let s = match tokio_expect_client_message_protocol::<CMD_AUTH_RECONNECT_PROOF_Client, _>(
+        &mut stream,
+        c.protocol_version,
+    )
+    .await
+    {
+        Ok(s) => s,
+        Err(err) => {
+            error!(?err, "invalid opcode received during reconnect proof");
+            return Ok(());
+        }
+    };
+
+    let result =
+        if s.client_checksum != wow_srp::integrity::reconnect_integrity_check(&s.proof_data) {
+            LoginResult::FailVersionInvalid
+        } else {
+            LoginResult::FailIncorrectPassword
+        };
+
+    CMD_AUTH_RECONNECT_PROOF_Server { result }
+        .tokio_write_protocol(&mut stream, c.protocol_version)
+        .await
+}
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tracing::{error, info, trace, warn};
 use wow_login_messages::all::CMD_AUTH_LOGON_CHALLENGE_Client;
@@ -60,11 +61,14 @@ pub(crate) async fn transfer(
     const TRANSFER_CHUNK: usize = 64;
 
     for i in data[offset..].chunks(TRANSFER_CHUNK) {
-        let mut buf = [0_u8; 1];
-        let size = stream.peek(&mut buf).await?;
-        if size != 0 {
-            // Client doesn't send any messages other than CMD_XFER_CANCEL
-            info!("client sent CMD_XFER_CANCEL");
+        if let Some(size) = peek_arrived(&stream).await {
+            if size? != 0 {
+                // Client doesn't send any messages other than CMD_XFER_CANCEL
+                info!("client sent CMD_XFER_CANCEL");
+            } else {
+                info!("client closed connection during transfer");
+            }
+
             return Ok(());
         }
 
@@ -80,3 +84,16 @@ pub(crate) async fn transfer(
 
     Ok(())
 }
+
+/// Peeks at data that has already arrived from the client without waiting for more.
+///
+/// [`TcpStream::peek`] waits until there is data, which clients never send during a
+/// transfer unless they cancel it, so it is only polled once.
+/// Returns [`None`] if nothing has arrived, and `Some(Ok(0))` if the client closed the connection.
+pub(crate) async fn peek_arrived(stream: &TcpStream) -> Option<std::io::Result<usize>> {
+    let mut buf = [0_u8; 1];
+
+    tokio::time::timeout(Duration::ZERO, stream.peek(&mut buf))
+        .await
+        .ok()
+}
@@ -0,0 +1,313 @@
+use crate::{
+    CMD_AUTH_LOGON_CHALLENGE_Client, CredentialProvider, Credentials, Realm, RealmListProvider,
+    UserInfo, UserModification, PASSWORD_VERIFIER_LENGTH, SALT_LENGTH,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct CacheOptions {
+    /// How long an entry is valid after being fetched from the inner provider.
+    pub time_to_live: Duration,
+    /// Maximum amount of entries kept at the same time.
+    pub max_entries: usize,
+}
+
+impl CacheOptions {
+    pub const fn new(time_to_live: Duration, max_entries: usize) -> Self {
+        Self {
+            time_to_live,
+            max_entries,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    inserted: Instant,
+    value: T,
+}
+
+#[derive(Debug)]
+struct Cache<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    options: CacheOptions,
+    /// Increased on every invalidation, so that values fetched before it are not inserted afterwards.
+    generation: u64,
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Cache<K, T> {
+    fn new(options: CacheOptions) -> Self {
+        Self {
+            entries: HashMap::new(),
+            options,
+            generation: 0,
+        }
+    }
+
+    /// Returns the cached value, or the generation to pass to [`Self::insert`] after fetching it.
+    fn get_or_generation(&mut self, key: &K) -> Result<T, u64> {
+        self.get(key).ok_or(self.generation)
+    }
+
+    fn get(&mut self, key: &K) -> Option<T> {
+        let entry = self.entries.get(key)?;
+
+        if entry.inserted.elapsed() < self.options.time_to_live {
+            Some(entry.value.clone())
+        } else {
+            self.entries.remove(key);
+            None
+        }
+    }
+
+    /// Does nothing if anything has been invalidated since `generation`,
+    /// since `value` might have been fetched before the invalidation.
+    fn insert(&mut self, key: K, value: T, generation: u64) {
+        if self.options.max_entries == 0 || generation != self.generation {
+            return;
+        }
+
+        if self.entries.len() >= self.options.max_entries && !self.entries.contains_key(&key) {
+            let ttl = self.options.time_to_live;
+            self.entries.retain(|_, e| e.inserted.elapsed() < ttl);
+        }
+
+        while self.entries.len() >= self.options.max_entries && !self.entries.contains_key(&key) {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.inserted)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+
+            self.entries.remove(&oldest);
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                inserted: Instant::now(),
+                value,
+            },
+        );
+    }
+
+    fn invalidate(&mut self, f: impl Fn(&K) -> bool) {
+        self.generation += 1;
+        self.entries.retain(|key, _| !f(key));
+    }
+
+    fn invalidate_all(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+    }
+}
+
+/// Key used for everything that depends on the client. Providers are allowed to
+/// give different answers depending on the client version, so it is part of the key.
+type VersionKey = (u8, u8, u8, u16);
+
+fn version_key(message: &CMD_AUTH_LOGON_CHALLENGE_Client) -> VersionKey {
+    (
+        message.version.major,
+        message.version.minor,
+        message.version.patch,
+        message.version.build,
+    )
+}
+
+fn username_key(username: &str) -> String {
+    username.to_uppercase()
+}
+
+/// Caches the results of [`CredentialProvider::get_user`] from an inner provider.
+///
+/// Unknown users are cached as well, so repeated attempts for non-existent accounts
+/// also skip the inner provider.
+/// Adding, removing or modifying a user through the cache invalidates the entries for that user.
+#[derive(Debug, Clone)]
+pub struct CachedCredentialProvider<P: CredentialProvider> {
+    inner: P,
+    cache: Arc<Mutex<Cache<(String, VersionKey), Option<Credentials>>>>,
+}
+
+impl<P: CredentialProvider> CachedCredentialProvider<P> {
+    pub fn new(inner: P, options: CacheOptions) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(Cache::new(options))),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Removes all cached entries for `username`.
+    pub fn invalidate(&self, username: &str) {
+        let username = username_key(username);
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(|(name, _)| *name == username);
+    }
+
+    /// Removes all cached entries.
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().invalidate_all();
+    }
+}
+
+impl<P: CredentialProvider> CredentialProvider for CachedCredentialProvider<P> {
+    fn get_user(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Option<Credentials>> + Send {
+        let key = (username_key(&message.account_name), version_key(message));
+
+        async move {
+            let cached = self.cache.lock().unwrap().get_or_generation(&key);
+            let generation = match cached {
+                Ok(credentials) => return credentials,
+                Err(generation) => generation,
+            };
+
+            let credentials = self.inner.get_user(message).await;
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, credentials.clone(), generation);
+
+            credentials
+        }
+    }
+
+    fn add_user(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Option<()>> + Send {
+        async move {
+            let result = self.inner.add_user(username, password).await;
+            self.invalidate(username);
+
+            result
+        }
+    }
+
+    fn add_user_with_verifier(
+        &mut self,
+        username: &str,
+        salt: [u8; SALT_LENGTH as usize],
+        password_verifier: [u8; PASSWORD_VERIFIER_LENGTH as usize],
+    ) -> impl Future<Output = Option<()>> + Send {
+        async move {
+            let result = self
+                .inner
+                .add_user_with_verifier(username, salt, password_verifier)
+                .await;
+            self.invalidate(username);
+
+            result
+        }
+    }
+
+    fn remove_user(&mut self, username: &str) -> impl Future<Output = bool> + Send {
+        async move {
+            let result = self.inner.remove_user(username).await;
+            self.invalidate(username);
+
+            result
+        }
+    }
+
+    fn modify_user(
+        &mut self,
+        username: &str,
+        modification: UserModification,
+    ) -> impl Future<Output = bool> + Send {
+        async move {
+            let result = self.inner.modify_user(username, modification).await;
+            self.invalidate(username);
+
+            result
+        }
+    }
+
+    fn list_users(
+        &mut self,
+        offset: u32,
+        limit: u32,
+    ) -> impl Future<Output = Vec<UserInfo>> + Send {
+        self.inner.list_users(offset, limit)
+    }
+}
+
+/// Caches the results of [`RealmListProvider::get_realm_list`] from an inner provider.
+///
+/// Clients poll the realm list every few seconds, so even a short time to live
+/// removes most calls to the inner provider.
+/// Lists are cached per account, since they contain the amount of characters the account has on each realm.
+#[derive(Debug, Clone)]
+pub struct CachedRealmListProvider<P: RealmListProvider> {
+    inner: P,
+    cache: Arc<Mutex<Cache<(String, VersionKey), Vec<Realm>>>>,
+}
+
+impl<P: RealmListProvider> CachedRealmListProvider<P> {
+    pub fn new(inner: P, options: CacheOptions) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(Cache::new(options))),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Removes all cached realm lists, for example after a realm has been added or changed.
+    pub fn invalidate(&self) {
+        self.cache.lock().unwrap().invalidate_all();
+    }
+
+    /// Removes the cached realm lists of `username`, for example after its character amounts have changed.
+    pub fn invalidate_account(&self, username: &str) {
+        let username = username_key(username);
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(|(name, _)| *name == username);
+    }
+}
+
+impl<P: RealmListProvider> RealmListProvider for CachedRealmListProvider<P> {
+    fn get_realm_list(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Vec<Realm>> + Send {
+        let key = (username_key(&message.account_name), version_key(message));
+
+        async move {
+            let cached = self.cache.lock().unwrap().get_or_generation(&key);
+            let generation = match cached {
+                Ok(realms) => return realms,
+                Err(generation) => generation,
+            };
+
+            let realms = self.inner.get_realm_list(message).await;
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, realms.clone(), generation);
+
+            realms
+        }
+    }
+}
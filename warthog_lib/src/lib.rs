@@ -1,22 +1,32 @@
 mod auth;
+mod cache;
+pub mod memory;
+#[cfg(test)]
+mod test;
 
 use std::fmt::Debug;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::net::TcpListener;
 use tracing::info;
 
 use crate::auth::auth;
+use crate::auth::crypto::CryptoPool;
+
+pub use crate::cache::{CacheOptions, CachedCredentialProvider, CachedRealmListProvider};
 
 pub use wow_login_messages::all::CMD_AUTH_LOGON_CHALLENGE_Client;
 pub use wow_login_messages::all::CMD_AUTH_RECONNECT_CHALLENGE_Client;
+pub use wow_login_messages::all::Os;
+pub use wow_login_messages::all::Platform;
 pub use wow_login_messages::all::Population;
 pub use wow_login_messages::all::Version;
 pub use wow_login_messages::errors::ExpectedOpcodeError;
 pub use wow_login_messages::version_8::opcodes::ClientOpcodeMessage;
+pub use wow_login_messages::version_8::AccountFlag;
 pub use wow_login_messages::version_8::Realm;
 
 pub use wow_login_messages::version_8::RealmCategory;
@@ -31,6 +41,7 @@ pub use wow_srp::normalized_string::NormalizedString;
 pub use wow_srp::pin::PinCode;
 pub use wow_srp::server::SrpServer;
 pub use wow_srp::server::SrpVerifier;
+pub use wow_srp::LARGE_SAFE_PRIME_LITTLE_ENDIAN;
 pub use wow_srp::PASSWORD_VERIFIER_LENGTH;
 pub use wow_srp::SALT_LENGTH;
 
@@ -42,6 +53,70 @@ pub struct Options {
     pub randomize_pin_grid: bool,
     /// Maximum amount of concurrent users.
     pub max_concurrent_users: u32,
+    /// Maximum amount of SRP and integrity calculations running at the same time on the blocking pool.
+    ///
+    /// 0 runs the calculations directly on the async worker threads.
+    pub max_concurrent_crypto_operations: u32,
+    /// Send fake challenges for unknown accounts instead of failing immediately.
+    ///
+    /// The login will instead fail with an incorrect password, so that it is not possible
+    /// to find out which account names exist.
+    pub unknown_account_secret: Option<UnknownAccountSecret>,
+}
+
+/// Secret used for deriving the salt of unknown accounts.
+///
+/// Must stay the same across restarts, otherwise the salts of unknown accounts will change
+/// while those of real accounts do not.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct UnknownAccountSecret {
+    secret: [u8; 32],
+    pin: bool,
+    matrix_card: Option<MatrixCardOptions>,
+}
+
+impl UnknownAccountSecret {
+    pub const fn new(secret: [u8; 32]) -> Self {
+        Self {
+            secret,
+            pin: false,
+            matrix_card: None,
+        }
+    }
+
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        use sha2::Digest;
+
+        Self::new(sha2::Sha256::digest(passphrase.as_bytes()).into())
+    }
+
+    /// Makes fake challenges ask for a PIN and matrix card.
+    ///
+    /// Should match what real accounts from the [`CredentialProvider`] use,
+    /// otherwise unknown accounts can be told apart by their security flags.
+    pub fn with_security(mut self, pin: bool, matrix_card: Option<MatrixCardOptions>) -> Self {
+        self.pin = pin;
+        self.matrix_card = matrix_card;
+        self
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.secret
+    }
+
+    pub(crate) fn pin(&self) -> bool {
+        self.pin
+    }
+
+    pub(crate) fn matrix_card(&self) -> Option<&MatrixCardOptions> {
+        self.matrix_card.as_ref()
+    }
+}
+
+impl Debug for UnknownAccountSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("UnknownAccountSecret(..)")
+    }
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -50,6 +125,36 @@ pub struct Credentials {
     pub salt: [u8; SALT_LENGTH as usize],
     pub pin: Option<PinCode>,
     pub matrix_card: Option<MatrixCardOptions>,
+    pub account_flag: AccountFlag,
+}
+
+/// Changes to an existing user. Fields that are [`None`] are left unchanged.
+#[derive(Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct UserModification {
+    pub password: Option<String>,
+    /// `Some(None)` removes the PIN.
+    pub pin: Option<Option<PinCode>>,
+    /// `Some(None)` removes the matrix card.
+    pub matrix_card: Option<Option<MatrixCardOptions>>,
+    pub account_flag: Option<AccountFlag>,
+}
+
+/// User returned by [`CredentialProvider::list_users`].
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct UserInfo {
+    pub username: String,
+    pub account_flag: AccountFlag,
+    pub has_pin: bool,
+    pub has_matrix_card: bool,
+}
+
+/// Session returned by [`KeyStorage::list_sessions`].
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SessionInfo {
+    pub username: String,
+    /// Address the user logged in from.
+    pub address: IpAddr,
+    pub login_time: SystemTime,
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -78,18 +183,80 @@ pub trait CredentialProvider: std::fmt::Debug + Clone + Send + Sync + 'static {
         password: &str,
     ) -> impl Future<Output = Option<()>> + Send;
 
+    /// Same as [`Self::add_user`], but with the salt and verifier already computed from the password,
+    /// for example with [`SrpVerifier::from_username_and_password`].
+    ///
+    /// The default adds nothing, for providers that can only add users from passwords.
+    fn add_user_with_verifier(
+        &mut self,
+        _username: &str,
+        _salt: [u8; SALT_LENGTH as usize],
+        _password_verifier: [u8; PASSWORD_VERIFIER_LENGTH as usize],
+    ) -> impl Future<Output = Option<()>> + Send {
+        std::future::ready(None)
+    }
+
     fn remove_user(&mut self, username: &str) -> impl Future<Output = bool> + Send;
 
-    fn modify_user(&mut self, username: &str) -> impl Future<Output = bool> + Send;
+    fn modify_user(
+        &mut self,
+        username: &str,
+        modification: UserModification,
+    ) -> impl Future<Output = bool> + Send;
+
+    /// Returns at most `limit` users, skipping the first `offset`.
+    ///
+    /// Users must be returned in the same order every time so that pages do not overlap.
+    ///
+    /// The default lists nothing, for providers that can not enumerate their users.
+    fn list_users(
+        &mut self,
+        _offset: u32,
+        _limit: u32,
+    ) -> impl Future<Output = Vec<UserInfo>> + Send {
+        std::future::ready(Vec::new())
+    }
 }
 
 pub trait KeyStorage: Debug + Clone + Send + Sync + 'static {
     fn add_key(&mut self, username: String, server: SrpServer) -> impl Future<Output = ()> + Send;
 
+    /// Same as [`Self::add_key`], but with `address` being the address the user logged in from.
+    ///
+    /// The default ignores the address.
+    fn add_key_with_address(
+        &mut self,
+        username: String,
+        server: SrpServer,
+        _address: IpAddr,
+    ) -> impl Future<Output = ()> + Send {
+        self.add_key(username, server)
+    }
+
     fn get_key_for_user(
         &mut self,
         username: &str,
     ) -> impl Future<Output = Option<SrpServer>> + Send;
+
+    /// Returns `false` if the user did not have a key.
+    ///
+    /// The default removes nothing, for storages where keys can only expire.
+    fn remove_key(&mut self, _username: &str) -> impl Future<Output = bool> + Send {
+        std::future::ready(false)
+    }
+
+    /// Returns at most `limit` sessions, skipping the first `offset`.
+    ///
+    /// Sessions must be returned in the same order every time so that pages do not overlap.
+    ///
+    /// The default lists nothing, for storages that can not enumerate their sessions.
+    fn list_sessions(
+        &mut self,
+        _offset: u32,
+        _limit: u32,
+    ) -> impl Future<Output = Vec<SessionInfo>> + Send {
+        std::future::ready(Vec::new())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -165,11 +332,49 @@ pub async fn start_auth_server(
     should_run: Arc<AtomicBool>,
     options: Options,
 ) -> std::io::Result<()> {
-    let options: &'static mut _ = Box::leak(Box::new(options));
     let listener = TcpListener::bind(options.address).await?;
+
+    start_auth_server_with_listener(
+        listener,
+        provider,
+        storage,
+        patch_provider,
+        game_file_provider,
+        realm_list_provider,
+        should_run,
+        options,
+    )
+    .await
+}
+
+/// Same as [`start_auth_server`], but uses an already bound `listener` instead of [`Options::address`].
+///
+/// Useful for binding to port 0 and finding the actual port through [`TcpListener::local_addr`].
+#[tracing::instrument(skip(
+    listener,
+    provider,
+    storage,
+    patch_provider,
+    game_file_provider,
+    realm_list_provider,
+    should_run
+))]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_auth_server_with_listener(
+    listener: TcpListener,
+    provider: impl CredentialProvider,
+    storage: impl KeyStorage,
+    patch_provider: impl PatchProvider,
+    game_file_provider: impl GameFileProvider,
+    realm_list_provider: impl RealmListProvider,
+    should_run: Arc<AtomicBool>,
+    options: Options,
+) -> std::io::Result<()> {
+    let options: &'static mut _ = Box::leak(Box::new(options));
     info!("auth server started");
 
     let concurrent_connections = Arc::new(AtomicU32::new(0));
+    let crypto = CryptoPool::new(options.max_concurrent_crypto_operations);
 
     let should_run = tokio::spawn(async move {
         while should_run.load(Ordering::SeqCst) {
@@ -192,6 +397,7 @@ pub async fn start_auth_server(
                 let game_file_provider = game_file_provider.clone();
                 let realm_list_provider = realm_list_provider.clone();
                 let options: &'static _ = &*options;
+                let crypto = crypto.clone();
 
                 tokio::spawn(async move {
                     auth(
@@ -202,6 +408,7 @@ pub async fn start_auth_server(
                         game_file_provider,
                         realm_list_provider,
                         options,
+                        crypto,
                     )
                     .await;
 
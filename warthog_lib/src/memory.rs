@@ -0,0 +1,494 @@
+//! Thread-safe in-memory implementations of the provider traits.
+//!
+//! Nothing is persisted, so these are mostly useful for testing and small servers.
+//! All types are cheap to clone and clones share the same underlying storage.
+
+use crate::{
+    AccountFlag, CMD_AUTH_LOGON_CHALLENGE_Client, CredentialProvider, Credentials,
+    GameFileProvider, KeyStorage, MatrixCardOptions, NormalizedString, Os, PatchFile,
+    PatchProvider, PinCode, Platform, Realm, RealmListProvider, SessionInfo, SrpServer,
+    SrpVerifier, UserInfo, UserModification, PASSWORD_VERIFIER_LENGTH, SALT_LENGTH,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::{self, Future};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Stores password verifiers, salts, PINs and matrix cards for users.
+///
+/// PINs and matrix cards are only sent to clients that support them.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCredentialProvider {
+    /// Ordered so that [`CredentialProvider::list_users`] pages are stable.
+    users: Arc<Mutex<BTreeMap<String, Credentials>>>,
+    default_pin: Option<PinCode>,
+    default_matrix_card: Option<MatrixCardOptions>,
+}
+
+impl MemoryCredentialProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Users added through [`CredentialProvider::add_user`] will get `pin` and `matrix_card`.
+    pub fn with_defaults(pin: Option<PinCode>, matrix_card: Option<MatrixCardOptions>) -> Self {
+        Self {
+            users: Default::default(),
+            default_pin: pin,
+            default_matrix_card: matrix_card,
+        }
+    }
+
+    fn key(username: &str) -> String {
+        username.to_uppercase()
+    }
+
+    /// Computes a new verifier and salt from `password`.
+    ///
+    /// Returns [`None`] if either the username or password can not be normalized.
+    pub fn credentials_from_password(
+        username: &str,
+        password: &str,
+        pin: Option<PinCode>,
+        matrix_card: Option<MatrixCardOptions>,
+    ) -> Option<Credentials> {
+        let username = NormalizedString::new(username).ok()?;
+        let password = NormalizedString::new(password).ok()?;
+        let v = SrpVerifier::from_username_and_password(username, password);
+
+        Some(Credentials {
+            password_verifier: *v.password_verifier(),
+            salt: *v.salt(),
+            pin,
+            matrix_card,
+            account_flag: AccountFlag::empty(),
+        })
+    }
+
+    /// Inserts the credentials for `username`, unless the user already exists.
+    fn insert_new_user(&self, username: &str, credentials: Credentials) -> Option<()> {
+        let mut users = self.users.lock().unwrap();
+        let key = Self::key(username);
+        if users.contains_key(&key) {
+            None
+        } else {
+            users.insert(key, credentials);
+            Some(())
+        }
+    }
+
+    /// Inserts or replaces the credentials for `username`.
+    pub fn insert_user(&self, username: &str, credentials: Credentials) {
+        self.users
+            .lock()
+            .unwrap()
+            .insert(Self::key(username), credentials);
+    }
+
+    pub fn get_credentials(&self, username: &str) -> Option<Credentials> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&Self::key(username))
+            .cloned()
+    }
+
+    pub fn set_pin(&self, username: &str, pin: Option<PinCode>) -> bool {
+        if let Some(c) = self.users.lock().unwrap().get_mut(&Self::key(username)) {
+            c.pin = pin;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_matrix_card(&self, username: &str, matrix_card: Option<MatrixCardOptions>) -> bool {
+        if let Some(c) = self.users.lock().unwrap().get_mut(&Self::key(username)) {
+            c.matrix_card = matrix_card;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CredentialProvider for MemoryCredentialProvider {
+    fn get_user(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Option<Credentials>> + Send {
+        let credentials = self.get_credentials(&message.account_name).map(|mut c| {
+            if !message.version.supports_pin() {
+                c.pin = None;
+            }
+            if !message.version.supports_matrix_card() {
+                c.matrix_card = None;
+            }
+
+            c
+        });
+
+        future::ready(credentials)
+    }
+
+    fn add_user(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Option<()>> + Send {
+        let credentials = Self::credentials_from_password(
+            username,
+            password,
+            self.default_pin.clone(),
+            self.default_matrix_card.clone(),
+        );
+
+        let result =
+            credentials.and_then(|credentials| self.insert_new_user(username, credentials));
+
+        future::ready(result)
+    }
+
+    fn add_user_with_verifier(
+        &mut self,
+        username: &str,
+        salt: [u8; SALT_LENGTH as usize],
+        password_verifier: [u8; PASSWORD_VERIFIER_LENGTH as usize],
+    ) -> impl Future<Output = Option<()>> + Send {
+        // Logins use the normalized name, so names that can not be normalized could never log in
+        let result = NormalizedString::new(username).ok().and_then(|_| {
+            self.insert_new_user(
+                username,
+                Credentials {
+                    password_verifier,
+                    salt,
+                    pin: self.default_pin.clone(),
+                    matrix_card: self.default_matrix_card.clone(),
+                    account_flag: AccountFlag::empty(),
+                },
+            )
+        });
+
+        future::ready(result)
+    }
+
+    fn remove_user(&mut self, username: &str) -> impl Future<Output = bool> + Send {
+        let removed = self
+            .users
+            .lock()
+            .unwrap()
+            .remove(&Self::key(username))
+            .is_some();
+
+        future::ready(removed)
+    }
+
+    fn modify_user(
+        &mut self,
+        username: &str,
+        modification: UserModification,
+    ) -> impl Future<Output = bool> + Send {
+        let new_password = match &modification.password {
+            Some(password) => match Self::credentials_from_password(username, password, None, None)
+            {
+                Some(c) => Some(c),
+                None => return future::ready(false),
+            },
+            None => None,
+        };
+
+        let mut users = self.users.lock().unwrap();
+        let success = if let Some(c) = users.get_mut(&Self::key(username)) {
+            if let Some(new_password) = new_password {
+                c.password_verifier = new_password.password_verifier;
+                c.salt = new_password.salt;
+            }
+            if let Some(pin) = modification.pin {
+                c.pin = pin;
+            }
+            if let Some(matrix_card) = modification.matrix_card {
+                c.matrix_card = matrix_card;
+            }
+            if let Some(account_flag) = modification.account_flag {
+                c.account_flag = account_flag;
+            }
+
+            true
+        } else {
+            false
+        };
+
+        future::ready(success)
+    }
+
+    fn list_users(
+        &mut self,
+        offset: u32,
+        limit: u32,
+    ) -> impl Future<Output = Vec<UserInfo>> + Send {
+        let users = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(username, c)| UserInfo {
+                username: username.clone(),
+                account_flag: c.account_flag,
+                has_pin: c.pin.is_some(),
+                has_matrix_card: c.matrix_card.is_some(),
+            })
+            .collect();
+
+        future::ready(users)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    server: SrpServer,
+    address: IpAddr,
+    login_time: SystemTime,
+}
+
+/// Stores session keys for logged in users.
+///
+/// Usernames are case insensitive.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryKeyStorage {
+    /// Ordered so that [`KeyStorage::list_sessions`] pages are stable.
+    keys: Arc<Mutex<BTreeMap<String, Session>>>,
+}
+
+impl MemoryKeyStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(username: &str) -> String {
+        username.to_uppercase()
+    }
+}
+
+impl KeyStorage for MemoryKeyStorage {
+    fn add_key(&mut self, username: String, server: SrpServer) -> impl Future<Output = ()> + Send {
+        self.add_key_with_address(username, server, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+
+    fn add_key_with_address(
+        &mut self,
+        username: String,
+        server: SrpServer,
+        address: IpAddr,
+    ) -> impl Future<Output = ()> + Send {
+        self.keys.lock().unwrap().insert(
+            Self::key(&username),
+            Session {
+                server,
+                address,
+                login_time: SystemTime::now(),
+            },
+        );
+
+        future::ready(())
+    }
+
+    fn get_key_for_user(
+        &mut self,
+        username: &str,
+    ) -> impl Future<Output = Option<SrpServer>> + Send {
+        let server = self
+            .keys
+            .lock()
+            .unwrap()
+            .get(&Self::key(username))
+            .map(|s| s.server.clone());
+
+        future::ready(server)
+    }
+
+    fn remove_key(&mut self, username: &str) -> impl Future<Output = bool> + Send {
+        let removed = self
+            .keys
+            .lock()
+            .unwrap()
+            .remove(&Self::key(username))
+            .is_some();
+
+        future::ready(removed)
+    }
+
+    fn list_sessions(
+        &mut self,
+        offset: u32,
+        limit: u32,
+    ) -> impl Future<Output = Vec<SessionInfo>> + Send {
+        let sessions = self
+            .keys
+            .lock()
+            .unwrap()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(username, s)| SessionInfo {
+                username: username.clone(),
+                address: s.address,
+                login_time: s.login_time,
+            })
+            .collect();
+
+        future::ready(sessions)
+    }
+}
+
+/// Static list of realms that can be changed at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRealmListProvider {
+    realms: Arc<Mutex<Vec<Realm>>>,
+}
+
+impl MemoryRealmListProvider {
+    pub fn new(realms: Vec<Realm>) -> Self {
+        Self {
+            realms: Arc::new(Mutex::new(realms)),
+        }
+    }
+
+    pub fn realms(&self) -> Vec<Realm> {
+        self.realms.lock().unwrap().clone()
+    }
+
+    pub fn set_realms(&self, realms: Vec<Realm>) {
+        *self.realms.lock().unwrap() = realms;
+    }
+
+    /// Inserts `realm`, replacing any realm with the same `realm_id`.
+    pub fn insert_realm(&self, realm: Realm) {
+        let mut realms = self.realms.lock().unwrap();
+
+        if let Some(r) = realms.iter_mut().find(|a| a.realm_id == realm.realm_id) {
+            *r = realm;
+        } else {
+            realms.push(realm);
+        }
+    }
+
+    pub fn remove_realm(&self, realm_id: u8) -> Option<Realm> {
+        let mut realms = self.realms.lock().unwrap();
+        let i = realms.iter().position(|a| a.realm_id == realm_id)?;
+
+        Some(realms.remove(i))
+    }
+}
+
+impl RealmListProvider for MemoryRealmListProvider {
+    fn get_realm_list(
+        &mut self,
+        _message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Vec<Realm>> + Send {
+        let realms = self.realms();
+
+        future::ready(realms)
+    }
+}
+
+/// Patches sent to clients of a specific build.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPatchProvider {
+    patches: Arc<Mutex<HashMap<u16, PatchFile>>>,
+}
+
+impl MemoryPatchProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clients with build `from_build` will be sent `patch` instead of being allowed to log in.
+    pub fn insert_patch(&self, from_build: u16, patch: PatchFile) {
+        self.patches.lock().unwrap().insert(from_build, patch);
+    }
+
+    pub fn remove_patch(&self, from_build: u16) -> Option<PatchFile> {
+        self.patches.lock().unwrap().remove(&from_build)
+    }
+}
+
+impl PatchProvider for MemoryPatchProvider {
+    fn get_patch(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Option<PatchFile>> + Send {
+        let patch = self
+            .patches
+            .lock()
+            .unwrap()
+            .get(&message.version.build)
+            .cloned();
+
+        future::ready(patch)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GameFiles {
+    build: u16,
+    os: Os,
+    platform: Platform,
+    data: Arc<[u8]>,
+}
+
+/// Game files used for the integrity check, separated by build, operating system and platform.
+///
+/// Clients without matching game files skip the integrity check.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryGameFileProvider {
+    files: Arc<Mutex<Vec<GameFiles>>>,
+}
+
+impl MemoryGameFileProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts or replaces the game files for the combination of `build`, `os` and `platform`.
+    pub fn insert_game_files(&self, build: u16, os: Os, platform: Platform, data: Arc<[u8]>) {
+        let mut files = self.files.lock().unwrap();
+
+        if let Some(f) = files
+            .iter_mut()
+            .find(|a| a.build == build && a.os == os && a.platform == platform)
+        {
+            f.data = data;
+        } else {
+            files.push(GameFiles {
+                build,
+                os,
+                platform,
+                data,
+            });
+        }
+    }
+}
+
+impl GameFileProvider for MemoryGameFileProvider {
+    fn get_game_files(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Option<Arc<[u8]>>> + Send {
+        let data = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|a| {
+                a.build == message.version.build
+                    && a.os == message.os
+                    && a.platform == message.platform
+            })
+            .map(|a| a.data.clone());
+
+        future::ready(data)
+    }
+}
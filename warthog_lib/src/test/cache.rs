@@ -0,0 +1,247 @@
+use crate::memory::{MemoryCredentialProvider, MemoryRealmListProvider};
+use crate::test::{realm, vanilla_1_12};
+use crate::{
+    CMD_AUTH_LOGON_CHALLENGE_Client, CacheOptions, CachedCredentialProvider,
+    CachedRealmListProvider, CredentialProvider, Credentials, Realm, RealmListProvider, UserInfo,
+    UserModification, PASSWORD_VERIFIER_LENGTH, SALT_LENGTH,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+const OPTIONS: CacheOptions = CacheOptions::new(Duration::from_secs(60), 16);
+
+/// Counts calls to [`CredentialProvider::get_user`], which can be held up to test races.
+#[derive(Debug, Clone, Default)]
+struct CountingProvider {
+    inner: MemoryCredentialProvider,
+    calls: Arc<AtomicU32>,
+    /// Notified when `get_user` has looked up the credentials.
+    looked_up: Arc<Notify>,
+    /// Held by the test to stop `get_user` from returning.
+    release: Arc<Mutex<()>>,
+}
+
+impl CountingProvider {
+    fn calls(&self) -> u32 {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl CredentialProvider for CountingProvider {
+    fn get_user(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Option<Credentials>> + Send {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let credentials = self.inner.get_credentials(&message.account_name);
+        let looked_up = self.looked_up.clone();
+        let release = self.release.clone();
+
+        async move {
+            looked_up.notify_one();
+            drop(release.lock().await);
+
+            credentials
+        }
+    }
+
+    fn add_user(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Option<()>> + Send {
+        self.inner.add_user(username, password)
+    }
+
+    fn add_user_with_verifier(
+        &mut self,
+        username: &str,
+        salt: [u8; SALT_LENGTH as usize],
+        password_verifier: [u8; PASSWORD_VERIFIER_LENGTH as usize],
+    ) -> impl Future<Output = Option<()>> + Send {
+        self.inner
+            .add_user_with_verifier(username, salt, password_verifier)
+    }
+
+    fn remove_user(&mut self, username: &str) -> impl Future<Output = bool> + Send {
+        self.inner.remove_user(username)
+    }
+
+    fn modify_user(
+        &mut self,
+        username: &str,
+        modification: UserModification,
+    ) -> impl Future<Output = bool> + Send {
+        self.inner.modify_user(username, modification)
+    }
+
+    fn list_users(
+        &mut self,
+        offset: u32,
+        limit: u32,
+    ) -> impl Future<Output = Vec<UserInfo>> + Send {
+        self.inner.list_users(offset, limit)
+    }
+}
+
+async fn provider_with_user() -> (CountingProvider, CachedCredentialProvider<CountingProvider>) {
+    let inner = CountingProvider::default();
+    let mut cached = CachedCredentialProvider::new(inner.clone(), OPTIONS);
+    cached.add_user("A", "PASSWORD").await.unwrap();
+
+    (inner, cached)
+}
+
+#[tokio::test]
+async fn credentials_hit() {
+    let (inner, mut cached) = provider_with_user().await;
+
+    let first = cached.get_user(&vanilla_1_12("A")).await;
+    let second = cached.get_user(&vanilla_1_12("a")).await;
+
+    assert!(first.is_some());
+    assert_eq!(first, second);
+    assert_eq!(inner.calls(), 1);
+}
+
+#[tokio::test]
+async fn credentials_miss_is_cached() {
+    let (inner, mut cached) = provider_with_user().await;
+
+    assert_eq!(cached.get_user(&vanilla_1_12("UNKNOWN")).await, None);
+    assert_eq!(cached.get_user(&vanilla_1_12("UNKNOWN")).await, None);
+    assert_eq!(inner.calls(), 1);
+}
+
+#[tokio::test]
+async fn credentials_expire() {
+    let inner = CountingProvider::default();
+    let mut cached =
+        CachedCredentialProvider::new(inner.clone(), CacheOptions::new(Duration::ZERO, 16));
+
+    cached.get_user(&vanilla_1_12("A")).await;
+    cached.get_user(&vanilla_1_12("A")).await;
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test]
+async fn credentials_invalidated() {
+    let (inner, mut cached) = provider_with_user().await;
+
+    let before = cached.get_user(&vanilla_1_12("A")).await.unwrap();
+    cached.invalidate("a");
+    cached.get_user(&vanilla_1_12("A")).await;
+    assert_eq!(inner.calls(), 2);
+
+    let modification = UserModification {
+        password: Some("OTHER".to_string()),
+        ..Default::default()
+    };
+    assert!(cached.modify_user("A", modification).await);
+
+    let after = cached.get_user(&vanilla_1_12("A")).await.unwrap();
+    assert_ne!(before.password_verifier, after.password_verifier);
+    assert_eq!(inner.calls(), 3);
+}
+
+#[tokio::test]
+async fn credentials_invalidated_during_fetch_are_not_cached() {
+    let (inner, cached) = provider_with_user().await;
+
+    let release = inner.release.lock().await;
+    let mut fetching = cached.clone();
+    let fetch = tokio::spawn(async move { fetching.get_user(&vanilla_1_12("A")).await });
+
+    // Changed after the old credentials have been looked up, but before they are inserted
+    inner.looked_up.notified().await;
+    let before = inner.inner.get_credentials("A").unwrap();
+    let mut modifying = cached.clone();
+    let modification = UserModification {
+        password: Some("OTHER".to_string()),
+        ..Default::default()
+    };
+    assert!(modifying.modify_user("A", modification).await);
+    drop(release);
+
+    assert_eq!(fetch.await.unwrap(), Some(before.clone()));
+
+    let mut cached = cached;
+    let after = cached.get_user(&vanilla_1_12("A")).await.unwrap();
+    assert_ne!(after, before);
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test]
+async fn realm_list_invalidated() {
+    let inner = MemoryRealmListProvider::new(vec![realm(0)]);
+    let mut cached = CachedRealmListProvider::new(inner.clone(), OPTIONS);
+
+    assert_eq!(cached.get_realm_list(&vanilla_1_12("A")).await.len(), 1);
+
+    inner.insert_realm(realm(1));
+    assert_eq!(cached.get_realm_list(&vanilla_1_12("A")).await.len(), 1);
+
+    cached.invalidate();
+    assert_eq!(cached.get_realm_list(&vanilla_1_12("A")).await.len(), 2);
+}
+
+/// Realm list with a different amount of characters for every account, like the one in `warthog_wow`.
+#[derive(Debug, Clone, Default)]
+struct CharacterAmounts {
+    amounts: Arc<std::sync::Mutex<HashMap<String, u8>>>,
+}
+
+impl RealmListProvider for CharacterAmounts {
+    fn get_realm_list(
+        &mut self,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+    ) -> impl Future<Output = Vec<Realm>> + Send {
+        let mut realm = realm(0);
+        realm.number_of_characters_on_realm = self
+            .amounts
+            .lock()
+            .unwrap()
+            .get(&message.account_name)
+            .copied()
+            .unwrap_or(0);
+
+        std::future::ready(vec![realm])
+    }
+}
+
+#[tokio::test]
+async fn realm_list_cached_per_account() {
+    let inner = CharacterAmounts::default();
+    inner.amounts.lock().unwrap().insert("A".to_string(), 1);
+    let mut cached = CachedRealmListProvider::new(inner.clone(), OPTIONS);
+
+    let characters = |realms: Vec<Realm>| realms[0].number_of_characters_on_realm;
+    assert_eq!(
+        characters(cached.get_realm_list(&vanilla_1_12("A")).await),
+        1
+    );
+    assert_eq!(
+        characters(cached.get_realm_list(&vanilla_1_12("B")).await),
+        0
+    );
+
+    inner.amounts.lock().unwrap().insert("B".to_string(), 2);
+    assert_eq!(
+        characters(cached.get_realm_list(&vanilla_1_12("B")).await),
+        0
+    );
+
+    cached.invalidate_account("b");
+    assert_eq!(
+        characters(cached.get_realm_list(&vanilla_1_12("B")).await),
+        2
+    );
+    assert_eq!(
+        characters(cached.get_realm_list(&vanilla_1_12("A")).await),
+        1
+    );
+}
@@ -0,0 +1,130 @@
+use crate::memory::{MemoryCredentialProvider, MemoryKeyStorage, MemoryRealmListProvider};
+use crate::test::{realm, srp_server, vanilla_1_12};
+use crate::{
+    AccountFlag, CredentialProvider, KeyStorage, RealmListProvider, UserInfo, UserModification,
+};
+use std::net::{IpAddr, Ipv4Addr};
+
+const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+#[tokio::test]
+async fn credentials_case_insensitive() {
+    let mut provider = MemoryCredentialProvider::new();
+
+    assert_eq!(provider.add_user("Alice", "PASSWORD").await, Some(()));
+    assert_eq!(provider.add_user("ALICE", "PASSWORD").await, None);
+
+    let credentials = provider.get_user(&vanilla_1_12("aLiCe")).await.unwrap();
+    assert_eq!(provider.get_credentials("alice"), Some(credentials));
+
+    assert!(provider.remove_user("alice").await);
+    assert!(!provider.remove_user("ALICE").await);
+    assert_eq!(provider.get_user(&vanilla_1_12("ALICE")).await, None);
+}
+
+#[tokio::test]
+async fn credentials_modify() {
+    let mut provider = MemoryCredentialProvider::new();
+    provider.add_user("A", "PASSWORD").await.unwrap();
+    let before = provider.get_credentials("A").unwrap();
+
+    let modification = UserModification {
+        account_flag: Some(AccountFlag::new(0x08)),
+        ..Default::default()
+    };
+    assert!(provider.modify_user("a", modification).await);
+    let after = provider.get_credentials("A").unwrap();
+    assert_eq!(after.account_flag, AccountFlag::new(0x08));
+    assert_eq!(after.password_verifier, before.password_verifier);
+
+    let modification = UserModification {
+        password: Some("OTHER".to_string()),
+        ..Default::default()
+    };
+    assert!(provider.modify_user("A", modification).await);
+    let after = provider.get_credentials("A").unwrap();
+    assert_ne!(after.password_verifier, before.password_verifier);
+    assert_eq!(after.account_flag, AccountFlag::new(0x08));
+
+    assert!(
+        !provider
+            .modify_user("UNKNOWN", UserModification::default())
+            .await
+    );
+}
+
+#[tokio::test]
+async fn credentials_list_pages() {
+    let mut provider = MemoryCredentialProvider::new();
+    for name in ["C", "a", "B"] {
+        provider.add_user(name, "PASSWORD").await.unwrap();
+    }
+
+    let names =
+        |users: Vec<UserInfo>| -> Vec<String> { users.into_iter().map(|u| u.username).collect() };
+
+    assert_eq!(names(provider.list_users(0, 2).await), ["A", "B"]);
+    assert_eq!(names(provider.list_users(2, 2).await), ["C"]);
+    assert!(provider.list_users(3, 2).await.is_empty());
+}
+
+#[tokio::test]
+async fn keys_case_insensitive() {
+    let mut storage = MemoryKeyStorage::new();
+    let server = srp_server("Alice", "PASSWORD");
+
+    storage
+        .add_key_with_address("Alice".to_string(), server, ADDRESS)
+        .await;
+
+    assert!(storage.get_key_for_user("ALICE").await.is_some());
+    assert!(storage.get_key_for_user("alice").await.is_some());
+
+    let sessions = storage.list_sessions(0, 10).await;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].username, "ALICE");
+    assert_eq!(sessions[0].address, ADDRESS);
+
+    assert!(storage.remove_key("aLiCe").await);
+    assert!(!storage.remove_key("Alice").await);
+    assert!(storage.get_key_for_user("Alice").await.is_none());
+}
+
+#[tokio::test]
+async fn keys_replaced_on_new_login() {
+    let mut storage = MemoryKeyStorage::new();
+
+    storage
+        .add_key("A".to_string(), srp_server("A", "PASSWORD"))
+        .await;
+    let second = srp_server("A", "PASSWORD");
+    storage
+        .add_key_with_address("a".to_string(), second.clone(), ADDRESS)
+        .await;
+
+    assert_eq!(
+        storage.get_key_for_user("A").await.unwrap().session_key(),
+        second.session_key()
+    );
+    let sessions = storage.list_sessions(0, 10).await;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].address, ADDRESS);
+}
+
+#[tokio::test]
+async fn realms_insert_and_remove() {
+    let mut provider = MemoryRealmListProvider::new(vec![realm(0)]);
+
+    provider.insert_realm(realm(1));
+    let mut renamed = realm(1);
+    renamed.name = "Renamed".to_string();
+    provider.insert_realm(renamed);
+
+    let realms = provider.get_realm_list(&vanilla_1_12("A")).await;
+    assert_eq!(realms.len(), 2);
+    assert_eq!(realms[1].name, "Renamed");
+
+    assert_eq!(provider.remove_realm(0).unwrap().realm_id, 0);
+    assert!(provider.remove_realm(0).is_none());
+    assert_eq!(provider.realms().len(), 1);
+}
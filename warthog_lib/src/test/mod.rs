@@ -0,0 +1,67 @@
+mod cache;
+mod memory;
+mod transfer;
+
+use crate::{
+    CMD_AUTH_LOGON_CHALLENGE_Client, NormalizedString, Os, Platform, Population, Realm,
+    RealmCategory, RealmType, Realm_RealmFlag, SrpServer, SrpVerifier, Version,
+};
+use std::net::Ipv4Addr;
+use wow_login_messages::all::{Locale, ProtocolVersion};
+use wow_srp::client::SrpClientChallenge;
+use wow_srp::{PublicKey, GENERATOR, LARGE_SAFE_PRIME_LITTLE_ENDIAN};
+
+fn vanilla_1_12(account_name: &str) -> CMD_AUTH_LOGON_CHALLENGE_Client {
+    CMD_AUTH_LOGON_CHALLENGE_Client {
+        protocol_version: ProtocolVersion::Three,
+        version: Version {
+            major: 1,
+            minor: 12,
+            patch: 1,
+            build: 5875,
+        },
+        platform: Platform::X86,
+        os: Os::Windows,
+        locale: Locale::EnGb,
+        utc_timezone_offset: 60,
+        client_ip_address: Ipv4Addr::new(127, 0, 0, 1),
+        account_name: account_name.to_string(),
+    }
+}
+
+fn realm(realm_id: u8) -> Realm {
+    Realm {
+        realm_type: RealmType::try_from(0).unwrap(),
+        locked: false,
+        flag: Realm_RealmFlag::new(0, None),
+        name: format!("Realm {realm_id}"),
+        address: "localhost:8085".to_string(),
+        population: Population::from(0.0),
+        number_of_characters_on_realm: 0,
+        category: RealmCategory::try_from(0).unwrap(),
+        realm_id,
+    }
+}
+
+/// Runs both sides of an SRP login to get the session key that would be stored after it.
+fn srp_server(username: &str, password: &str) -> SrpServer {
+    let username = NormalizedString::new(username).unwrap();
+    let password = NormalizedString::new(password).unwrap();
+    let proof =
+        SrpVerifier::from_username_and_password(username.clone(), password.clone()).into_proof();
+
+    let client = SrpClientChallenge::new(
+        username,
+        password,
+        GENERATOR,
+        LARGE_SAFE_PRIME_LITTLE_ENDIAN,
+        PublicKey::from_le_bytes(*proof.server_public_key()).unwrap(),
+        *proof.salt(),
+    );
+    let client_public_key = PublicKey::from_le_bytes(*client.client_public_key()).unwrap();
+
+    let (server, _) = proof
+        .into_server(client_public_key, *client.client_proof())
+        .unwrap();
+    server
+}
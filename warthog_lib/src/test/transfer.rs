@@ -0,0 +1,44 @@
+use crate::auth::transfer::peek_arrived;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+async fn connected() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    (client, server)
+}
+
+#[tokio::test]
+async fn peek_does_not_wait_for_client() {
+    let (_client, server) = connected().await;
+
+    assert!(peek_arrived(&server).await.is_none());
+}
+
+#[tokio::test]
+async fn peek_sees_cancel() {
+    let (mut client, server) = connected().await;
+
+    client.write_all(&[0x34]).await.unwrap();
+    client.flush().await.unwrap();
+    // Only peek once the byte has arrived, without depending on how long that takes
+    server.readable().await.unwrap();
+
+    assert_eq!(peek_arrived(&server).await.unwrap().unwrap(), 1);
+    // Peeking does not consume the message
+    assert_eq!(peek_arrived(&server).await.unwrap().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn peek_sees_closed_connection() {
+    let (client, server) = connected().await;
+
+    drop(client);
+    server.readable().await.unwrap();
+
+    assert_eq!(peek_arrived(&server).await.unwrap().unwrap(), 0);
+}
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use warthog_messages::ClientOpcodes;
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    // Anything that is read must be written and read back the same
+    if let Ok((request_id, mut message)) = runtime.block_on(ClientOpcodes::tokio_read(data)) {
+        let mut frame = Vec::new();
+        runtime
+            .block_on(message.tokio_write(request_id, &mut frame))
+            .unwrap();
+
+        let (_, mut read) = runtime
+            .block_on(ClientOpcodes::tokio_read(frame.as_slice()))
+            .unwrap();
+        let mut written = Vec::new();
+        runtime
+            .block_on(read.tokio_write(request_id, &mut written))
+            .unwrap();
+
+        assert_eq!(frame, written);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use warthog_messages::ServerOpcodes;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that is read must be written and read back the same
+    if let Ok((request_id, mut message)) = ServerOpcodes::read(data) {
+        let mut frame = Vec::new();
+        message.write(request_id, &mut frame).unwrap();
+
+        let (_, mut read) = ServerOpcodes::read(frame.as_slice()).unwrap();
+        let mut written = Vec::new();
+        read.write(request_id, &mut written).unwrap();
+
+        assert_eq!(frame, written);
+    }
+});
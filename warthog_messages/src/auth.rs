@@ -0,0 +1,158 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::ops::{BitAnd, BitOr};
+
+pub const CHALLENGE_LENGTH: usize = 32;
+pub const PROOF_LENGTH: usize = 32;
+/// Longest key name, since the length is a single byte in the proof.
+pub const MAX_KEY_NAME_LENGTH: usize = u8::MAX as usize;
+
+/// Operations a connection to the reply server is allowed to perform.
+#[derive(Debug, Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    /// `request_session_key` and `subscribe_sessions`.
+    pub const SESSION_KEYS: Self = Self(1 << 0);
+    /// `register_realm`, `character_amount_answer` and `account_online`.
+    pub const REALM: Self = Self(1 << 1);
+    /// `add_user`, `add_user_with_verifier`, `remove_user`, `modify_user`, `revoke_session` and `kick_account`.
+    pub const USER_MANAGEMENT: Self = Self(1 << 2);
+    /// `list_accounts`, `list_sessions` and `list_realms`.
+    pub const QUERIES: Self = Self(1 << 3);
+    /// Changing any realm through the HTTP gateway, instead of only the realm registered on the connection.
+    pub const REALM_ADMIN: Self = Self(1 << 4);
+    pub const ALL: Self = Self(
+        Self::SESSION_KEYS.0
+            | Self::REALM.0
+            | Self::USER_MANAGEMENT.0
+            | Self::QUERIES.0
+            | Self::REALM_ADMIN.0,
+    );
+
+    pub const fn new(value: u8) -> Self {
+        Self(value)
+    }
+
+    pub const fn as_int(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Permissions {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// Sent by the auth server when a connection has been authenticated.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct AuthAccepted {
+    pub permissions: Permissions,
+    /// Proves that the auth server also knows the key.
+    pub server_proof: [u8; PROOF_LENGTH],
+}
+
+pub fn random_challenge() -> [u8; CHALLENGE_LENGTH] {
+    let mut challenge = [0_u8; CHALLENGE_LENGTH];
+    getrandom::getrandom(&mut challenge).expect("unable to get random bytes from the OS");
+
+    challenge
+}
+
+/// [`None`] if `name` is longer than [`MAX_KEY_NAME_LENGTH`].
+fn proof(
+    label: &[u8],
+    key: &[u8],
+    name: &str,
+    server_challenge: &[u8; CHALLENGE_LENGTH],
+    client_challenge: &[u8; CHALLENGE_LENGTH],
+) -> Option<Hmac<Sha256>> {
+    let name_length = u8::try_from(name.len()).ok()?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(label);
+    mac.update(&[name_length]);
+    mac.update(name.as_bytes());
+    mac.update(server_challenge);
+    mac.update(client_challenge);
+
+    Some(mac)
+}
+
+/// Proof sent by the world server in `auth_proof`.
+///
+/// [`None`] if `name` is longer than [`MAX_KEY_NAME_LENGTH`].
+pub fn client_proof(
+    key: &[u8],
+    name: &str,
+    server_challenge: &[u8; CHALLENGE_LENGTH],
+    client_challenge: &[u8; CHALLENGE_LENGTH],
+) -> Option<[u8; PROOF_LENGTH]> {
+    Some(
+        proof(b"client", key, name, server_challenge, client_challenge)?
+            .finalize()
+            .into_bytes()
+            .into(),
+    )
+}
+
+/// Constant time comparison of a received client proof.
+///
+/// Always `false` if `name` is longer than [`MAX_KEY_NAME_LENGTH`].
+pub fn verify_client_proof(
+    key: &[u8],
+    name: &str,
+    server_challenge: &[u8; CHALLENGE_LENGTH],
+    client_challenge: &[u8; CHALLENGE_LENGTH],
+    client_proof: &[u8; PROOF_LENGTH],
+) -> bool {
+    proof(b"client", key, name, server_challenge, client_challenge)
+        .is_some_and(|mac| mac.verify_slice(client_proof).is_ok())
+}
+
+/// Proof sent by the auth server in `auth_proof_reply`.
+///
+/// [`None`] if `name` is longer than [`MAX_KEY_NAME_LENGTH`].
+pub fn server_proof(
+    key: &[u8],
+    name: &str,
+    server_challenge: &[u8; CHALLENGE_LENGTH],
+    client_challenge: &[u8; CHALLENGE_LENGTH],
+) -> Option<[u8; PROOF_LENGTH]> {
+    Some(
+        proof(b"server", key, name, server_challenge, client_challenge)?
+            .finalize()
+            .into_bytes()
+            .into(),
+    )
+}
+
+/// Constant time comparison of a received server proof.
+///
+/// Always `false` if `name` is longer than [`MAX_KEY_NAME_LENGTH`].
+pub fn verify_server_proof(
+    key: &[u8],
+    name: &str,
+    server_challenge: &[u8; CHALLENGE_LENGTH],
+    client_challenge: &[u8; CHALLENGE_LENGTH],
+    server_proof: &[u8; PROOF_LENGTH],
+) -> bool {
+    proof(b"server", key, name, server_challenge, client_challenge)
+        .is_some_and(|mac| mac.verify_slice(server_proof).is_ok())
+}
@@ -1,175 +1,209 @@
-use crate::MessageError;
-
-pub enum ClientOpcodes {
-    SessionKeyAnswer {
-        name: String,
-        session_key: Option<[u8; 40]>,
-    },
-    RegisterRealmReply {
-        realm_id: Option<u8>,
-    },
-    AddUserReply {
-        name: String,
-        success: bool,
-    },
-    RemoveUserReply {
-        name: String,
-        success: bool,
-    },
-    ModifyUserReply {
-        name: String,
-        success: bool,
-    },
+use crate::{AuthAccepted, MessageError, CHALLENGE_LENGTH};
+use std::fmt::{Display, Formatter};
+
+/// Reason for rejecting a realm registration or update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterRealmError {
+    /// No specific reason, also used for reasons added in newer versions.
+    Unknown,
+    PermissionDenied,
+    InvalidCategory,
+    InvalidRealmType,
+    /// Address is not `host:port`.
+    InvalidAddress,
+    /// Requested realm ID is assigned to another realm, or there are no free realm IDs.
+    ///
+    /// For updates, the new name is assigned a different realm ID.
+    RealmIdUnavailable,
+    /// Update was sent before a realm was registered on the connection.
+    NotRegistered,
 }
 
-impl ClientOpcodes {
-    const SESSION_KEY_ANSWER_OPCODE: u8 = 1;
-    const REGISTER_REALM_REPLY_OPCODE: u8 = 5;
-    const ADD_USER_REPLY_OPCODE: u8 = 7;
-    const REMOVE_USER_REPLY_OPCODE: u8 = 9;
-    const MODIFY_USER_REPLY_OPCODE: u8 = 11;
-
-    const fn opcode(&self) -> u8 {
+impl RegisterRealmError {
+    pub const fn as_int(&self) -> u8 {
         match self {
-            ClientOpcodes::SessionKeyAnswer { .. } => Self::SESSION_KEY_ANSWER_OPCODE,
-            ClientOpcodes::RegisterRealmReply { .. } => Self::REGISTER_REALM_REPLY_OPCODE,
-            ClientOpcodes::AddUserReply { .. } => Self::ADD_USER_REPLY_OPCODE,
-            ClientOpcodes::RemoveUserReply { .. } => Self::REMOVE_USER_REPLY_OPCODE,
-            ClientOpcodes::ModifyUserReply { .. } => Self::MODIFY_USER_REPLY_OPCODE,
+            RegisterRealmError::Unknown => 0,
+            RegisterRealmError::PermissionDenied => 1,
+            RegisterRealmError::InvalidCategory => 2,
+            RegisterRealmError::InvalidRealmType => 3,
+            RegisterRealmError::InvalidAddress => 4,
+            RegisterRealmError::RealmIdUnavailable => 5,
+            RegisterRealmError::NotRegistered => 6,
         }
     }
 
-    #[cfg(feature = "sync")]
-    pub fn read<R: std::io::Read>(mut r: R) -> Result<Self, MessageError> {
-        let mut opcode = [0_u8; 1];
-        r.read_exact(&mut opcode)?;
-
-        Ok(match opcode[0] {
-            Self::SESSION_KEY_ANSWER_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-
-                let session_key = if crate::read_bool(&mut r)? {
-                    let mut session_key = [0_u8; 40];
-                    r.read_exact(&mut session_key)?;
-
-                    Some(session_key)
-                } else {
-                    None
-                };
-
-                Self::SessionKeyAnswer { name, session_key }
-            }
-            Self::REGISTER_REALM_REPLY_OPCODE => {
-                let success = crate::read_bool(&mut r)?;
-                let realm_id = if success {
-                    Some(crate::read_u8(&mut r)?)
-                } else {
-                    None
-                };
-
-                Self::RegisterRealmReply { realm_id }
-            }
-            Self::REMOVE_USER_REPLY_OPCODE
-            | Self::MODIFY_USER_REPLY_OPCODE
-            | Self::ADD_USER_REPLY_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-
-                let success = crate::read_bool(&mut r)?;
+    pub const fn from_int(value: u8) -> Self {
+        match value {
+            1 => RegisterRealmError::PermissionDenied,
+            2 => RegisterRealmError::InvalidCategory,
+            3 => RegisterRealmError::InvalidRealmType,
+            4 => RegisterRealmError::InvalidAddress,
+            5 => RegisterRealmError::RealmIdUnavailable,
+            6 => RegisterRealmError::NotRegistered,
+            _ => RegisterRealmError::Unknown,
+        }
+    }
+}
 
-                Self::AddUserReply { name, success }
-            }
-            v => return Err(MessageError::InvalidOpcode(v)),
+impl Display for RegisterRealmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RegisterRealmError::Unknown => "realm was not registered",
+            RegisterRealmError::PermissionDenied => "permission denied",
+            RegisterRealmError::InvalidCategory => "invalid realm category",
+            RegisterRealmError::InvalidRealmType => "invalid realm type",
+            RegisterRealmError::InvalidAddress => "invalid realm address",
+            RegisterRealmError::RealmIdUnavailable => "realm id is not available",
+            RegisterRealmError::NotRegistered => "realm is not registered",
         })
     }
+}
 
-    pub fn write<W: std::io::Write>(&mut self, mut w: W) -> std::io::Result<()> {
-        crate::write_u8(&mut w, self.opcode())?;
-
-        match self {
-            ClientOpcodes::SessionKeyAnswer { name, session_key } => {
-                crate::write_string(&mut w, &name)?;
-
-                if let Some(session_key) = session_key {
-                    crate::write_bool(&mut w, true)?;
-
-                    w.write_all(session_key)?;
-                } else {
-                    crate::write_bool(&mut w, false)?;
-                }
-            }
-            ClientOpcodes::RegisterRealmReply { realm_id } => {
-                if let Some(realm_id) = realm_id {
-                    crate::write_bool(&mut w, true)?;
+impl std::error::Error for RegisterRealmError {}
+
+/// Change to the session of a user, pushed to connections that subscribed to sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionChange {
+    /// User logged in without having a session.
+    Added { session_key: [u8; 40] },
+    /// User logged in again, replacing the previous session key.
+    Refreshed { session_key: [u8; 40] },
+    /// Session was removed, and the user must log in again.
+    Revoked,
+}
 
-                    crate::write_u8(&mut w, *realm_id)?;
-                } else {
-                    crate::write_bool(&mut w, false)?;
-                }
-            }
-            ClientOpcodes::ModifyUserReply { name, success }
-            | ClientOpcodes::RemoveUserReply { name, success }
-            | ClientOpcodes::AddUserReply { name, success } => {
-                crate::write_string(&mut w, name)?;
+impl SessionChange {
+    pub(crate) const ADDED: u8 = 0;
+    pub(crate) const REFRESHED: u8 = 1;
+    pub(crate) const REVOKED: u8 = 2;
 
-                crate::write_bool(&mut w, *success)?;
-            }
+    pub(crate) const fn tag(&self) -> u8 {
+        match self {
+            SessionChange::Added { .. } => Self::ADDED,
+            SessionChange::Refreshed { .. } => Self::REFRESHED,
+            SessionChange::Revoked => Self::REVOKED,
         }
-
-        Ok(())
     }
 
-    #[cfg(feature = "tokio")]
-    pub async fn tokio_read<R: tokio::io::AsyncReadExt + Unpin>(
-        mut r: R,
-    ) -> Result<Self, MessageError> {
-        let mut opcode = [0_u8; 1];
-        r.read_exact(&mut opcode).await?;
-
-        Ok(match opcode[0] {
-            Self::SESSION_KEY_ANSWER_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
-
-                let session_key = if crate::read_bool_tokio(&mut r).await? {
-                    let mut session_key = [0_u8; 40];
-                    r.read_exact(&mut session_key).await?;
-
-                    Some(session_key)
-                } else {
-                    None
-                };
-
-                Self::SessionKeyAnswer { name, session_key }
+    /// Returns [`None`] if the session has been revoked.
+    pub const fn session_key(&self) -> Option<&[u8; 40]> {
+        match self {
+            SessionChange::Added { session_key } | SessionChange::Refreshed { session_key } => {
+                Some(session_key)
             }
-            Self::REGISTER_REALM_REPLY_OPCODE => {
-                let success = crate::read_bool_tokio(&mut r).await?;
-                let realm_id = if success {
-                    Some(crate::read_u8_tokio(&mut r).await?)
-                } else {
-                    None
-                };
+            SessionChange::Revoked => None,
+        }
+    }
+}
 
-                Self::RegisterRealmReply { realm_id }
-            }
-            Self::MODIFY_USER_REPLY_OPCODE
-            | Self::REMOVE_USER_REPLY_OPCODE
-            | Self::ADD_USER_REPLY_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
+/// Account in [`ClientOpcodes::ListAccountsReply`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub name: String,
+    pub account_flags: u32,
+    pub has_pin: bool,
+    pub has_matrix_card: bool,
+}
 
-                let success = crate::read_bool_tokio(&mut r).await?;
+/// Session in [`ClientOpcodes::ListSessionsReply`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub name: String,
+    /// IP address the user logged in from.
+    pub address: String,
+    /// Seconds since the Unix epoch.
+    pub login_time: u64,
+}
 
-                Self::AddUserReply { name, success }
-            }
-            v => return Err(MessageError::InvalidOpcode(v)),
-        })
-    }
+/// Realm in [`ClientOpcodes::ListRealmsReply`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealmSummary {
+    pub realm_id: u8,
+    pub name: String,
+    /// Address that clients connect to.
+    pub address: String,
+    pub population: f32,
+    /// Flags sent by the world server, without `OFFLINE`.
+    pub flags: u8,
+    pub category: u8,
+    pub realm_type: u8,
+    /// `false` if the world server has disconnected or stopped sending heartbeats.
+    pub online: bool,
+    /// Address of the reply connection of the world server that registered the realm.
+    pub peer_address: String,
+}
 
-    #[cfg(feature = "tokio")]
-    pub async fn tokio_write<W: tokio::io::AsyncWriteExt + Unpin>(
-        &mut self,
-        mut w: W,
-    ) -> std::io::Result<()> {
-        let mut v = Vec::new();
-        self.write(&mut v)?;
-        w.write_all(&v).await
+messages! {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ClientOpcodes {
+        1 => SessionKeyAnswer {
+            name: String,
+            session_key: Option<[u8; 40]>,
+        },
+        2 => RequestCharacterAmount {
+            name: String,
+        },
+        5 => RegisterRealmReply {
+            /// Realm ID, or why the realm was not registered.
+            result: Result<u8, RegisterRealmError>,
+        },
+        7 => AddUserReply {
+            name: String,
+            success: bool,
+        },
+        9 => RemoveUserReply {
+            name: String,
+            success: bool,
+        },
+        11 => ModifyUserReply {
+            name: String,
+            success: bool,
+        },
+        13 => AuthChallenge {
+            server_challenge: [u8; CHALLENGE_LENGTH],
+        },
+        15 => AuthProofReply {
+            accepted: Option<AuthAccepted>,
+        },
+        17 => UpdateRealmReply {
+            /// Empty, or why the realm was not updated.
+            result: Result<(), RegisterRealmError>,
+        },
+        21 => SubscribeSessionsReply {
+            success: bool,
+        },
+        /// Sent with a `request_id` of 0 after [`ServerOpcodes::SubscribeSessions`](crate::ServerOpcodes::SubscribeSessions).
+        23 => SessionChanged {
+            name: String,
+            change: SessionChange,
+        },
+        25 => RevokeSessionReply {
+            name: String,
+            /// `false` if the user did not have a session.
+            success: bool,
+        },
+        27 => KickAccountReply {
+            name: String,
+            session_revoked: bool,
+            /// A world server had reported the account as online and was told to disconnect it.
+            disconnected: bool,
+        },
+        /// Sent with a `request_id` of 0 to the connection that reported the account as online
+        /// with [`ServerOpcodes::AccountOnline`](crate::ServerOpcodes::AccountOnline).
+        29 => DisconnectAccount {
+            name: String,
+        },
+        33 => ListAccountsReply {
+            /// [`None`] if the connection is not allowed to list accounts.
+            accounts: Option<Vec<AccountSummary>>,
+        },
+        35 => ListSessionsReply {
+            /// [`None`] if the connection is not allowed to list sessions.
+            sessions: Option<Vec<SessionSummary>>,
+        },
+        37 => ListRealmsReply {
+            /// [`None`] if the connection is not allowed to list realms.
+            realms: Option<Vec<RealmSummary>>,
+        },
     }
 }
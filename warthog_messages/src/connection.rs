@@ -0,0 +1,770 @@
+//! Client for world servers.
+//!
+//! [`WarthogConnection`] sends requests over a single reply connection and matches replies to requests,
+//! so methods can be called concurrently from multiple tasks.
+//! If the connection is lost, it reconnects with exponential backoff, registers the realm again,
+//! renews the session subscription and reports online accounts again.
+
+use crate::{
+    client_proof, random_challenge, tokio_request_version, verify_server_proof, AccountSummary,
+    ClientOpcodes, MatrixCardData, MessageError, Modification, Permissions, RealmFlags,
+    RealmSummary, RegisterRealmError, ServerOpcodes, SessionChange, SessionSummary,
+    PASSWORD_VERIFIER_LENGTH, PROTOCOL_VERSION, REQUEST_ID_VERSION, SALT_LENGTH,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// First wait before reconnecting, doubled for every failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// Maximum amount of requests waiting to be sent.
+const MAX_QUEUED_REQUESTS: usize = 64;
+/// Session changes and disconnects that a slow receiver can fall behind on before new ones are dropped.
+const MAX_QUEUED_CHANGES: usize = 1024;
+/// Request ID used during the handshake, before any other requests are sent.
+const HANDSHAKE_REQUEST_ID: u32 = 0;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Prefix of [`ConnectionOptions::address`] for Unix domain sockets.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+pub struct ConnectionOptions {
+    /// `host:port` of the reply server, or `unix:PATH` for a Unix domain socket.
+    ///
+    /// TLS is not used for Unix domain sockets.
+    pub address: String,
+    /// Name and key for reply servers that require authentication.
+    pub key: Option<(String, Vec<u8>)>,
+    /// Connector and the name that the reply server certificate must be valid for.
+    #[cfg(feature = "tls")]
+    pub tls: Option<(crate::tls::TlsConnector, crate::tls::ServerName<'static>)>,
+    /// Longest wait between reconnection attempts.
+    pub max_backoff: Duration,
+    /// Longest wait for the reply to a request, including waiting for a reconnection.
+    ///
+    /// Requests that time out fail with [`MessageError::TimedOut`].
+    pub request_timeout: Duration,
+    /// Longest wait for connecting, negotiating the version, authenticating and restoring state.
+    pub handshake_timeout: Duration,
+}
+
+/// Realm sent in [`WarthogConnection::register_realm`].
+#[derive(Debug, Clone)]
+pub struct RealmRegistration {
+    pub name: String,
+    pub address: String,
+    pub population: f32,
+    pub locked: bool,
+    pub flags: RealmFlags,
+    pub category: u8,
+    pub realm_type: u8,
+    /// Ask for a specific realm ID.
+    pub realm_id: Option<u8>,
+}
+
+impl RealmRegistration {
+    fn message(&self) -> ServerOpcodes {
+        ServerOpcodes::RegisterRealm {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            population: self.population,
+            locked: self.locked,
+            flags: self.flags.flags,
+            category: self.category,
+            realm_type: self.realm_type,
+            version_major: self.flags.version_major,
+            version_minor: self.flags.version_minor,
+            version_patch: self.flags.version_patch,
+            version_build: self.flags.version_build,
+            realm_id: self.realm_id,
+        }
+    }
+}
+
+/// Fields changed in [`WarthogConnection::update_realm`]. Fields that are [`None`] are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct RealmUpdate {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub population: Option<f32>,
+    pub locked: Option<bool>,
+    pub flags: Option<RealmFlags>,
+    pub category: Option<u8>,
+    pub realm_type: Option<u8>,
+}
+
+impl RealmUpdate {
+    fn apply(&self, realm: &mut RealmRegistration) {
+        if let Some(name) = &self.name {
+            realm.name = name.clone();
+        }
+        if let Some(address) = &self.address {
+            realm.address = address.clone();
+        }
+        if let Some(population) = self.population {
+            realm.population = population;
+        }
+        if let Some(locked) = self.locked {
+            realm.locked = locked;
+        }
+        if let Some(flags) = self.flags {
+            realm.flags = flags;
+        }
+        if let Some(category) = self.category {
+            realm.category = category;
+        }
+        if let Some(realm_type) = self.realm_type {
+            realm.realm_type = realm_type;
+        }
+    }
+
+    fn message(&self) -> ServerOpcodes {
+        ServerOpcodes::UpdateRealm {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            population: self.population,
+            locked: self.locked,
+            flags: self.flags,
+            category: self.category,
+            realm_type: self.realm_type,
+        }
+    }
+}
+
+/// Session changes from [`WarthogConnection::subscribe_sessions`].
+///
+/// Changes are dropped while the receiver is more than 1024 changes behind.
+pub type SessionReceiver = mpsc::Receiver<(String, SessionChange)>;
+
+/// Names of accounts that the auth server wants disconnected, from [`WarthogConnection::disconnects`].
+///
+/// Names are dropped while the receiver is more than 1024 names behind.
+pub type DisconnectReceiver = mpsc::Receiver<String>;
+
+/// Names of accounts that the auth server wants the amount of characters for,
+/// from [`WarthogConnection::character_amount_requests`].
+///
+/// Names are dropped while the receiver is more than 1024 names behind.
+pub type CharacterAmountReceiver = mpsc::Receiver<String>;
+
+/// Restored after reconnecting.
+#[derive(Debug, Default)]
+struct State {
+    realm: Option<RealmRegistration>,
+    sessions: Option<mpsc::Sender<(String, SessionChange)>>,
+    online: HashSet<String>,
+    disconnects: Option<mpsc::Sender<String>>,
+    character_amount_requests: Option<mpsc::Sender<String>>,
+}
+
+struct Request {
+    message: ServerOpcodes,
+    /// [`None`] for messages without a reply.
+    reply: Option<oneshot::Sender<ClientOpcodes>>,
+}
+
+/// Connection to the reply server of an auth server.
+///
+/// Cloning is cheap and all clones share the same connection.
+#[derive(Debug, Clone)]
+pub struct WarthogConnection {
+    requests: mpsc::Sender<Request>,
+    state: Arc<Mutex<State>>,
+    request_timeout: Duration,
+}
+
+impl WarthogConnection {
+    /// Connects to the reply server.
+    ///
+    /// Errors from the first connection are returned, after that the connection is kept open in the background
+    /// until every clone has been dropped or the key or the registered realm is rejected.
+    pub async fn connect(options: ConnectionOptions) -> Result<Self, MessageError> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let stream = connect(&options, &state).await?;
+
+        let request_timeout = options.request_timeout;
+        let (requests, receiver) = mpsc::channel(MAX_QUEUED_REQUESTS);
+        tokio::spawn(run(options, stream, receiver, state.clone()));
+
+        Ok(Self {
+            requests,
+            state,
+            request_timeout,
+        })
+    }
+
+    async fn send(&self, message: ServerOpcodes) -> Result<(), MessageError> {
+        let send = self.requests.send(Request {
+            message,
+            reply: None,
+        });
+
+        tokio::time::timeout(self.request_timeout, send)
+            .await
+            .map_err(|_| MessageError::TimedOut)?
+            .map_err(|_| MessageError::ConnectionClosed)
+    }
+
+    async fn request(&self, message: ServerOpcodes) -> Result<ClientOpcodes, MessageError> {
+        let request = async {
+            let (sender, receiver) = oneshot::channel();
+            self.requests
+                .send(Request {
+                    message,
+                    reply: Some(sender),
+                })
+                .await
+                .map_err(|_| MessageError::ConnectionClosed)?;
+
+            receiver.await.map_err(|_| MessageError::ConnectionClosed)
+        };
+
+        tokio::time::timeout(self.request_timeout, request)
+            .await
+            .map_err(|_| MessageError::TimedOut)?
+    }
+
+    /// Returns the realm ID, or why the auth server did not accept the realm.
+    ///
+    /// The realm is registered again with the same ID if the connection is lost.
+    pub async fn register_realm(
+        &self,
+        realm: RealmRegistration,
+    ) -> Result<Result<u8, RegisterRealmError>, MessageError> {
+        match self.request(realm.message()).await? {
+            ClientOpcodes::RegisterRealmReply { result } => {
+                if let Ok(realm_id) = result {
+                    self.state.lock().unwrap().realm = Some(RealmRegistration {
+                        realm_id: Some(realm_id),
+                        ..realm
+                    });
+                }
+
+                Ok(result)
+            }
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Returns why the auth server did not accept the update, if it did not.
+    pub async fn update_realm(
+        &self,
+        update: RealmUpdate,
+    ) -> Result<Result<(), RegisterRealmError>, MessageError> {
+        match self.request(update.message()).await? {
+            ClientOpcodes::UpdateRealmReply { result } => {
+                if result.is_ok() {
+                    if let Some(realm) = self.state.lock().unwrap().realm.as_mut() {
+                        update.apply(realm);
+                    }
+                }
+
+                Ok(result)
+            }
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    pub async fn heartbeat(&self) -> Result<(), MessageError> {
+        self.send(ServerOpcodes::Heartbeat).await
+    }
+
+    /// Tells the auth server how many characters the account has on the realm.
+    ///
+    /// Sent to answer [`Self::character_amount_requests`], or whenever the amount changes.
+    pub async fn character_amount(
+        &self,
+        name: String,
+        amount_of_characters: u8,
+    ) -> Result<(), MessageError> {
+        self.send(ServerOpcodes::CharacterAmountAnswer {
+            name,
+            amount_of_characters,
+        })
+        .await
+    }
+
+    /// Returns [`None`] if the user is not logged in.
+    pub async fn session_key(&self, name: String) -> Result<Option<[u8; 40]>, MessageError> {
+        match self
+            .request(ServerOpcodes::RequestSessionKey { name })
+            .await?
+        {
+            ClientOpcodes::SessionKeyAnswer { session_key, .. } => Ok(session_key),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Returns every new, refreshed and revoked session from now on,
+    /// or [`None`] if the connection does not have permission to see sessions.
+    ///
+    /// The subscription is renewed after reconnecting, but changes while disconnected are lost.
+    /// Calling this again replaces the previous subscription.
+    pub async fn subscribe_sessions(&self) -> Result<Option<SessionReceiver>, MessageError> {
+        // Set before sending, since changes can arrive right after the reply
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_CHANGES);
+        self.state.lock().unwrap().sessions = Some(sender);
+
+        let success = match self.request(ServerOpcodes::SubscribeSessions).await? {
+            ClientOpcodes::SubscribeSessionsReply { success } => success,
+            reply => return Err(MessageError::UnexpectedReply(reply.opcode())),
+        };
+
+        if !success {
+            self.state.lock().unwrap().sessions = None;
+            return Ok(None);
+        }
+
+        Ok(Some(receiver))
+    }
+
+    /// Lets the auth server forward kicks for the account to this connection.
+    ///
+    /// The auth server ignores accounts that do not have a session.
+    pub async fn account_online(&self, name: String, online: bool) -> Result<(), MessageError> {
+        {
+            // Kicks are sent in uppercase
+            let mut state = self.state.lock().unwrap();
+            if online {
+                state.online.insert(name.to_uppercase());
+            } else {
+                state.online.remove(&name.to_uppercase());
+            }
+        }
+
+        self.send(ServerOpcodes::AccountOnline { name, online })
+            .await
+    }
+
+    /// Returns the names of accounts reported with [`Self::account_online`] that have been kicked.
+    ///
+    /// Kicked accounts are no longer reported as online.
+    /// Calling this again replaces the previous receiver.
+    pub fn disconnects(&self) -> DisconnectReceiver {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_CHANGES);
+        self.state.lock().unwrap().disconnects = Some(sender);
+
+        receiver
+    }
+
+    /// Returns the names of accounts that the auth server wants [`Self::character_amount`] for.
+    ///
+    /// The auth server asks every registered realm when an account logs in.
+    /// Calling this again replaces the previous receiver.
+    pub fn character_amount_requests(&self) -> CharacterAmountReceiver {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_CHANGES);
+        self.state.lock().unwrap().character_amount_requests = Some(sender);
+
+        receiver
+    }
+
+    /// Returns `false` if the user did not have a session.
+    pub async fn revoke_session(&self, name: String) -> Result<bool, MessageError> {
+        match self.request(ServerOpcodes::RevokeSession { name }).await? {
+            ClientOpcodes::RevokeSessionReply { success, .. } => Ok(success),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Revokes the session and disconnects the account from the world server it is playing on.
+    ///
+    /// Returns whether the session was revoked and whether a world server was told to disconnect the account.
+    pub async fn kick_account(&self, name: String) -> Result<(bool, bool), MessageError> {
+        match self.request(ServerOpcodes::KickAccount { name }).await? {
+            ClientOpcodes::KickAccountReply {
+                session_revoked,
+                disconnected,
+                ..
+            } => Ok((session_revoked, disconnected)),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    pub async fn add_user(&self, name: String, password: String) -> Result<bool, MessageError> {
+        match self
+            .request(ServerOpcodes::AddUser { name, password })
+            .await?
+        {
+            ClientOpcodes::AddUserReply { success, .. } => Ok(success),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Adds a user without sending the password.
+    ///
+    /// `salt` and `password_verifier` are computed from the normalized name and password,
+    /// for example with `wow_srp::server::SrpVerifier::from_username_and_password`.
+    pub async fn add_user_with_verifier(
+        &self,
+        name: String,
+        salt: [u8; SALT_LENGTH],
+        password_verifier: [u8; PASSWORD_VERIFIER_LENGTH],
+    ) -> Result<bool, MessageError> {
+        match self
+            .request(ServerOpcodes::AddUserWithVerifier {
+                name,
+                salt,
+                password_verifier,
+            })
+            .await?
+        {
+            ClientOpcodes::AddUserReply { success, .. } => Ok(success),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    pub async fn remove_user(&self, name: String) -> Result<bool, MessageError> {
+        match self.request(ServerOpcodes::RemoveUser { name }).await? {
+            ClientOpcodes::RemoveUserReply { success, .. } => Ok(success),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    pub async fn modify_user(
+        &self,
+        name: String,
+        password: Option<String>,
+        pin: Modification<u64>,
+        matrix_card: Modification<MatrixCardData>,
+        account_flags: Option<u32>,
+    ) -> Result<bool, MessageError> {
+        let message = ServerOpcodes::ModifyUser {
+            name,
+            password,
+            pin,
+            matrix_card,
+            account_flags,
+        };
+
+        match self.request(message).await? {
+            ClientOpcodes::ModifyUserReply { success, .. } => Ok(success),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Returns [`None`] if the connection is not allowed to list accounts.
+    ///
+    /// `limit` is capped at [`MAX_LIST_LIMIT`](crate::MAX_LIST_LIMIT).
+    pub async fn list_accounts(
+        &self,
+        offset: u32,
+        limit: u8,
+    ) -> Result<Option<Vec<AccountSummary>>, MessageError> {
+        match self
+            .request(ServerOpcodes::ListAccounts { offset, limit })
+            .await?
+        {
+            ClientOpcodes::ListAccountsReply { accounts } => Ok(accounts),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Returns [`None`] if the connection is not allowed to list sessions.
+    ///
+    /// `limit` is capped at [`MAX_LIST_LIMIT`](crate::MAX_LIST_LIMIT).
+    pub async fn list_sessions(
+        &self,
+        offset: u32,
+        limit: u8,
+    ) -> Result<Option<Vec<SessionSummary>>, MessageError> {
+        match self
+            .request(ServerOpcodes::ListSessions { offset, limit })
+            .await?
+        {
+            ClientOpcodes::ListSessionsReply { sessions } => Ok(sessions),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    /// Returns [`None`] if the connection is not allowed to list realms.
+    ///
+    /// `limit` is capped at [`MAX_LIST_LIMIT`](crate::MAX_LIST_LIMIT).
+    pub async fn list_realms(
+        &self,
+        offset: u32,
+        limit: u8,
+    ) -> Result<Option<Vec<RealmSummary>>, MessageError> {
+        match self
+            .request(ServerOpcodes::ListRealms { offset, limit })
+            .await?
+        {
+            ClientOpcodes::ListRealmsReply { realms } => Ok(realms),
+            reply => Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+}
+
+/// [`handshake`] limited to [`ConnectionOptions::handshake_timeout`].
+async fn connect(
+    options: &ConnectionOptions,
+    state: &Mutex<State>,
+) -> Result<Box<dyn Stream>, MessageError> {
+    tokio::time::timeout(options.handshake_timeout, handshake(options, state))
+        .await
+        .map_err(|_| MessageError::TimedOut)?
+}
+
+/// Connects, negotiates the version, authenticates and restores the realm, session subscription and online accounts.
+async fn handshake(
+    options: &ConnectionOptions,
+    state: &Mutex<State>,
+) -> Result<Box<dyn Stream>, MessageError> {
+    let mut stream = open_stream(options).await?;
+
+    // Replies are matched to requests by their ID
+    if tokio_request_version(&mut stream).await? < REQUEST_ID_VERSION {
+        return Err(MessageError::UnsupportedVersion {
+            min: REQUEST_ID_VERSION,
+            max: PROTOCOL_VERSION,
+        });
+    }
+
+    if let Some((name, key)) = &options.key {
+        authenticate(&mut stream, name, key).await?;
+    }
+
+    let realm = state.lock().unwrap().realm.clone();
+    if let Some(realm) = realm {
+        realm
+            .message()
+            .tokio_write(HANDSHAKE_REQUEST_ID, &mut stream)
+            .await?;
+
+        match ClientOpcodes::tokio_read(&mut stream).await? {
+            (_, ClientOpcodes::RegisterRealmReply { result: Ok(_) }) => {}
+            (
+                _,
+                ClientOpcodes::RegisterRealmReply {
+                    result: Err(reason),
+                },
+            ) => return Err(MessageError::RegistrationRejected(reason)),
+            (_, reply) => return Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    let subscribed = state.lock().unwrap().sessions.is_some();
+    if subscribed {
+        ServerOpcodes::SubscribeSessions
+            .tokio_write(HANDSHAKE_REQUEST_ID, &mut stream)
+            .await?;
+
+        match ClientOpcodes::tokio_read(&mut stream).await? {
+            (_, ClientOpcodes::SubscribeSessionsReply { success: true }) => {}
+            // Not AuthenticationFailed, since the key may be given the permission again
+            (_, ClientOpcodes::SubscribeSessionsReply { success: false }) => {
+                return Err(MessageError::Io(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "session subscription was rejected",
+                )));
+            }
+            (_, reply) => return Err(MessageError::UnexpectedReply(reply.opcode())),
+        }
+    }
+
+    let online = state.lock().unwrap().online.clone();
+    for name in online {
+        ServerOpcodes::AccountOnline { name, online: true }
+            .tokio_write(HANDSHAKE_REQUEST_ID, &mut stream)
+            .await?;
+    }
+
+    Ok(stream)
+}
+
+/// Opens a Unix domain socket for addresses starting with [`UNIX_SOCKET_PREFIX`], and TCP otherwise.
+async fn open_stream(options: &ConnectionOptions) -> Result<Box<dyn Stream>, MessageError> {
+    if let Some(path) = options.address.strip_prefix(UNIX_SOCKET_PREFIX) {
+        #[cfg(unix)]
+        return Ok(Box::new(tokio::net::UnixStream::connect(path).await?));
+
+        #[cfg(not(unix))]
+        return Err(MessageError::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("unix domain sockets are not supported: '{path}'"),
+        )));
+    }
+
+    let stream = TcpStream::connect(&options.address).await?;
+
+    #[cfg(feature = "tls")]
+    if let Some((connector, server_name)) = &options.tls {
+        return Ok(Box::new(
+            connector.connect(server_name.clone(), stream).await?,
+        ));
+    }
+
+    Ok(Box::new(stream))
+}
+
+async fn authenticate(
+    stream: &mut Box<dyn Stream>,
+    name: &str,
+    key: &[u8],
+) -> Result<Permissions, MessageError> {
+    ServerOpcodes::RequestAuthChallenge
+        .tokio_write(HANDSHAKE_REQUEST_ID, &mut *stream)
+        .await?;
+
+    let server_challenge = match ClientOpcodes::tokio_read(&mut *stream).await? {
+        (_, ClientOpcodes::AuthChallenge { server_challenge }) => server_challenge,
+        (_, reply) => return Err(MessageError::UnexpectedReply(reply.opcode())),
+    };
+
+    let client_challenge = random_challenge();
+    let client_proof = client_proof(key, name, &server_challenge, &client_challenge)
+        .ok_or(MessageError::StringTooLong(name.len()))?;
+    ServerOpcodes::AuthProof {
+        name: name.to_string(),
+        client_challenge,
+        client_proof,
+    }
+    .tokio_write(HANDSHAKE_REQUEST_ID, &mut *stream)
+    .await?;
+
+    match ClientOpcodes::tokio_read(&mut *stream).await? {
+        (
+            _,
+            ClientOpcodes::AuthProofReply {
+                accepted: Some(accepted),
+            },
+        ) if verify_server_proof(
+            key,
+            name,
+            &server_challenge,
+            &client_challenge,
+            &accepted.server_proof,
+        ) =>
+        {
+            Ok(accepted.permissions)
+        }
+        (_, ClientOpcodes::AuthProofReply { .. }) => Err(MessageError::AuthenticationFailed),
+        (_, reply) => Err(MessageError::UnexpectedReply(reply.opcode())),
+    }
+}
+
+/// Serves requests and reconnects until every [`WarthogConnection`] has been dropped.
+async fn run(
+    options: ConnectionOptions,
+    mut stream: Box<dyn Stream>,
+    mut requests: mpsc::Receiver<Request>,
+    state: Arc<Mutex<State>>,
+) {
+    loop {
+        if serve(stream, &mut requests, &state).await.is_ok() {
+            return;
+        }
+
+        let mut backoff = MIN_BACKOFF;
+        stream = loop {
+            tokio::time::sleep(backoff).await;
+            // Every WarthogConnection has been dropped
+            if Arc::strong_count(&state) == 1 {
+                return;
+            }
+
+            match connect(&options, &state).await {
+                Ok(stream) => break stream,
+                // Retrying with the same key or realm will not work
+                Err(MessageError::AuthenticationFailed | MessageError::RegistrationRejected(_)) => {
+                    return
+                }
+                Err(_) => backoff = (backoff * 2).min(options.max_backoff),
+            }
+        };
+    }
+}
+
+/// Returns [`Ok`] when every [`WarthogConnection`] has been dropped, and [`Err`] if the connection was lost.
+///
+/// Requests that were sent but not answered are failed with [`MessageError::ConnectionClosed`].
+async fn serve(
+    stream: Box<dyn Stream>,
+    requests: &mut mpsc::Receiver<Request>,
+    state: &Mutex<State>,
+) -> Result<(), MessageError> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Reads are not cancellation safe, so they are done in a separate task
+    let (replies, mut receiver) = mpsc::channel(MAX_QUEUED_REQUESTS);
+    let reader = tokio::spawn(async move {
+        loop {
+            let reply = ClientOpcodes::tokio_read(&mut reader).await;
+            let failed = matches!(&reply, Err(e) if !matches!(e, MessageError::InvalidOpcode(_)));
+
+            if replies.send(reply).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let mut pending = HashMap::new();
+    let mut next_request_id = HANDSHAKE_REQUEST_ID.wrapping_add(1);
+
+    let result = loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(Request { mut message, reply }) = request else {
+                    break Ok(());
+                };
+
+                let request_id = next_request_id;
+                next_request_id = next_request_id.wrapping_add(1);
+                if next_request_id == HANDSHAKE_REQUEST_ID {
+                    next_request_id = HANDSHAKE_REQUEST_ID.wrapping_add(1);
+                }
+
+                if let Some(reply) = reply {
+                    // Requests that timed out are never answered if the reply server stopped responding
+                    pending.retain(|_, sender: &mut oneshot::Sender<_>| !sender.is_closed());
+                    pending.insert(request_id, reply);
+                }
+
+                if let Err(e) = message.tokio_write(request_id, &mut writer).await {
+                    break Err(e);
+                }
+            }
+            reply = receiver.recv() => {
+                match reply {
+                    Some(Ok((_, ClientOpcodes::SessionChanged { name, change }))) => {
+                        if let Some(sessions) = &state.lock().unwrap().sessions {
+                            // The subscriber may have stopped listening or fallen behind
+                            let _ = sessions.try_send((name, change));
+                        }
+                    }
+                    Some(Ok((_, ClientOpcodes::DisconnectAccount { name }))) => {
+                        let mut state = state.lock().unwrap();
+                        state.online.remove(&name);
+                        if let Some(disconnects) = &state.disconnects {
+                            let _ = disconnects.try_send(name);
+                        }
+                    }
+                    Some(Ok((_, ClientOpcodes::RequestCharacterAmount { name }))) => {
+                        if let Some(requests) = &state.lock().unwrap().character_amount_requests {
+                            let _ = requests.try_send(name);
+                        }
+                    }
+                    Some(Ok((request_id, reply))) => {
+                        if let Some(sender) = pending.remove(&request_id) {
+                            // The caller may have stopped waiting
+                            let _ = sender.send(reply);
+                        }
+                    }
+                    // Unknown messages from a newer auth server are skipped
+                    Some(Err(MessageError::InvalidOpcode(_))) => {}
+                    Some(Err(e)) => break Err(e),
+                    None => break Err(MessageError::ConnectionClosed),
+                }
+            }
+        }
+    };
+
+    reader.abort();
+
+    result
+}
@@ -1,3 +1,4 @@
+use crate::RegisterRealmError;
 use std::fmt::{Display, Formatter};
 use std::string::FromUtf8Error;
 
@@ -5,7 +6,42 @@ use std::string::FromUtf8Error;
 pub enum MessageError {
     Io(std::io::Error),
     InvalidOpcode(u8),
+    InvalidModification(u8),
+    InvalidSessionChange(u8),
     Utf8(FromUtf8Error),
+    /// Received frame is empty or larger than [`MAX_MESSAGE_SIZE`](crate::MAX_MESSAGE_SIZE).
+    InvalidMessageSize(u32),
+    /// Message to be sent is larger than [`MAX_MESSAGE_SIZE`](crate::MAX_MESSAGE_SIZE).
+    MessageTooLarge(usize),
+    /// String longer than 255 bytes.
+    StringTooLong(usize),
+    /// Byte array longer than 65535 bytes.
+    BytesTooLong(usize),
+    /// List longer than 255 entries.
+    ListTooLong(usize),
+    /// Connection did not start with the expected magic.
+    InvalidMagic([u8; 4]),
+    /// No protocol version supported by both sides.
+    UnsupportedVersion {
+        min: u16,
+        max: u16,
+    },
+    /// Reply server rejected the key.
+    AuthenticationFailed,
+    /// Reply server rejected the realm when registering it again after reconnecting.
+    RegistrationRejected(RegisterRealmError),
+    /// Reply has a different opcode than expected for the request.
+    UnexpectedReply(u8),
+    /// Connection was lost before the reply was received, or has been shut down.
+    ConnectionClosed,
+    /// Reply or handshake did not arrive in time.
+    TimedOut,
+    /// Realm category that does not exist.
+    InvalidRealmCategory(u8),
+    /// Realm type that does not exist.
+    InvalidRealmType(u8),
+    /// Realm address that is not `host:port`.
+    InvalidAddress(String),
 }
 
 impl Display for MessageError {
@@ -13,7 +49,40 @@ impl Display for MessageError {
         match self {
             MessageError::Io(e) => e.fmt(f),
             MessageError::InvalidOpcode(e) => write!(f, "invalid opcode received: {e}"),
+            MessageError::InvalidModification(e) => {
+                write!(f, "invalid modification received: {e}")
+            }
+            MessageError::InvalidSessionChange(e) => {
+                write!(f, "invalid session change received: {e}")
+            }
             MessageError::Utf8(e) => e.fmt(f),
+            MessageError::InvalidMessageSize(e) => write!(f, "invalid message size received: {e}"),
+            MessageError::MessageTooLarge(e) => {
+                write!(f, "message of {e} bytes is larger than the maximum")
+            }
+            MessageError::StringTooLong(e) => {
+                write!(f, "string of {e} bytes is longer than 255 bytes")
+            }
+            MessageError::BytesTooLong(e) => {
+                write!(f, "byte array of {e} bytes is longer than 65535 bytes")
+            }
+            MessageError::ListTooLong(e) => {
+                write!(f, "list of {e} entries is longer than 255 entries")
+            }
+            MessageError::InvalidMagic(e) => write!(f, "invalid magic received: {e:?}"),
+            MessageError::UnsupportedVersion { min, max } => {
+                write!(f, "no supported protocol version in {min}..={max}")
+            }
+            MessageError::AuthenticationFailed => write!(f, "authentication failed"),
+            MessageError::RegistrationRejected(e) => {
+                write!(f, "realm registration rejected: {e}")
+            }
+            MessageError::UnexpectedReply(e) => write!(f, "unexpected reply received: {e}"),
+            MessageError::ConnectionClosed => write!(f, "connection closed"),
+            MessageError::TimedOut => write!(f, "timed out"),
+            MessageError::InvalidRealmCategory(e) => write!(f, "invalid realm category: {e}"),
+            MessageError::InvalidRealmType(e) => write!(f, "invalid realm type: {e}"),
+            MessageError::InvalidAddress(e) => write!(f, "invalid realm address: '{e}'"),
         }
     }
 }
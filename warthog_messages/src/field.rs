@@ -0,0 +1,623 @@
+use crate::{
+    AccountSummary, AuthAccepted, MatrixCardData, MessageError, Modification, Permissions,
+    RealmFlags, RealmSummary, RegisterRealmError, SessionChange, SessionSummary,
+};
+
+/// Type that can be used as a field in [`messages!`].
+pub(crate) trait Field: Sized {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError>;
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError>;
+}
+
+impl Field for u8 {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_u8(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_u8(w, *self)?)
+    }
+}
+
+impl Field for u16 {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_u16(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_u16(w, *self)?)
+    }
+}
+
+impl Field for u32 {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_u32(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_u32(w, *self)?)
+    }
+}
+
+impl Field for u64 {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_u64(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_u64(w, *self)?)
+    }
+}
+
+/// Nothing is sent, for results without a value.
+impl Field for () {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(_: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(())
+    }
+
+    fn write(&self, _: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(())
+    }
+}
+
+impl Field for f32 {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_f32(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_f32(w, *self)?)
+    }
+}
+
+impl Field for bool {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(crate::read_bool(r)?)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        Ok(crate::write_bool(w, *self)?)
+    }
+}
+
+/// `u8` length followed by UTF-8.
+impl Field for String {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        crate::read_string(r)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        crate::write_string(w, self)
+    }
+}
+
+/// Without a length.
+impl<const N: usize> Field for [u8; N] {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        use std::io::Read;
+
+        let mut buf = [0_u8; N];
+        r.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        w.extend_from_slice(self);
+
+        Ok(())
+    }
+}
+
+/// `bool` followed by the value if it is `true`.
+impl<T: Field> Field for Option<T> {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(if bool::read(r)? {
+            Some(T::read(r)?)
+        } else {
+            None
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.is_some().write(w)?;
+        if let Some(value) = self {
+            value.write(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `u8` amount followed by the values.
+impl<T: Field> Field for Vec<T> {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        let amount = u8::read(r)?;
+
+        (0..amount).map(|_| T::read(r)).collect()
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        let amount: u8 = self
+            .len()
+            .try_into()
+            .map_err(|_| MessageError::ListTooLong(self.len()))?;
+        amount.write(w)?;
+
+        for value in self {
+            value.write(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `bool` that is `true` for [`Ok`], followed by the value.
+impl<T: Field, E: Field> Field for Result<T, E> {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(if bool::read(r)? {
+            Ok(T::read(r)?)
+        } else {
+            Err(E::read(r)?)
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.is_ok().write(w)?;
+        match self {
+            Ok(value) => value.write(w),
+            Err(e) => e.write(w),
+        }
+    }
+}
+
+/// `u8` tag followed by the value if it is set.
+impl<T: Field> Field for Modification<T> {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(match u8::read(r)? {
+            Modification::<T>::UNCHANGED => Modification::Unchanged,
+            Modification::<T>::REMOVED => Modification::Removed,
+            Modification::<T>::SET => Modification::Set(T::read(r)?),
+            v => return Err(MessageError::InvalidModification(v)),
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.tag().write(w)?;
+        if let Modification::Set(value) = self {
+            value.write(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Field for RealmFlags {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            flags: u8::read(r)?,
+            version_major: u8::read(r)?,
+            version_minor: u8::read(r)?,
+            version_patch: u8::read(r)?,
+            version_build: u16::read(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.flags.write(w)?;
+        self.version_major.write(w)?;
+        self.version_minor.write(w)?;
+        self.version_patch.write(w)?;
+        self.version_build.write(w)
+    }
+}
+
+/// `data` has a `u16` length.
+impl Field for MatrixCardData {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            challenge_count: u8::read(r)?,
+            data: crate::read_bytes(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.challenge_count.write(w)?;
+        crate::write_bytes(w, &self.data)
+    }
+}
+
+impl Field for Permissions {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self::new(u8::read(r)?))
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.as_int().write(w)
+    }
+}
+
+impl Field for AuthAccepted {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            permissions: Permissions::read(r)?,
+            server_proof: Field::read(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.permissions.write(w)?;
+        self.server_proof.write(w)
+    }
+}
+
+/// A missing reason is read as [`RegisterRealmError::Unknown`],
+/// since auth servers from before reasons were added do not send it.
+impl Field for RegisterRealmError {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        if r.is_empty() {
+            return Ok(Self::Unknown);
+        }
+
+        Ok(Self::from_int(u8::read(r)?))
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.as_int().write(w)
+    }
+}
+
+/// `u8` tag followed by the session key if the session was not revoked.
+impl Field for SessionChange {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(match u8::read(r)? {
+            Self::ADDED => Self::Added {
+                session_key: Field::read(r)?,
+            },
+            Self::REFRESHED => Self::Refreshed {
+                session_key: Field::read(r)?,
+            },
+            Self::REVOKED => Self::Revoked,
+            v => return Err(MessageError::InvalidSessionChange(v)),
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.tag().write(w)?;
+        match self.session_key() {
+            Some(session_key) => session_key.write(w),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Field for AccountSummary {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            name: String::read(r)?,
+            account_flags: u32::read(r)?,
+            has_pin: bool::read(r)?,
+            has_matrix_card: bool::read(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.name.write(w)?;
+        self.account_flags.write(w)?;
+        self.has_pin.write(w)?;
+        self.has_matrix_card.write(w)
+    }
+}
+
+impl Field for SessionSummary {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            name: String::read(r)?,
+            address: String::read(r)?,
+            login_time: u64::read(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.name.write(w)?;
+        self.address.write(w)?;
+        self.login_time.write(w)
+    }
+}
+
+impl Field for RealmSummary {
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    fn read(r: &mut &[u8]) -> Result<Self, MessageError> {
+        Ok(Self {
+            realm_id: u8::read(r)?,
+            name: String::read(r)?,
+            address: String::read(r)?,
+            population: f32::read(r)?,
+            flags: u8::read(r)?,
+            category: u8::read(r)?,
+            realm_type: u8::read(r)?,
+            online: bool::read(r)?,
+            peer_address: String::read(r)?,
+        })
+    }
+
+    fn write(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+        self.realm_id.write(w)?;
+        self.name.write(w)?;
+        self.address.write(w)?;
+        self.population.write(w)?;
+        self.flags.write(w)?;
+        self.category.write(w)?;
+        self.realm_type.write(w)?;
+        self.online.write(w)?;
+        self.peer_address.write(w)
+    }
+}
+
+/// Generates values of a [`Field`] for the tests.
+#[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+pub(crate) trait FieldStrategy: Field {
+    fn strategy() -> proptest::strategy::BoxedStrategy<Self>;
+
+    /// Bytes at the start of the written field that must be present for it to be read.
+    fn required_length(&self) -> usize {
+        written_length(self)
+    }
+}
+
+#[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+pub(crate) fn written_length<T: Field>(field: &T) -> usize {
+    let mut w = Vec::new();
+    field.write(&mut w).unwrap();
+
+    w.len()
+}
+
+#[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+mod strategies {
+    use super::FieldStrategy;
+    use crate::{
+        AccountSummary, AuthAccepted, MatrixCardData, Modification, Permissions, RealmFlags,
+        RealmSummary, RegisterRealmError, SessionChange, SessionSummary,
+    };
+    use proptest::prelude::*;
+
+    macro_rules! any_strategy {
+        ($($ty:ty),*) => {
+            $(
+                impl FieldStrategy for $ty {
+                    fn strategy() -> BoxedStrategy<Self> {
+                        any::<$ty>().boxed()
+                    }
+                }
+            )*
+        };
+    }
+
+    any_strategy!(u8, u16, u32, u64, bool);
+
+    impl FieldStrategy for () {
+        fn strategy() -> BoxedStrategy<Self> {
+            Just(()).boxed()
+        }
+    }
+
+    /// Without NaN, since it is not equal to itself.
+    impl FieldStrategy for f32 {
+        fn strategy() -> BoxedStrategy<Self> {
+            use proptest::num::f32::{INFINITE, NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+
+            (POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO | INFINITE).boxed()
+        }
+    }
+
+    /// At most 255 bytes.
+    impl FieldStrategy for String {
+        fn strategy() -> BoxedStrategy<Self> {
+            prop_oneof!["\\PC{0,63}", "[a-zA-Z0-9 ]{0,255}"].boxed()
+        }
+    }
+
+    impl<const N: usize> FieldStrategy for [u8; N] {
+        fn strategy() -> BoxedStrategy<Self> {
+            proptest::collection::vec(any::<u8>(), N)
+                .prop_map(|v| v.try_into().unwrap())
+                .boxed()
+        }
+    }
+
+    impl<T: FieldStrategy + std::fmt::Debug + 'static> FieldStrategy for Option<T> {
+        fn strategy() -> BoxedStrategy<Self> {
+            proptest::option::of(T::strategy()).boxed()
+        }
+    }
+
+    /// Short enough to fit in a single message.
+    impl<T: FieldStrategy + std::fmt::Debug + 'static> FieldStrategy for Vec<T> {
+        fn strategy() -> BoxedStrategy<Self> {
+            proptest::collection::vec(T::strategy(), 0..4).boxed()
+        }
+    }
+
+    impl<T, E> FieldStrategy for Result<T, E>
+    where
+        T: FieldStrategy + std::fmt::Debug + 'static,
+        E: FieldStrategy + std::fmt::Debug + 'static,
+    {
+        fn strategy() -> BoxedStrategy<Self> {
+            prop_oneof![T::strategy().prop_map(Ok), E::strategy().prop_map(Err)].boxed()
+        }
+
+        fn required_length(&self) -> usize {
+            1 + match self {
+                Ok(value) => value.required_length(),
+                Err(e) => e.required_length(),
+            }
+        }
+    }
+
+    impl<T: FieldStrategy + Clone + std::fmt::Debug + 'static> FieldStrategy for Modification<T> {
+        fn strategy() -> BoxedStrategy<Self> {
+            prop_oneof![
+                Just(Modification::Unchanged),
+                Just(Modification::Removed),
+                T::strategy().prop_map(Modification::Set),
+            ]
+            .boxed()
+        }
+    }
+
+    impl FieldStrategy for RealmFlags {
+        fn strategy() -> BoxedStrategy<Self> {
+            any::<(u8, u8, u8, u8, u16)>()
+                .prop_map(
+                    |(flags, version_major, version_minor, version_patch, version_build)| {
+                        RealmFlags {
+                            flags,
+                            version_major,
+                            version_minor,
+                            version_patch,
+                            version_build,
+                        }
+                    },
+                )
+                .boxed()
+        }
+    }
+
+    /// Small enough to fit in a single message.
+    impl FieldStrategy for MatrixCardData {
+        fn strategy() -> BoxedStrategy<Self> {
+            (any::<u8>(), proptest::collection::vec(any::<u8>(), 0..1024))
+                .prop_map(|(challenge_count, data)| MatrixCardData {
+                    challenge_count,
+                    data,
+                })
+                .boxed()
+        }
+    }
+
+    impl FieldStrategy for Permissions {
+        fn strategy() -> BoxedStrategy<Self> {
+            any::<u8>().prop_map(Permissions::new).boxed()
+        }
+    }
+
+    impl FieldStrategy for AuthAccepted {
+        fn strategy() -> BoxedStrategy<Self> {
+            (
+                Permissions::strategy(),
+                <[u8; crate::PROOF_LENGTH]>::strategy(),
+            )
+                .prop_map(|(permissions, server_proof)| AuthAccepted {
+                    permissions,
+                    server_proof,
+                })
+                .boxed()
+        }
+    }
+
+    impl FieldStrategy for SessionChange {
+        fn strategy() -> BoxedStrategy<Self> {
+            prop_oneof![
+                <[u8; 40]>::strategy().prop_map(|session_key| SessionChange::Added { session_key }),
+                <[u8; 40]>::strategy()
+                    .prop_map(|session_key| SessionChange::Refreshed { session_key }),
+                Just(SessionChange::Revoked),
+            ]
+            .boxed()
+        }
+    }
+
+    impl FieldStrategy for AccountSummary {
+        fn strategy() -> BoxedStrategy<Self> {
+            (String::strategy(), any::<(u32, bool, bool)>())
+                .prop_map(
+                    |(name, (account_flags, has_pin, has_matrix_card))| AccountSummary {
+                        name,
+                        account_flags,
+                        has_pin,
+                        has_matrix_card,
+                    },
+                )
+                .boxed()
+        }
+    }
+
+    impl FieldStrategy for SessionSummary {
+        fn strategy() -> BoxedStrategy<Self> {
+            (String::strategy(), String::strategy(), any::<u64>())
+                .prop_map(|(name, address, login_time)| SessionSummary {
+                    name,
+                    address,
+                    login_time,
+                })
+                .boxed()
+        }
+    }
+
+    impl FieldStrategy for RealmSummary {
+        fn strategy() -> BoxedStrategy<Self> {
+            (
+                (any::<u8>(), String::strategy(), String::strategy()),
+                f32::strategy(),
+                any::<(u8, u8, u8, bool)>(),
+                String::strategy(),
+            )
+                .prop_map(
+                    |(
+                        (realm_id, name, address),
+                        population,
+                        (flags, category, realm_type, online),
+                        peer_address,
+                    )| RealmSummary {
+                        realm_id,
+                        name,
+                        address,
+                        population,
+                        flags,
+                        category,
+                        realm_type,
+                        online,
+                        peer_address,
+                    },
+                )
+                .boxed()
+        }
+    }
+
+    /// Only reasons that exist, since others are read as [`RegisterRealmError::Unknown`].
+    impl FieldStrategy for RegisterRealmError {
+        fn strategy() -> BoxedStrategy<Self> {
+            (0..=6_u8).prop_map(RegisterRealmError::from_int).boxed()
+        }
+
+        /// Missing reasons are read as [`RegisterRealmError::Unknown`].
+        fn required_length(&self) -> usize {
+            0
+        }
+    }
+}
@@ -1,21 +1,133 @@
+#[macro_use]
+mod macros;
+
+mod auth;
 mod client;
+#[cfg(feature = "tokio")]
+mod connection;
 mod error;
+mod field;
 mod server;
+#[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+mod test;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod version;
 
+pub use auth::*;
 pub use client::*;
+#[cfg(feature = "tokio")]
+pub use connection::*;
 pub use error::*;
 pub use server::*;
+pub use version::*;
+
+/// Maximum size of the opcode, request ID and body of a single message.
+pub const MAX_MESSAGE_SIZE: u32 = 16 * 1024;
+
+/// Size of the opcode and, from [`REQUEST_ID_VERSION`], the request ID.
+const fn frame_header_size(version: u16) -> u32 {
+    if version >= REQUEST_ID_VERSION {
+        5
+    } else {
+        1
+    }
+}
 
+/// Opcode, request ID and body of a frame.
+#[cfg(any(feature = "sync", feature = "tokio"))]
+type Frame = (u8, u32, Vec<u8>);
+
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn verify_frame_size(size: u32, version: u16) -> Result<usize, MessageError> {
+    if !(frame_header_size(version)..=MAX_MESSAGE_SIZE).contains(&size) {
+        return Err(MessageError::InvalidMessageSize(size));
+    }
+
+    Ok(size as usize)
+}
+
+/// Request ID of frames from versions before [`REQUEST_ID_VERSION`].
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn read_request_id<R: std::io::Read>(r: R, version: u16) -> std::io::Result<u32> {
+    if version >= REQUEST_ID_VERSION {
+        read_u32(r)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Reads the `u32` size of a frame, followed by the rest of the frame.
+///
+/// Frames before [`REQUEST_ID_VERSION`] have no request ID, so it is always 0.
 #[cfg(feature = "sync")]
+fn read_frame<R: std::io::Read>(mut r: R, version: u16) -> Result<Frame, MessageError> {
+    let size = verify_frame_size(read_u32(&mut r)?, version)?;
+
+    let opcode = read_u8(&mut r)?;
+    let request_id = read_request_id(&mut r, version)?;
+    let mut body = vec![0_u8; size - frame_header_size(version) as usize];
+    r.read_exact(&mut body)?;
+
+    Ok((opcode, request_id, body))
+}
+
+#[cfg(feature = "tokio")]
+async fn read_frame_tokio<R: tokio::io::AsyncReadExt + Unpin>(
+    mut r: R,
+    version: u16,
+) -> Result<Frame, MessageError> {
+    let mut size = [0_u8; 4];
+    r.read_exact(&mut size).await?;
+    let size = verify_frame_size(u32::from_le_bytes(size), version)?;
+
+    let mut frame = vec![0_u8; size];
+    r.read_exact(&mut frame).await?;
+    let body = frame.split_off(frame_header_size(version) as usize);
+
+    let mut header = frame.as_slice();
+    let opcode = read_u8(&mut header)?;
+    let request_id = read_request_id(&mut header, version)?;
+
+    Ok((opcode, request_id, body))
+}
+
+/// `request_id` is not written before [`REQUEST_ID_VERSION`].
+fn write_frame<W: std::io::Write>(
+    mut w: W,
+    version: u16,
+    opcode: u8,
+    request_id: u32,
+    body: &[u8],
+) -> Result<(), MessageError> {
+    let size = body.len() + frame_header_size(version) as usize;
+    if size > MAX_MESSAGE_SIZE as usize {
+        return Err(MessageError::MessageTooLarge(size));
+    }
+
+    let mut frame = Vec::with_capacity(size + 4);
+    write_u32(&mut frame, size as u32)?;
+    write_u8(&mut frame, opcode)?;
+    if version >= REQUEST_ID_VERSION {
+        write_u32(&mut frame, request_id)?;
+    }
+    frame.extend_from_slice(body);
+
+    w.write_all(&frame)?;
+
+    Ok(())
+}
+
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_bool<R: std::io::Read>(r: R) -> std::io::Result<bool> {
-    Ok(if read_u8(r)? == 1 { true } else { false })
+    Ok(read_u8(r)? == 1)
 }
 
 fn write_bool<W: std::io::Write>(w: W, value: bool) -> std::io::Result<()> {
-    write_u8(w, if value { 1 } else { 0 })
+    write_u8(w, u8::from(value))
 }
 
-#[cfg(feature = "sync")]
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_u8<R: std::io::Read>(mut r: R) -> std::io::Result<u8> {
     let mut buf = [0_u8; 1];
     r.read_exact(&mut buf)?;
@@ -28,12 +140,12 @@ fn write_u8<W: std::io::Write>(mut w: W, value: u8) -> std::io::Result<()> {
     w.write_all(&buf)
 }
 
-#[cfg(feature = "sync")]
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_u16<R: std::io::Read>(mut r: R) -> std::io::Result<u16> {
     let mut buf = [0_u8; 2];
     r.read_exact(&mut buf)?;
 
-    Ok(buf[0] as u16 | (buf[1] as u16) << 8)
+    Ok(u16::from_le_bytes(buf))
 }
 
 fn write_u16<W: std::io::Write>(mut w: W, value: u16) -> std::io::Result<()> {
@@ -41,12 +153,12 @@ fn write_u16<W: std::io::Write>(mut w: W, value: u16) -> std::io::Result<()> {
     w.write_all(&buf)
 }
 
-#[cfg(feature = "sync")]
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_u32<R: std::io::Read>(mut r: R) -> std::io::Result<u32> {
     let mut buf = [0_u8; 4];
     r.read_exact(&mut buf)?;
 
-    Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
+    Ok(u32::from_le_bytes(buf))
 }
 
 fn write_u32<W: std::io::Write>(mut w: W, value: u32) -> std::io::Result<()> {
@@ -54,17 +166,29 @@ fn write_u32<W: std::io::Write>(mut w: W, value: u32) -> std::io::Result<()> {
     w.write_all(&buf)
 }
 
-#[cfg(feature = "sync")]
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn read_u64<R: std::io::Read>(mut r: R) -> std::io::Result<u64> {
+    let mut buf = [0_u8; 8];
+    r.read_exact(&mut buf)?;
+
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_u64<W: std::io::Write>(mut w: W, value: u64) -> std::io::Result<()> {
+    let buf = value.to_le_bytes();
+    w.write_all(&buf)
+}
+
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_f32<R: std::io::Read>(r: R) -> std::io::Result<f32> {
-    let f = read_u32(r)?.to_le_bytes();
-    Ok(f32::from_le_bytes(f))
+    Ok(f32::from_bits(read_u32(r)?))
 }
 
 fn write_f32<W: std::io::Write>(w: W, value: f32) -> std::io::Result<()> {
-    Ok(write_u32(w, u32::from_le_bytes(value.to_le_bytes()))?)
+    write_u32(w, value.to_bits())
 }
 
-#[cfg(feature = "sync")]
+#[cfg(any(feature = "sync", feature = "tokio"))]
 fn read_string<R: std::io::Read>(mut r: R) -> Result<String, MessageError> {
     let length = read_u8(&mut r)?;
     let mut buf = vec![0_u8; length.into()];
@@ -75,64 +199,30 @@ fn read_string<R: std::io::Read>(mut r: R) -> Result<String, MessageError> {
     Ok(s)
 }
 
-fn write_string<W: std::io::Write>(mut w: W, value: &str) -> std::io::Result<()> {
-    write_u8(&mut w, value.len() as u8)?;
+/// Strings longer than 255 bytes are rejected instead of being truncated.
+fn write_string<W: std::io::Write>(mut w: W, value: &str) -> Result<(), MessageError> {
+    let length = u8::try_from(value.len()).map_err(|_| MessageError::StringTooLong(value.len()))?;
 
-    for b in value.as_bytes() {
-        write_u8(&mut w, *b)?;
-    }
+    write_u8(&mut w, length)?;
+    w.write_all(value.as_bytes())?;
 
     Ok(())
 }
 
-#[cfg(feature = "tokio")]
-async fn read_bool_tokio<R: tokio::io::AsyncReadExt + Unpin>(r: R) -> std::io::Result<bool> {
-    Ok(if read_u8_tokio(r).await? == 1 {
-        true
-    } else {
-        false
-    })
-}
-
-#[cfg(feature = "tokio")]
-async fn read_u8_tokio<R: tokio::io::AsyncReadExt + Unpin>(mut r: R) -> std::io::Result<u8> {
-    let mut buf = [0_u8; 1];
-    r.read_exact(&mut buf).await?;
-
-    Ok(buf[0])
-}
-
-#[cfg(feature = "tokio")]
-async fn read_u16_tokio<R: tokio::io::AsyncReadExt + Unpin>(mut r: R) -> std::io::Result<u16> {
-    let mut buf = [0_u8; 2];
-    r.read_exact(&mut buf).await?;
-
-    Ok(buf[0] as u16 | (buf[1] as u16) << 8)
-}
-
-#[cfg(feature = "tokio")]
-async fn read_u32_tokio<R: tokio::io::AsyncReadExt + Unpin>(mut r: R) -> std::io::Result<u32> {
-    let mut buf = [0_u8; 4];
-    r.read_exact(&mut buf).await?;
-
-    Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
-}
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn read_bytes<R: std::io::Read>(mut r: R) -> std::io::Result<Vec<u8>> {
+    let length = read_u16(&mut r)?;
+    let mut buf = vec![0_u8; length.into()];
+    r.read_exact(&mut buf)?;
 
-#[cfg(feature = "tokio")]
-async fn read_f32_tokio<R: tokio::io::AsyncReadExt + Unpin>(r: R) -> std::io::Result<f32> {
-    let f = read_u32_tokio(r).await?.to_le_bytes();
-    Ok(f32::from_le_bytes(f))
+    Ok(buf)
 }
 
-#[cfg(feature = "tokio")]
-async fn read_string_tokio<R: tokio::io::AsyncReadExt + Unpin>(
-    mut r: R,
-) -> Result<String, MessageError> {
-    let length = read_u8_tokio(&mut r).await?;
-    let mut buf = vec![0_u8; length.into()];
-    r.read_exact(&mut buf).await?;
+fn write_bytes<W: std::io::Write>(mut w: W, value: &[u8]) -> Result<(), MessageError> {
+    let length = u16::try_from(value.len()).map_err(|_| MessageError::BytesTooLong(value.len()))?;
 
-    let s = String::from_utf8(buf)?;
+    write_u16(&mut w, length)?;
+    w.write_all(value)?;
 
-    Ok(s)
+    Ok(())
 }
@@ -0,0 +1,317 @@
+/// Defines an opcode enum together with its codecs.
+///
+/// Every variant is given its opcode and fields once, and the fields are read and written in order through
+/// [`Field`](crate::field::Field).
+/// This generates the enum, `read`, `write`, `tokio_read` and `tokio_write`,
+/// as well as property tests that round trip every variant through both codecs.
+///
+/// Fields added to the end of existing messages are given a default with `field: Type = default`,
+/// which is used when the message ends before the field.
+macro_rules! messages {
+    (@strategy $variant:ident) => {
+        proptest::strategy::Just(()).prop_map(|()| Self::$variant {}).boxed()
+    };
+    (@strategy $variant:ident { $($field:ident : $ty:ty),* }) => {
+        ($(<$ty as crate::field::FieldStrategy>::strategy(),)*)
+            .prop_map(|($($field,)*)| Self::$variant { $($field),* })
+            .boxed()
+    };
+    (@required $required:ident, $length:ident, $field:ident) => {
+        $required = $length + crate::field::FieldStrategy::required_length($field);
+        $length += crate::field::written_length($field);
+    };
+    (@required $required:ident, $length:ident, $field:ident, $default:expr) => {
+        $length += crate::field::written_length($field);
+    };
+    (@read $r:ident, $ty:ty) => {
+        <$ty as Field>::read(&mut $r)?
+    };
+    (@read $r:ident, $ty:ty, $default:expr) => {
+        if $r.is_empty() {
+            $default
+        } else {
+            <$ty as Field>::read(&mut $r)?
+        }
+    };
+    (
+        $(#[$enum_meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $opcode:literal => $variant:ident $({
+                    $(
+                        $(#[$field_meta:meta])*
+                        $field:ident : $ty:ty $(= $default:expr)?
+                    ),* $(,)?
+                })?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$enum_meta])*
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant $({
+                    $(
+                        $(#[$field_meta])*
+                        $field: $ty,
+                    )*
+                })?,
+            )*
+        }
+
+        impl $name {
+            pub(crate) const fn opcode(&self) -> u8 {
+                match self {
+                    $(Self::$variant { .. } => $opcode,)*
+                }
+            }
+
+            /// Reads a message from a single frame.
+            ///
+            /// Bytes after the known fields are ignored, so that newer versions can add fields to the end.
+            #[cfg(any(feature = "sync", feature = "tokio"))]
+            fn read_body(opcode: u8, mut r: &[u8]) -> Result<Self, MessageError> {
+                #[allow(unused_imports)]
+                use crate::field::Field;
+
+                Ok(match opcode {
+                    $(
+                        $opcode => Self::$variant {
+                            $($($field: messages!(@read r, $ty $(, $default)?),)*)?
+                        },
+                    )*
+                    v => return Err(MessageError::InvalidOpcode(v)),
+                })
+            }
+
+            fn write_body(&self, w: &mut Vec<u8>) -> Result<(), MessageError> {
+                #[allow(unused_imports)]
+                use crate::field::Field;
+
+                match self {
+                    $(
+                        Self::$variant { $($($field,)*)? } => {
+                            $($(Field::write($field, w)?;)*)?
+                        }
+                    )*
+                }
+
+                Ok(())
+            }
+
+            #[cfg(feature = "sync")]
+            /// Returns the request ID together with the message.
+            pub fn read<R: std::io::Read>(r: R) -> Result<(u32, Self), MessageError> {
+                Self::read_with_version(r, crate::PROTOCOL_VERSION)
+            }
+
+            #[cfg(feature = "sync")]
+            /// Reads a frame of the negotiated protocol `version`.
+            ///
+            /// The request ID is 0 before [`REQUEST_ID_VERSION`](crate::REQUEST_ID_VERSION).
+            pub fn read_with_version<R: std::io::Read>(
+                r: R,
+                version: u16,
+            ) -> Result<(u32, Self), MessageError> {
+                let (opcode, request_id, body) = crate::read_frame(r, version)?;
+
+                Ok((request_id, Self::read_body(opcode, &body)?))
+            }
+
+            pub fn write<W: std::io::Write>(
+                &mut self,
+                request_id: u32,
+                w: W,
+            ) -> Result<(), MessageError> {
+                self.write_with_version(crate::PROTOCOL_VERSION, request_id, w)
+            }
+
+            /// Writes a frame of the negotiated protocol `version`.
+            ///
+            /// `request_id` is left out before [`REQUEST_ID_VERSION`](crate::REQUEST_ID_VERSION).
+            pub fn write_with_version<W: std::io::Write>(
+                &mut self,
+                version: u16,
+                request_id: u32,
+                w: W,
+            ) -> Result<(), MessageError> {
+                let mut body = Vec::new();
+                self.write_body(&mut body)?;
+
+                crate::write_frame(w, version, self.opcode(), request_id, &body)
+            }
+
+            #[cfg(feature = "tokio")]
+            /// Returns the request ID together with the message.
+            pub async fn tokio_read<R: tokio::io::AsyncReadExt + Unpin>(
+                r: R,
+            ) -> Result<(u32, Self), MessageError> {
+                Self::tokio_read_with_version(r, crate::PROTOCOL_VERSION).await
+            }
+
+            #[cfg(feature = "tokio")]
+            /// Reads a frame of the negotiated protocol `version`.
+            ///
+            /// The request ID is 0 before [`REQUEST_ID_VERSION`](crate::REQUEST_ID_VERSION).
+            pub async fn tokio_read_with_version<R: tokio::io::AsyncReadExt + Unpin>(
+                r: R,
+                version: u16,
+            ) -> Result<(u32, Self), MessageError> {
+                let (opcode, request_id, body) = crate::read_frame_tokio(r, version).await?;
+
+                Ok((request_id, Self::read_body(opcode, &body)?))
+            }
+
+            #[cfg(feature = "tokio")]
+            pub async fn tokio_write<W: tokio::io::AsyncWriteExt + Unpin>(
+                &mut self,
+                request_id: u32,
+                w: W,
+            ) -> Result<(), MessageError> {
+                self.tokio_write_with_version(crate::PROTOCOL_VERSION, request_id, w)
+                    .await
+            }
+
+            #[cfg(feature = "tokio")]
+            /// Writes a frame of the negotiated protocol `version`.
+            ///
+            /// `request_id` is left out before [`REQUEST_ID_VERSION`](crate::REQUEST_ID_VERSION).
+            pub async fn tokio_write_with_version<W: tokio::io::AsyncWriteExt + Unpin>(
+                &mut self,
+                version: u16,
+                request_id: u32,
+                mut w: W,
+            ) -> Result<(), MessageError> {
+                let mut v = Vec::new();
+                self.write_with_version(version, request_id, &mut v)?;
+                w.write_all(&v).await?;
+
+                Ok(())
+            }
+
+            /// Opcode and name of every variant.
+            #[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+            pub(crate) const NAMES: &'static [(u8, &'static str)] =
+                &[$(($opcode, stringify!($variant)),)*];
+
+            /// Length of the body up to the end of the last field without a default.
+            #[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+            #[allow(unused_mut, unused_assignments)]
+            pub(crate) fn required_length(&self) -> usize {
+                let (mut required, mut length) = (0, 0);
+
+                match self {
+                    $(
+                        Self::$variant { $($($field,)*)? } => {
+                            $($(messages!(@required required, length, $field $(, $default)?);)*)?
+                        }
+                    )*
+                }
+
+                required
+            }
+
+            /// Generates every variant.
+            #[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+            pub(crate) fn strategy() -> proptest::strategy::BoxedStrategy<Self> {
+                use proptest::strategy::Strategy;
+
+                proptest::strategy::Union::new([
+                    $(messages!(@strategy $variant $({ $($field: $ty),* })?),)*
+                ])
+                .boxed()
+            }
+        }
+
+        #[cfg(all(test, any(feature = "sync", feature = "tokio")))]
+        mod generated_tests {
+            use super::$name;
+            use proptest::prelude::*;
+
+            proptest! {
+                #[cfg(feature = "sync")]
+                #[test]
+                fn sync_round_trip(mut message in $name::strategy(), request_id: u32) {
+                    let mut frame = Vec::new();
+                    message.write(request_id, &mut frame).unwrap();
+
+                    let (read_request_id, read) = $name::read(frame.as_slice()).unwrap();
+                    prop_assert_eq!(read_request_id, request_id);
+                    prop_assert_eq!(read, message);
+                }
+
+                #[cfg(feature = "tokio")]
+                #[test]
+                fn tokio_round_trip(mut message in $name::strategy(), request_id: u32) {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+                    let mut frame = Vec::new();
+                    runtime.block_on(message.tokio_write(request_id, &mut frame)).unwrap();
+
+                    let (read_request_id, read) =
+                        runtime.block_on($name::tokio_read(frame.as_slice())).unwrap();
+                    prop_assert_eq!(read_request_id, request_id);
+                    prop_assert_eq!(read, message);
+                }
+
+                #[cfg(feature = "tokio")]
+                #[test]
+                fn tokio_round_trip_without_request_id(
+                    mut message in $name::strategy(),
+                    request_id: u32,
+                ) {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    let version = crate::MIN_PROTOCOL_VERSION;
+
+                    let mut frame = Vec::new();
+                    runtime
+                        .block_on(message.tokio_write_with_version(version, request_id, &mut frame))
+                        .unwrap();
+
+                    let (read_request_id, read) = runtime
+                        .block_on($name::tokio_read_with_version(frame.as_slice(), version))
+                        .unwrap();
+                    prop_assert_eq!(read_request_id, 0);
+                    prop_assert_eq!(read, message);
+                }
+
+                /// The frame size matches the truncated body, so only reading the fields can fail.
+                #[test]
+                fn truncated_frame_is_error(
+                    message in $name::strategy(),
+                    length in any::<prop::sample::Index>(),
+                ) {
+                    let required = message.required_length();
+                    if required == 0 {
+                        return Ok(());
+                    }
+
+                    let mut body = Vec::new();
+                    message.write_body(&mut body).unwrap();
+                    body.truncate(length.index(required));
+
+                    let mut frame = Vec::new();
+                    crate::write_frame(
+                        &mut frame,
+                        crate::PROTOCOL_VERSION,
+                        message.opcode(),
+                        0,
+                        &body,
+                    )
+                    .unwrap();
+                    let frame = frame.as_slice();
+
+                    #[cfg(feature = "sync")]
+                    prop_assert!($name::read(frame).is_err());
+
+                    #[cfg(feature = "tokio")]
+                    {
+                        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                        prop_assert!(runtime.block_on($name::tokio_read(frame)).is_err());
+                    }
+                }
+            }
+        }
+    };
+}
@@ -1,245 +1,152 @@
 use crate::error::MessageError;
-
-pub enum ServerOpcodes {
-    RequestSessionKey {
-        name: String,
-    },
-    RegisterRealm {
-        name: String,
-        address: String,
-        population: f32,
-        locked: bool,
-        flags: u8,
-        category: u8,
-        realm_type: u8,
-        version_major: u8,
-        version_minor: u8,
-        version_patch: u8,
-        version_build: u16,
-    },
-    AddUser {
-        name: String,
-        password: String,
-    },
-    RemoveUser {
-        name: String,
-    },
-    ModifyUser {
-        name: String,
-    },
+use crate::{CHALLENGE_LENGTH, PROOF_LENGTH};
+
+/// Length of the SRP salt in [`ServerOpcodes::AddUserWithVerifier`].
+pub const SALT_LENGTH: usize = 32;
+/// Length of the SRP password verifier in [`ServerOpcodes::AddUserWithVerifier`].
+pub const PASSWORD_VERIFIER_LENGTH: usize = 32;
+
+/// Maximum `limit` of list requests, so that every page fits in a single message.
+pub const MAX_LIST_LIMIT: u8 = 16;
+
+/// Change to a value that can also be removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Modification<T> {
+    #[default]
+    Unchanged,
+    Removed,
+    Set(T),
 }
 
-impl ServerOpcodes {
-    const REQUEST_SESSION_KEY_OPCODE: u8 = 0;
-    const REGISTER_REALM_OPCODE: u8 = 4;
-    const ADD_USER_OPCODE: u8 = 6;
-    const REMOVE_USER_OPCODE: u8 = 8;
-    const MODIFY_USER_OPCODE: u8 = 10;
+impl<T> Modification<T> {
+    pub(crate) const UNCHANGED: u8 = 0;
+    pub(crate) const REMOVED: u8 = 1;
+    pub(crate) const SET: u8 = 2;
 
-    const fn opcode(&self) -> u8 {
+    pub(crate) const fn tag(&self) -> u8 {
         match self {
-            ServerOpcodes::RequestSessionKey { .. } => Self::REQUEST_SESSION_KEY_OPCODE,
-            ServerOpcodes::RegisterRealm { .. } => Self::REGISTER_REALM_OPCODE,
-            ServerOpcodes::AddUser { .. } => Self::ADD_USER_OPCODE,
-            ServerOpcodes::RemoveUser { .. } => Self::REMOVE_USER_OPCODE,
-            ServerOpcodes::ModifyUser { .. } => Self::MODIFY_USER_OPCODE,
-        }
-    }
-
-    #[cfg(feature = "sync")]
-    pub fn read<R: std::io::Read>(mut r: R) -> Result<Self, MessageError> {
-        let mut opcode = [0_u8; 1];
-        r.read_exact(&mut opcode)?;
-
-        Ok(match opcode[0] {
-            Self::REQUEST_SESSION_KEY_OPCODE => {
-                let name = crate::read_string(r)?;
-
-                Self::RequestSessionKey { name }
-            }
-            Self::REGISTER_REALM_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-
-                let address = crate::read_string(&mut r)?;
-
-                let population = crate::read_f32(&mut r)?;
-
-                let locked = crate::read_bool(&mut r)?;
-
-                let flags = crate::read_u8(&mut r)?;
-
-                let category = crate::read_u8(&mut r)?;
-
-                let realm_type = crate::read_u8(&mut r)?;
-
-                let version_major = crate::read_u8(&mut r)?;
-                let version_minor = crate::read_u8(&mut r)?;
-                let version_patch = crate::read_u8(&mut r)?;
-                let version_build = crate::read_u16(&mut r)?;
-
-                Self::RegisterRealm {
-                    name,
-                    address,
-                    population,
-                    locked,
-                    flags,
-                    category,
-                    realm_type,
-                    version_major,
-                    version_minor,
-                    version_patch,
-                    version_build,
-                }
-            }
-            Self::ADD_USER_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-                let password = crate::read_string(&mut r)?;
-
-                Self::AddUser { name, password }
-            }
-            Self::REMOVE_USER_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-
-                Self::RemoveUser { name }
-            }
-            Self::MODIFY_USER_OPCODE => {
-                let name = crate::read_string(&mut r)?;
-
-                Self::ModifyUser { name }
-            }
-            v => return Err(MessageError::InvalidOpcode(v)),
-        })
-    }
-
-    pub fn write<W: std::io::Write>(&mut self, mut w: W) -> std::io::Result<()> {
-        crate::write_u8(&mut w, self.opcode())?;
-
-        match self {
-            ServerOpcodes::RequestSessionKey { name } => {
-                crate::write_string(&mut w, &name)?;
-            }
-            ServerOpcodes::RegisterRealm {
-                name,
-                address,
-                population,
-                locked,
-                flags,
-                category,
-                realm_type,
-                version_major,
-                version_minor,
-                version_patch,
-                version_build,
-            } => {
-                crate::write_string(&mut w, name)?;
-
-                crate::write_string(&mut w, address)?;
-
-                crate::write_f32(&mut w, *population)?;
-
-                crate::write_bool(&mut w, *locked)?;
-
-                crate::write_u8(&mut w, *flags)?;
-
-                crate::write_u8(&mut w, *category)?;
-
-                crate::write_u8(&mut w, *realm_type)?;
-
-                crate::write_u8(&mut w, *version_major)?;
-                crate::write_u8(&mut w, *version_minor)?;
-                crate::write_u8(&mut w, *version_patch)?;
-                crate::write_u16(&mut w, *version_build)?;
-            }
-            ServerOpcodes::AddUser { name, password } => {
-                crate::write_string(&mut w, name)?;
-
-                crate::write_string(&mut w, password)?;
-            }
-            ServerOpcodes::RemoveUser { name } => {
-                crate::write_string(&mut w, name)?;
-            }
-            ServerOpcodes::ModifyUser { name } => {
-                crate::write_string(&mut w, name)?;
-            }
+            Modification::Unchanged => Self::UNCHANGED,
+            Modification::Removed => Self::REMOVED,
+            Modification::Set(_) => Self::SET,
         }
-
-        Ok(())
     }
+}
 
-    #[cfg(feature = "tokio")]
-    pub async fn tokio_read<R: tokio::io::AsyncReadExt + Unpin>(
-        mut r: R,
-    ) -> Result<Self, MessageError> {
-        let mut opcode = [0_u8; 1];
-        r.read_exact(&mut opcode).await?;
-
-        Ok(match opcode[0] {
-            Self::REQUEST_SESSION_KEY_OPCODE => {
-                let name = crate::read_string_tokio(r).await?;
-
-                Self::RequestSessionKey { name }
-            }
-            Self::REGISTER_REALM_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
-
-                let address = crate::read_string_tokio(&mut r).await?;
-
-                let population = crate::read_f32_tokio(&mut r).await?;
-
-                let locked = crate::read_bool_tokio(&mut r).await?;
-
-                let flags = crate::read_u8_tokio(&mut r).await?;
-
-                let category = crate::read_u8_tokio(&mut r).await?;
-
-                let realm_type = crate::read_u8_tokio(&mut r).await?;
-
-                let version_major = crate::read_u8_tokio(&mut r).await?;
-                let version_minor = crate::read_u8_tokio(&mut r).await?;
-                let version_patch = crate::read_u8_tokio(&mut r).await?;
-                let version_build = crate::read_u16_tokio(&mut r).await?;
-
-                Self::RegisterRealm {
-                    name,
-                    address,
-                    population,
-                    locked,
-                    flags,
-                    category,
-                    realm_type,
-                    version_major,
-                    version_minor,
-                    version_patch,
-                    version_build,
-                }
-            }
-            Self::ADD_USER_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
-                let password = crate::read_string_tokio(&mut r).await?;
-
-                Self::AddUser { name, password }
-            }
-            Self::REMOVE_USER_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
-
-                Self::RemoveUser { name }
-            }
-            Self::MODIFY_USER_OPCODE => {
-                let name = crate::read_string_tokio(&mut r).await?;
+/// Realm flags, with the version only being used if the `SPECIFY_BUILD` flag is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RealmFlags {
+    pub flags: u8,
+    pub version_major: u8,
+    pub version_minor: u8,
+    pub version_patch: u8,
+    pub version_build: u16,
+}
 
-                Self::ModifyUser { name }
-            }
-            v => return Err(MessageError::InvalidOpcode(v)),
-        })
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixCardData {
+    pub challenge_count: u8,
+    pub data: Vec<u8>,
+}
 
-    #[cfg(feature = "tokio")]
-    pub async fn tokio_write<W: tokio::io::AsyncWriteExt + Unpin>(
-        &mut self,
-        mut w: W,
-    ) -> std::io::Result<()> {
-        let mut v = Vec::new();
-        self.write(&mut v)?;
-        w.write_all(&v).await
+messages! {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ServerOpcodes {
+        0 => RequestSessionKey {
+            name: String,
+        },
+        3 => CharacterAmountAnswer {
+            name: String,
+            amount_of_characters: u8,
+        },
+        4 => RegisterRealm {
+            name: String,
+            address: String,
+            population: f32,
+            locked: bool,
+            flags: u8,
+            category: u8,
+            realm_type: u8,
+            version_major: u8,
+            version_minor: u8,
+            version_patch: u8,
+            version_build: u16,
+            /// Ask for a specific realm ID instead of the one assigned by the auth server.
+            realm_id: Option<u8> = None,
+        },
+        /// Sends the password in cleartext, prefer [`ServerOpcodes::AddUserWithVerifier`].
+        6 => AddUser {
+            name: String,
+            password: String,
+        },
+        8 => RemoveUser {
+            name: String,
+        },
+        10 => ModifyUser {
+            name: String,
+            password: Option<String>,
+            pin: Modification<u64>,
+            matrix_card: Modification<MatrixCardData>,
+            account_flags: Option<u32>,
+        },
+        12 => RequestAuthChallenge,
+        14 => AuthProof {
+            name: String,
+            client_challenge: [u8; CHALLENGE_LENGTH],
+            client_proof: [u8; PROOF_LENGTH],
+        },
+        /// Changes fields of the realm registered on this connection. Fields that are [`None`] are left unchanged.
+        16 => UpdateRealm {
+            name: Option<String>,
+            address: Option<String>,
+            population: Option<f32>,
+            locked: Option<bool>,
+            flags: Option<RealmFlags>,
+            category: Option<u8>,
+            realm_type: Option<u8>,
+        },
+        /// Keeps the realm registered on this connection from being shown as offline.
+        18 => Heartbeat,
+        /// Pushes every new, refreshed and revoked session to this connection
+        /// with [`ClientOpcodes::SessionChanged`](crate::ClientOpcodes::SessionChanged).
+        20 => SubscribeSessions,
+        /// Removes the session of a user, who then has to log in again.
+        24 => RevokeSession {
+            name: String,
+        },
+        /// Revokes the session of a user and tells the world server that reported the account as online
+        /// to disconnect it.
+        26 => KickAccount {
+            name: String,
+        },
+        /// Tells the auth server which world server the account is playing on, so that kicks reach it.
+        28 => AccountOnline {
+            name: String,
+            online: bool,
+        },
+        /// Adds a user with the salt and password verifier already computed, so that the password never
+        /// leaves the caller. Answered with [`ClientOpcodes::AddUserReply`](crate::ClientOpcodes::AddUserReply).
+        30 => AddUserWithVerifier {
+            name: String,
+            salt: [u8; SALT_LENGTH],
+            password_verifier: [u8; PASSWORD_VERIFIER_LENGTH],
+        },
+        /// Lists accounts ordered by name. `limit` is capped at [`MAX_LIST_LIMIT`],
+        /// fewer accounts than `limit` means that this is the last page.
+        32 => ListAccounts {
+            offset: u32,
+            limit: u8,
+        },
+        /// Lists active sessions ordered by name. `limit` is capped at [`MAX_LIST_LIMIT`],
+        /// fewer sessions than `limit` means that this is the last page.
+        34 => ListSessions {
+            offset: u32,
+            limit: u8,
+        },
+        /// Lists registered realms ordered by realm ID. `limit` is capped at [`MAX_LIST_LIMIT`],
+        /// fewer realms than `limit` means that this is the last page.
+        36 => ListRealms {
+            offset: u32,
+            limit: u8,
+        },
     }
 }
@@ -0,0 +1,241 @@
+use crate::{MessageError, MAX_MESSAGE_SIZE};
+use proptest::prelude::*;
+
+#[cfg(feature = "sync")]
+fn read_server(frame: &[u8]) -> Result<crate::ServerOpcodes, MessageError> {
+    Ok(crate::ServerOpcodes::read(frame)?.1)
+}
+
+#[cfg(feature = "tokio")]
+fn read_client(frame: &[u8]) -> Result<crate::ClientOpcodes, MessageError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    Ok(runtime.block_on(crate::ClientOpcodes::tokio_read(frame))?.1)
+}
+
+fn build_frame(size: u32, opcode: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&size.to_le_bytes());
+    frame.push(opcode);
+    frame.extend_from_slice(&0_u32.to_le_bytes());
+    frame.extend_from_slice(body);
+
+    frame
+}
+
+fn build_message(opcode: u8, body: &[u8]) -> Vec<u8> {
+    build_frame(5 + body.len() as u32, opcode, body)
+}
+
+#[test]
+fn oversized_frame_is_error() {
+    // Only the header is sent, so reading the body would wait forever on a connection
+    let frame = build_frame(MAX_MESSAGE_SIZE + 1, 0, &[]);
+
+    #[cfg(feature = "sync")]
+    assert!(matches!(
+        read_server(&frame),
+        Err(MessageError::InvalidMessageSize(_))
+    ));
+
+    #[cfg(feature = "tokio")]
+    assert!(matches!(
+        read_client(&frame),
+        Err(MessageError::InvalidMessageSize(_))
+    ));
+}
+
+#[test]
+fn undersized_frame_is_error() {
+    let frame = build_frame(4, 0, &[]);
+
+    #[cfg(feature = "sync")]
+    assert!(matches!(
+        read_server(&frame),
+        Err(MessageError::InvalidMessageSize(4))
+    ));
+
+    #[cfg(feature = "tokio")]
+    assert!(matches!(
+        read_client(&frame),
+        Err(MessageError::InvalidMessageSize(4))
+    ));
+}
+
+#[test]
+fn invalid_opcode_is_error() {
+    let frame = build_message(0xFF, &[]);
+
+    #[cfg(feature = "sync")]
+    assert!(matches!(
+        read_server(&frame),
+        Err(MessageError::InvalidOpcode(0xFF))
+    ));
+
+    #[cfg(feature = "tokio")]
+    assert!(matches!(
+        read_client(&frame),
+        Err(MessageError::InvalidOpcode(0xFF))
+    ));
+}
+
+#[test]
+fn invalid_utf8_is_error() {
+    let name = [2, 0xC3, 0x28];
+
+    // `request_session_key`
+    #[cfg(feature = "sync")]
+    assert!(matches!(
+        read_server(&build_message(0, &name)),
+        Err(MessageError::Utf8(_))
+    ));
+
+    // `request_character_amount`
+    #[cfg(feature = "tokio")]
+    assert!(matches!(
+        read_client(&build_message(2, &name)),
+        Err(MessageError::Utf8(_))
+    ));
+}
+
+#[test]
+fn truncated_body_is_error() {
+    let name = [1, b'A'];
+
+    // `add_user` without `password`
+    #[cfg(feature = "sync")]
+    assert!(matches!(
+        read_server(&build_message(6, &name)),
+        Err(MessageError::Io(_))
+    ));
+
+    // `add_user_reply` without `success`
+    #[cfg(feature = "tokio")]
+    assert!(matches!(
+        read_client(&build_message(7, &name)),
+        Err(MessageError::Io(_))
+    ));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn missing_update_realm_reason_is_unknown() {
+    // `update_realm_reply` from an auth server that only sends `success`
+    assert!(matches!(
+        read_client(&build_message(0x11, &[0])),
+        Ok(crate::ClientOpcodes::UpdateRealmReply {
+            result: Err(crate::RegisterRealmError::Unknown)
+        })
+    ));
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_body_does_not_panic(
+        opcode: u8,
+        body in prop::collection::vec(any::<u8>(), 0..512),
+    ) {
+        let frame = build_message(opcode, &body);
+
+        #[cfg(feature = "sync")]
+        let _ = read_server(&frame);
+
+        #[cfg(feature = "tokio")]
+        let _ = read_client(&frame);
+    }
+
+    #[test]
+    fn arbitrary_bytes_do_not_panic(data in prop::collection::vec(any::<u8>(), 0..512)) {
+        #[cfg(feature = "sync")]
+        let _ = read_server(&data);
+
+        #[cfg(feature = "tokio")]
+        let _ = read_client(&data);
+    }
+}
+
+#[test]
+fn long_key_names_are_rejected() {
+    let name = "A".repeat(crate::MAX_KEY_NAME_LENGTH + 1);
+    let challenge = [0; crate::CHALLENGE_LENGTH];
+
+    assert!(crate::client_proof(b"KEY", &name[1..], &challenge, &challenge).is_some());
+    assert!(crate::client_proof(b"KEY", &name, &challenge, &challenge).is_none());
+    assert!(crate::server_proof(b"KEY", &name, &challenge, &challenge).is_none());
+    assert!(!crate::verify_client_proof(
+        b"KEY",
+        &name,
+        &challenge,
+        &challenge,
+        &[0; crate::PROOF_LENGTH]
+    ));
+}
+
+const MESSAGES_MD: &str = include_str!("../messages.md");
+
+/// Converts `RegisterRealmReply` to `register_realm_reply`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && !snake.is_empty() {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+
+    snake
+}
+
+#[test]
+fn messages_md_matches_definitions() {
+    let mut documented = MESSAGES_MD
+        .lines()
+        .filter_map(|line| {
+            let (name, opcode) = line
+                .strip_prefix("msg ")?
+                .strip_suffix(" {")?
+                .split_once(" = 0x")?;
+
+            Some((name.to_string(), u8::from_str_radix(opcode, 16).unwrap()))
+        })
+        .collect::<Vec<_>>();
+    documented.sort();
+
+    let mut defined = crate::ServerOpcodes::NAMES
+        .iter()
+        .chain(crate::ClientOpcodes::NAMES)
+        .map(|(opcode, name)| (snake_case(name), *opcode))
+        .collect::<Vec<_>>();
+    defined.sort();
+
+    assert_eq!(documented, defined);
+}
+
+#[test]
+fn messages_md_matches_register_realm_errors() {
+    let documented = MESSAGES_MD
+        .lines()
+        .skip_while(|line| *line != "enum RegisterRealmError : u8 {")
+        .skip(1)
+        .take_while(|line| *line != "}")
+        .map(|line| {
+            let (name, value) = line.trim().trim_end_matches(';').split_once(" = ").unwrap();
+
+            (name.to_string(), value.parse::<u8>().unwrap())
+        })
+        .collect::<Vec<_>>();
+
+    // Unknown values are read as `Unknown`, so only values that are kept exist
+    let defined = (0..=u8::MAX)
+        .filter(|&value| crate::RegisterRealmError::from_int(value).as_int() == value)
+        .map(|value| {
+            let name = format!("{:?}", crate::RegisterRealmError::from_int(value));
+
+            (snake_case(&name).to_uppercase(), value)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(documented, defined);
+}
@@ -0,0 +1,112 @@
+//! TLS for the reply connection.
+//!
+//! [`ServerOpcodes::tokio_read`](crate::ServerOpcodes::tokio_read) and the other tokio functions
+//! accept any async stream, so the streams returned by [`TlsAcceptor::accept`]
+//! and [`TlsConnector::connect`] can be used directly.
+
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+pub use tokio_rustls::rustls::pki_types::ServerName;
+pub use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+fn tls_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+/// Reads all PEM encoded certificates in `path`.
+pub fn load_certificates(path: &Path) -> std::io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+    let certificates = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(tls_error(format!(
+            "no certificates found in '{}'",
+            path.display()
+        )));
+    }
+
+    Ok(certificates)
+}
+
+/// Reads the first PEM encoded private key in `path`.
+pub fn load_private_key(path: &Path) -> std::io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| tls_error(format!("no private key found in '{}'", path.display())))
+}
+
+fn root_store(path: &Path) -> std::io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots.add(certificate).map_err(tls_error)?;
+    }
+
+    Ok(roots)
+}
+
+/// Acceptor for the auth server.
+///
+/// If `client_ca` is set, clients must present a certificate signed by it.
+pub fn acceptor(
+    certificate: &Path,
+    private_key: &Path,
+    client_ca: Option<&Path>,
+) -> std::io::Result<TlsAcceptor> {
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+
+    let builder = if let Some(client_ca) = client_ca {
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(root_store(client_ca)?.into(), provider)
+                .build()
+                .map_err(tls_error)?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    let config = builder
+        .with_single_cert(
+            load_certificates(certificate)?,
+            load_private_key(private_key)?,
+        )
+        .map_err(tls_error)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Connector for world servers.
+///
+/// The auth server certificate must be signed by `ca`.
+/// `client_identity` is the certificate and private key used when the auth server requires client certificates.
+pub fn connector(
+    ca: &Path,
+    client_identity: Option<(&Path, &Path)>,
+) -> std::io::Result<TlsConnector> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_root_certificates(root_store(ca)?);
+
+    let config = if let Some((certificate, private_key)) = client_identity {
+        builder
+            .with_client_auth_cert(
+                load_certificates(certificate)?,
+                load_private_key(private_key)?,
+            )
+            .map_err(tls_error)?
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
@@ -0,0 +1,112 @@
+#[cfg(any(feature = "sync", feature = "tokio"))]
+use crate::MessageError;
+
+/// Sent at the start of every connection, before the versions.
+pub const MAGIC: [u8; 4] = *b"WRTH";
+/// Newest protocol version that this crate implements.
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Oldest protocol version that this crate implements.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+/// First protocol version with a request ID in every frame.
+///
+/// Before it, replies are sent in the same order as the requests.
+pub const REQUEST_ID_VERSION: u16 = 2;
+/// Sent by the auth server if it does not support any of the requested versions.
+#[cfg(any(feature = "sync", feature = "tokio"))]
+const NO_VERSION: u16 = 0;
+
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn request(min: u16, max: u16) -> [u8; 8] {
+    let mut buf = [0_u8; 8];
+    buf[0..4].copy_from_slice(&MAGIC);
+    buf[4..6].copy_from_slice(&min.to_le_bytes());
+    buf[6..8].copy_from_slice(&max.to_le_bytes());
+
+    buf
+}
+
+/// Picks the newest version in `min..=max` that is also supported by this crate.
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn choose_version(request: [u8; 8]) -> Result<u16, MessageError> {
+    let magic = [request[0], request[1], request[2], request[3]];
+    if magic != MAGIC {
+        return Err(MessageError::InvalidMagic(magic));
+    }
+
+    let min = u16::from_le_bytes([request[4], request[5]]);
+    let max = u16::from_le_bytes([request[6], request[7]]);
+
+    let version = max.min(PROTOCOL_VERSION);
+    if version < min || version < MIN_PROTOCOL_VERSION {
+        Err(MessageError::UnsupportedVersion { min, max })
+    } else {
+        Ok(version)
+    }
+}
+
+#[cfg(any(feature = "sync", feature = "tokio"))]
+fn chosen_version(reply: [u8; 2]) -> Result<u16, MessageError> {
+    match u16::from_le_bytes(reply) {
+        NO_VERSION => Err(MessageError::UnsupportedVersion {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        }),
+        v => Ok(v),
+    }
+}
+
+/// Sent by the world server immediately after connecting.
+///
+/// Returns the protocol version used for the rest of the connection.
+#[cfg(feature = "sync")]
+pub fn request_version<S: std::io::Read + std::io::Write>(mut s: S) -> Result<u16, MessageError> {
+    s.write_all(&request(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION))?;
+
+    let mut reply = [0_u8; 2];
+    s.read_exact(&mut reply)?;
+
+    chosen_version(reply)
+}
+
+/// Answers [`request_version`] on the auth server.
+///
+/// Returns the protocol version used for the rest of the connection.
+#[cfg(feature = "sync")]
+pub fn accept_version<S: std::io::Read + std::io::Write>(mut s: S) -> Result<u16, MessageError> {
+    let mut request = [0_u8; 8];
+    s.read_exact(&mut request)?;
+
+    let version = choose_version(request);
+    s.write_all(&version.as_ref().unwrap_or(&NO_VERSION).to_le_bytes())?;
+
+    version
+}
+
+#[cfg(feature = "tokio")]
+pub async fn tokio_request_version<
+    S: tokio::io::AsyncReadExt + tokio::io::AsyncWriteExt + Unpin,
+>(
+    mut s: S,
+) -> Result<u16, MessageError> {
+    s.write_all(&request(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION))
+        .await?;
+
+    let mut reply = [0_u8; 2];
+    s.read_exact(&mut reply).await?;
+
+    chosen_version(reply)
+}
+
+#[cfg(feature = "tokio")]
+pub async fn tokio_accept_version<S: tokio::io::AsyncReadExt + tokio::io::AsyncWriteExt + Unpin>(
+    mut s: S,
+) -> Result<u16, MessageError> {
+    let mut request = [0_u8; 8];
+    s.read_exact(&mut request).await?;
+
+    let version = choose_version(request);
+    s.write_all(&version.as_ref().unwrap_or(&NO_VERSION).to_le_bytes())
+        .await?;
+
+    version
+}
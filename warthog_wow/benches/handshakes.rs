@@ -0,0 +1,105 @@
+//! Measures full logon handshakes per second against an in-process server,
+//! with the SRP calculations running directly on the async workers and on the blocking pool.
+//!
+//! Run with `cargo bench -p warthog_wow --bench handshakes`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use warthog_lib::Options;
+use warthog_wow::ApplicationOptions;
+use wow_client::{
+    connect_and_authenticate, CMD_AUTH_LOGON_CHALLENGE_Client, Locale, Os, Platform,
+    ProtocolVersion, Version,
+};
+
+const CONCURRENCY: usize = 64;
+const HANDSHAKES: usize = 2000;
+
+fn challenge() -> CMD_AUTH_LOGON_CHALLENGE_Client {
+    CMD_AUTH_LOGON_CHALLENGE_Client {
+        protocol_version: ProtocolVersion::Three,
+        version: Version {
+            major: 1,
+            minor: 12,
+            patch: 1,
+            build: 5875,
+        },
+        platform: Platform::X86,
+        os: Os::Windows,
+        locale: Locale::EnGb,
+        utc_timezone_offset: 60,
+        client_ip_address: Ipv4Addr::new(127, 0, 0, 1),
+        account_name: "A".to_string(),
+    }
+}
+
+fn free_address() -> SocketAddr {
+    std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn run(max_concurrent_crypto_operations: u32) -> f64 {
+    let address = free_address();
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let main = tokio::spawn(warthog_wow::lib_main(
+        Options {
+            address,
+            randomize_pin_grid: false,
+            max_concurrent_users: 10_000,
+            max_concurrent_crypto_operations,
+            unknown_account_secret: None,
+        },
+        ApplicationOptions {
+            reply_address: Some(free_address()),
+            reply_insecure: true,
+            users: vec![("A".to_string(), "A".to_string())],
+            ..Default::default()
+        },
+        should_run.clone(),
+    ));
+
+    while TcpStream::connect(address).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let start = Instant::now();
+    let mut tasks = Vec::with_capacity(CONCURRENCY);
+    for _ in 0..CONCURRENCY {
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..HANDSHAKES / CONCURRENCY {
+                connect_and_authenticate(challenge(), address, "A", None)
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    should_run.store(false, Ordering::SeqCst);
+    main.await.unwrap();
+
+    (HANDSHAKES / CONCURRENCY * CONCURRENCY) as f64 / elapsed.as_secs_f64()
+}
+
+#[tokio::main]
+async fn main() {
+    let cpus = std::thread::available_parallelism()
+        .map(|a| a.get() as u32)
+        .unwrap_or(4);
+
+    let inline = run(0).await;
+    println!("async workers:      {inline:>10.1} handshakes/s");
+
+    let pool = run(cpus).await;
+    println!("blocking pool ({cpus:>2}): {pool:>10.1} handshakes/s");
+}
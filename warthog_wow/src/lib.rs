@@ -1,61 +1,196 @@
-mod credentials;
-mod game_files;
-mod keys;
-mod patches;
+mod realm_ids;
 mod realm_list;
 mod reply;
+mod sessions;
 #[cfg(test)]
 mod test;
 
-use crate::reply::start_reply_server;
-use credentials::ProviderImpl;
-use game_files::GameFileImpl;
-use keys::KeyImpl;
-use patches::PatchImpl;
+use crate::reply::{start_reply_server, ReplyAccess};
+pub use crate::reply::{Cidr, ReplyKey};
+use realm_ids::RealmIds;
 use realm_list::RealmListImpl;
+use sessions::NotifyingKeyStorage;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use tracing::info;
-use warthog_lib::{start_auth_server, Options};
+use std::time::Duration;
+use tracing::{error, info};
+use warthog_lib::memory::{
+    MemoryCredentialProvider, MemoryGameFileProvider, MemoryKeyStorage, MemoryPatchProvider,
+};
+use warthog_lib::{
+    start_auth_server, CredentialProvider, MatrixCard, MatrixCardOptions, MatrixCardVerifier,
+    Options, PinCode,
+};
 
-#[derive(Debug)]
+/// The default does not start the reply server.
+#[derive(Debug, Default)]
 pub struct ApplicationOptions {
-    pub reply_address: SocketAddr,
+    /// Address to accept reply connections on over TCP.
+    ///
+    /// Can be [`None`] if [`Self::reply_socket`] is set.
+    pub reply_address: Option<SocketAddr>,
+    /// Unix domain socket to accept reply connections on, as well as or instead of TCP.
+    pub reply_socket: Option<ReplySocketOptions>,
+    /// Address to accept HTTP requests with JSON versions of the reply messages on.
+    ///
+    /// Uses the same allow lists and keys as [`Self::reply_address`]. If [`None`], the gateway is not started.
+    /// Must be a loopback address since the gateway does not use TLS.
+    pub reply_http_address: Option<SocketAddr>,
+    /// Addresses that are allowed to connect to [`Self::reply_address`].
+    ///
+    /// Other connections are closed before anything is read. If empty, every address is allowed.
+    ///
+    /// This default differs from the command line, which only allows localhost unless told otherwise.
+    pub reply_allow: Vec<Cidr>,
+    /// Addresses that are allowed to manage users and make admin queries over [`Self::reply_address`].
+    ///
+    /// These are allowed to connect even if they are not in [`Self::reply_allow`].
+    /// If empty, every address that is allowed to connect can do admin operations.
+    pub reply_admin_allow: Vec<Cidr>,
     pub use_pin: bool,
     pub use_matrix_card: bool,
+    /// Usernames and passwords of users that are added when the server starts.
+    ///
+    /// Users are only kept in memory, so these are the only ones that exist after a restart.
+    /// More can be added over the reply server.
+    pub users: Vec<(String, String)>,
+    /// Keys that connections to the reply server authenticate with.
+    ///
+    /// The reply server does not start without keys unless [`Self::reply_insecure`] is set.
+    pub reply_keys: Vec<ReplyKey>,
+    /// Accept connections without authentication when [`Self::reply_keys`] is empty.
+    ///
+    /// These connections are allowed to do everything that their address allows.
+    pub reply_insecure: bool,
+    /// Use TLS for the reply server. Session keys and passwords are sent in plaintext without it.
+    pub reply_tls: Option<ReplyTlsOptions>,
+    /// Show realms as offline when no heartbeat has been received for this long.
+    ///
+    /// If [`None`], realms are shown as online for as long as they are connected.
+    pub realm_heartbeat_timeout: Option<Duration>,
+    /// Keep realms listed as offline for this long after their reply connection is lost.
+    ///
+    /// A world server that registers a realm with the same name and reply key within the grace period
+    /// gets the old `realm_id` back.
+    /// If [`None`], realms are removed as soon as the connection is lost.
+    pub realm_offline_grace_period: Option<Duration>,
+    /// File that realm IDs are read from and saved to, so that realms keep their IDs across restarts.
+    ///
+    /// Renamed realms keep their ID under the new name.
+    /// When all 256 IDs are assigned, the least recently assigned ID of a realm that is not registered is reused.
+    ///
+    /// If [`None`], realm IDs are only kept until the auth server is restarted.
+    pub realm_id_file: Option<PathBuf>,
+}
+
+/// Unix domain socket for the reply server.
+///
+/// Anyone that can open the socket file can connect, so the file permissions decide who is allowed to.
+/// [`ApplicationOptions::reply_allow`] and [`ApplicationOptions::reply_admin_allow`] do not apply,
+/// connections get all permissions that their key allows.
+#[derive(Debug, Clone)]
+pub struct ReplySocketOptions {
+    /// Replaced if a socket already exists at the path, and removed when the reply server stops.
+    pub path: PathBuf,
+    /// Permissions of the socket file, such as `0o660`, applied before anyone can connect.
+    pub mode: u32,
+}
+
+/// PEM encoded files for TLS on the reply server.
+#[derive(Debug, Clone)]
+pub struct ReplyTlsOptions {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+    /// Require world servers to present a certificate signed by this CA.
+    pub client_ca: Option<PathBuf>,
 }
 
 pub async fn lib_main(
-    options: Options,
+    mut options: Options,
     application_options: ApplicationOptions,
     should_run: Arc<AtomicBool>,
 ) {
-    let keys = KeyImpl::new();
-    let realms = RealmListImpl::new();
-    let provider = ProviderImpl::new(
-        application_options.use_pin,
-        application_options.use_matrix_card,
-    );
+    let keys = NotifyingKeyStorage::new(MemoryKeyStorage::new());
+    let realm_ids = match application_options.realm_id_file {
+        Some(path) => match RealmIds::load(path) {
+            Ok(realm_ids) => realm_ids,
+            Err(e) => {
+                error!(?e, "unable to load realm ids");
+                return;
+            }
+        },
+        None => RealmIds::new(),
+    };
+    let realms = RealmListImpl::new(application_options.realm_heartbeat_timeout, realm_ids);
+
+    let pin = if application_options.use_pin {
+        Some(PinCode::from_u64(1234).unwrap())
+    } else {
+        None
+    };
+    let matrix_card = if application_options.use_matrix_card {
+        Some(MatrixCardOptions {
+            matrix_card: MatrixCard::from_data(vec![
+                0;
+                MatrixCard::DEFAULT_DIGIT_COUNT as usize
+                    * MatrixCard::DEFAULT_HEIGHT as usize
+                    * MatrixCard::DEFAULT_WIDTH as usize
+            ]),
+            challenge_count: MatrixCardVerifier::DEFAULT_CHALLENGE_COUNT,
+        })
+    } else {
+        None
+    };
+    // Unknown accounts must look like those with the default PIN and matrix card
+    options.unknown_account_secret = options
+        .unknown_account_secret
+        .map(|secret| secret.with_security(pin.is_some(), matrix_card.clone()));
+    let mut provider = MemoryCredentialProvider::with_defaults(pin, matrix_card);
+    for (username, password) in &application_options.users {
+        if provider.add_user(username, password).await.is_none() {
+            error!(username, "unable to add user");
+            return;
+        }
+    }
 
     let keys_auth = keys.clone();
     let realms_auth = realms.clone();
     let provider_auth = provider.clone();
+    let should_run_auth = should_run.clone();
     let auth = tokio::spawn(async move {
         start_auth_server(
             provider_auth,
             keys_auth,
-            PatchImpl {},
-            GameFileImpl {},
+            MemoryPatchProvider::new(),
+            MemoryGameFileProvider::new(),
             realms_auth,
-            should_run,
+            should_run_auth,
             options,
         )
         .await
     });
 
     let reply = tokio::spawn(async move {
-        start_reply_server(keys, realms, provider, application_options.reply_address).await
+        start_reply_server(
+            keys,
+            realms,
+            provider,
+            application_options.reply_address,
+            application_options.reply_socket,
+            application_options.reply_http_address,
+            ReplyAccess::new(
+                application_options.reply_allow,
+                application_options.reply_admin_allow,
+            ),
+            application_options.reply_keys,
+            application_options.reply_insecure,
+            application_options.reply_tls,
+            application_options.realm_offline_grace_period,
+            should_run,
+        )
+        .await
     });
 
     tokio::select! {
@@ -1,10 +1,15 @@
 use clap::Parser;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
-use warthog_lib::Options;
-use warthog_wow::ApplicationOptions;
+use warthog_lib::{Options, UnknownAccountSecret};
+use warthog_wow::{ApplicationOptions, Cidr, ReplyKey, ReplySocketOptions, ReplyTlsOptions};
+
+/// Used when neither `--reply-address` nor `--reply-socket` is given.
+const DEFAULT_REPLY_ADDRESS: &str = "0.0.0.0:8086";
 
 #[derive(clap::Parser, Debug)]
 #[command(version, about)]
@@ -16,22 +21,136 @@ struct Args {
     #[arg(short, long, default_value = "false")]
     pin_grid_randomize: bool,
     /// Address to reply to inter server communication on.
-    #[arg(short, long, default_value = "0.0.0.0:8086")]
-    reply_address: SocketAddr,
+    ///
+    /// Defaults to 0.0.0.0:8086, unless `--reply-socket` is given.
+    #[arg(short, long)]
+    reply_address: Option<SocketAddr>,
+    /// Unix domain socket to reply to inter server communication on.
+    #[arg(long)]
+    reply_socket: Option<PathBuf>,
+    /// Octal file permissions of the reply socket, which decide who can connect.
+    #[arg(long, default_value = "660", value_parser = parse_mode)]
+    reply_socket_mode: u32,
+    /// Loopback address to accept HTTP requests with JSON versions of the reply messages on,
+    /// such as `127.0.0.1:8087`.
+    ///
+    /// Uses the same `--reply-allow`, `--reply-admin-allow` and `--reply-key` as the reply server.
+    /// Not started if not given.
+    #[arg(long)]
+    reply_http_address: Option<SocketAddr>,
+    /// Address range, such as `10.0.0.0/8`, that is allowed to connect to `--reply-address`.
+    ///
+    /// Can be given multiple times. Defaults to localhost only, world servers on other hosts must be added.
+    #[arg(long = "reply-allow", default_values = ["127.0.0.0/8", "::1/128"])]
+    reply_allow: Vec<Cidr>,
+    /// Address range that is allowed to manage users and make admin queries over `--reply-address`.
+    ///
+    /// Can be given multiple times. Defaults to localhost only.
+    #[arg(long = "reply-admin-allow", default_values = ["127.0.0.0/8", "::1/128"])]
+    reply_admin_allow: Vec<Cidr>,
+    /// Send fake challenges derived from this secret for unknown accounts,
+    /// instead of revealing that the account does not exist.
+    #[arg(long)]
+    unknown_account_secret: Option<String>,
+    /// User to add when the server starts, as `NAME:PASSWORD`.
+    ///
+    /// Can be given multiple times. Users are only kept in memory,
+    /// so without this the server starts without users until they are added over the reply server.
+    #[arg(long = "user", value_parser = parse_user)]
+    users: Vec<(String, String)>,
+    /// Key for authenticating reply connections, as `NAME:PERMISSIONS:SECRET`.
+    ///
+    /// PERMISSIONS is a comma separated list of `session_keys`, `realm`, `users`, `queries`, `realm_admin` or `all`.
+    /// Can be given multiple times. The reply server does not start without keys unless `--reply-insecure` is given.
+    #[arg(long = "reply-key")]
+    reply_keys: Vec<ReplyKey>,
+    /// Trust all reply connections when no `--reply-key` is given.
+    #[arg(long, conflicts_with = "reply_keys")]
+    reply_insecure: bool,
+    /// PEM certificate chain for TLS on the reply server.
+    #[arg(long, requires = "reply_tls_key")]
+    reply_tls_certificate: Option<PathBuf>,
+    /// PEM private key for TLS on the reply server.
+    #[arg(long, requires = "reply_tls_certificate")]
+    reply_tls_key: Option<PathBuf>,
+    /// PEM CA that reply connections must present a client certificate from.
+    #[arg(long, requires = "reply_tls_certificate")]
+    reply_tls_client_ca: Option<PathBuf>,
+    /// Seconds without a heartbeat before a realm is shown as offline.
+    #[arg(long)]
+    realm_heartbeat_timeout: Option<u64>,
+    /// Seconds that a realm stays listed as offline after its world server disconnects.
+    #[arg(long)]
+    realm_offline_grace_period: Option<u64>,
+    /// File with `ID NAME` lines that realm IDs are read from and saved to.
+    #[arg(long)]
+    realm_id_file: Option<PathBuf>,
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    let mode = u32::from_str_radix(s, 8).map_err(|e| format!("invalid octal mode: {e}"))?;
+    if mode > 0o777 {
+        return Err(format!("mode {mode:o} is larger than 777"));
+    }
+
+    Ok(mode)
+}
+
+fn parse_user(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, password)) if !name.is_empty() && !password.is_empty() => {
+            Ok((name.to_string(), password.to_string()))
+        }
+        _ => Err("expected 'name:password'".to_string()),
+    }
 }
 
 impl Args {
     fn to_options(self) -> (Options, ApplicationOptions) {
+        let reply_address = match (self.reply_address, &self.reply_socket) {
+            (Some(address), _) => Some(address),
+            (None, Some(_)) => None,
+            (None, None) => Some(DEFAULT_REPLY_ADDRESS.parse().unwrap()),
+        };
+
         (
             Options {
                 address: self.address,
                 randomize_pin_grid: self.pin_grid_randomize,
                 max_concurrent_users: 1000,
+                max_concurrent_crypto_operations: std::thread::available_parallelism()
+                    .map(|a| a.get() as u32)
+                    .unwrap_or(4),
+                unknown_account_secret: self
+                    .unknown_account_secret
+                    .map(|a| UnknownAccountSecret::from_passphrase(&a)),
             },
             ApplicationOptions {
-                reply_address: self.reply_address,
+                reply_address,
+                reply_socket: self.reply_socket.map(|path| ReplySocketOptions {
+                    path,
+                    mode: self.reply_socket_mode,
+                }),
+                reply_http_address: self.reply_http_address,
+                reply_allow: self.reply_allow,
+                reply_admin_allow: self.reply_admin_allow,
                 use_pin: false,
                 use_matrix_card: false,
+                users: self.users,
+                reply_keys: self.reply_keys,
+                reply_insecure: self.reply_insecure,
+                reply_tls: self.reply_tls_certificate.zip(self.reply_tls_key).map(
+                    |(certificate, private_key)| ReplyTlsOptions {
+                        certificate,
+                        private_key,
+                        client_ca: self.reply_tls_client_ca,
+                    },
+                ),
+                realm_heartbeat_timeout: self.realm_heartbeat_timeout.map(Duration::from_secs),
+                realm_offline_grace_period: self
+                    .realm_offline_grace_period
+                    .map(Duration::from_secs),
+                realm_id_file: self.realm_id_file,
             },
         )
     }
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+#[derive(Debug, Clone)]
+struct Assignment {
+    realm_id: u8,
+    /// Increased on every assignment, so that the least recently assigned ID can be reclaimed.
+    sequence: u64,
+}
+
+/// Realm IDs assigned to realm names.
+///
+/// With a file, assignments are kept across restarts.
+/// The file has one `ID NAME` per line, and can also be written by hand to configure IDs.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub(crate) struct RealmIds {
+    ids: HashMap<String, Assignment>,
+    next_sequence: u64,
+    path: Option<PathBuf>,
+    /// Sequence of the newest assignments written to the file.
+    saved: Arc<Mutex<u64>>,
+}
+
+impl RealmIds {
+    /// Assignments that only last until the auth server is restarted.
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            next_sequence: 0,
+            path: None,
+            saved: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Reads assignments from `path`, which is created when the first ID is assigned if it does not exist.
+    ///
+    /// Assignments from the file count as assigned in the order they appear in.
+    pub fn load(path: PathBuf) -> std::io::Result<Self> {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut ids = HashMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: {reason}", path.display(), i + 1),
+                )
+            };
+
+            let (id, name) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid("expected 'ID NAME'"))?;
+            let id = id.parse::<u8>().map_err(|_| invalid("invalid realm id"))?;
+            let name = name.trim();
+
+            if ids.values().any(|a: &Assignment| a.realm_id == id) {
+                return Err(invalid("realm id is assigned more than once"));
+            }
+            let assignment = Assignment {
+                realm_id: id,
+                sequence: ids.len() as u64,
+            };
+            if ids.insert(name.to_string(), assignment).is_some() {
+                return Err(invalid("realm name is assigned more than once"));
+            }
+        }
+
+        info!(path = %path.display(), amount = ids.len(), "loaded realm ids");
+
+        Ok(Self {
+            next_sequence: ids.len() as u64,
+            ids,
+            path: Some(path),
+            saved: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<u8> {
+        self.ids.get(name).map(|a| a.realm_id)
+    }
+
+    pub fn is_assigned(&self, realm_id: u8) -> bool {
+        self.ids.values().any(|a| a.realm_id == realm_id)
+    }
+
+    /// Returns the least recently assigned ID for which `available` returns `true`.
+    pub fn least_recently_assigned(&self, available: impl Fn(u8) -> bool) -> Option<u8> {
+        self.ids
+            .values()
+            .filter(|a| available(a.realm_id))
+            .min_by_key(|a| a.sequence)
+            .map(|a| a.realm_id)
+    }
+
+    /// Moves `realm_id` to the new name of its realm.
+    ///
+    /// Returns [`Err`] if `name` is already assigned a different ID.
+    pub fn rename(&mut self, name: &str, realm_id: u8) -> Result<Option<RealmIdsSave>, ()> {
+        match self.get(name) {
+            Some(assigned) if assigned != realm_id => {
+                error!(assigned, realm_id, "realm name is assigned a different id");
+                Err(())
+            }
+            _ => Ok(self.assign(name, realm_id)),
+        }
+    }
+
+    /// Assigns `realm_id` to `name`, taking it from any other name it was assigned to.
+    ///
+    /// The returned [`RealmIdsSave`] must be written after the lock on `self` is released.
+    pub fn assign(&mut self, name: &str, realm_id: u8) -> Option<RealmIdsSave> {
+        if self.get(name) == Some(realm_id) {
+            return None;
+        }
+
+        info!(name, realm_id, "assigning realm id");
+        self.ids.retain(|_, a| a.realm_id != realm_id);
+        self.ids.insert(
+            name.to_string(),
+            Assignment {
+                realm_id,
+                sequence: self.next_sequence,
+            },
+        );
+        self.next_sequence += 1;
+
+        let path = self.path.clone()?;
+
+        let mut ids = self.ids.iter().collect::<Vec<_>>();
+        ids.sort_by_key(|(_, a)| a.realm_id);
+
+        let mut contents = String::new();
+        for (name, a) in ids {
+            contents.push_str(&format!("{} {name}\n", a.realm_id));
+        }
+
+        Some(RealmIdsSave {
+            path,
+            contents,
+            sequence: self.next_sequence,
+            saved: self.saved.clone(),
+        })
+    }
+}
+
+/// Contents of the realm ID file after an assignment.
+#[must_use]
+#[derive(Debug)]
+pub(crate) struct RealmIdsSave {
+    path: PathBuf,
+    contents: String,
+    sequence: u64,
+    saved: Arc<Mutex<u64>>,
+}
+
+impl RealmIdsSave {
+    /// Replaces the file without blocking the runtime.
+    ///
+    /// Does nothing if newer contents have already been written.
+    pub async fn write(self) {
+        let path = self.path.clone();
+
+        let result = tokio::task::spawn_blocking(move || self.write_blocking())
+            .await
+            .unwrap_or_else(|e| Err(Error::other(e)));
+
+        if let Err(e) = result {
+            error!(?e, path = %path.display(), "unable to save realm ids");
+        }
+    }
+
+    /// Writes to a temporary file and renames it, so that the file is never partially written.
+    fn write_blocking(self) -> std::io::Result<()> {
+        let mut saved = self.saved.lock().unwrap();
+        if *saved >= self.sequence {
+            return Ok(());
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+
+        std::fs::write(&temporary, &self.contents)?;
+        std::fs::rename(&temporary, &self.path)?;
+
+        *saved = self.sequence;
+
+        Ok(())
+    }
+}
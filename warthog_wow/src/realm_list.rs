@@ -1,77 +1,201 @@
-use std::collections::BTreeSet;
+use crate::realm_ids::{RealmIds, RealmIdsSave};
+use crate::reply::PeerAddress;
+use std::collections::HashMap;
 use std::future::Future;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{error, info};
 use warthog_lib::{
-    CMD_AUTH_LOGON_CHALLENGE_Client, Population, Realm, RealmCategory, RealmListProvider,
-    RealmType, Realm_RealmFlag,
+    CMD_AUTH_LOGON_CHALLENGE_Client, Population, Realm, RealmCategory, RealmFlag,
+    RealmListProvider, RealmType, Realm_RealmFlag, Realm_RealmFlag_SpecifyBuild,
 };
+use warthog_messages::RegisterRealmError;
+
+#[derive(Clone, Debug)]
+struct RealmEntry {
+    realm: Realm,
+    /// Flags sent by the world server, kept separately so that `OFFLINE` can be added and removed.
+    flags: u8,
+    specify_build: Option<Realm_RealmFlag_SpecifyBuild>,
+    last_heartbeat: Instant,
+    /// Set when the reply connection is lost, until the realm is registered again or removed.
+    disconnected: Option<Instant>,
+    /// Address of the reply connection that registered the realm.
+    peer_address: PeerAddress,
+    /// Name of the reply key that registered the realm, [`None`] if the reply server is insecure.
+    owner: Option<String>,
+}
+
+impl RealmEntry {
+    fn is_offline(&self, heartbeat_timeout: Option<Duration>) -> bool {
+        self.disconnected.is_some()
+            || heartbeat_timeout.is_some_and(|timeout| self.last_heartbeat.elapsed() > timeout)
+    }
+}
+
+/// Registered realm as seen by admin queries.
+#[derive(Clone, Debug)]
+pub(crate) struct RealmStatus {
+    pub realm: Realm,
+    /// Flags sent by the world server, without `OFFLINE`.
+    pub flags: u8,
+    pub online: bool,
+    pub peer_address: PeerAddress,
+}
+
+/// Changes to a registered realm. Fields that are [`None`] are left unchanged.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RealmUpdate {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub population: Option<Population>,
+    pub locked: Option<bool>,
+    pub flags: Option<(u8, Option<Realm_RealmFlag_SpecifyBuild>)>,
+    pub category: Option<RealmCategory>,
+    pub realm_type: Option<RealmType>,
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct RealmListImpl {
-    realms: Arc<Mutex<Vec<Realm>>>,
+    realms: Arc<Mutex<Vec<RealmEntry>>>,
+    characters: Arc<Mutex<HashMap<(u8, String), u8>>>,
+    /// Realms without a heartbeat for this long are shown as offline.
+    heartbeat_timeout: Option<Duration>,
+    ids: Arc<Mutex<RealmIds>>,
 }
 
 impl RealmListImpl {
-    pub fn new() -> Self {
+    pub fn new(heartbeat_timeout: Option<Duration>, ids: RealmIds) -> Self {
         Self {
             realms: Arc::new(Mutex::new(vec![])),
+            characters: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat_timeout,
+            ids: Arc::new(Mutex::new(ids)),
         }
     }
 
-    fn first_available_realm_id(&self) -> Option<u8> {
-        let mut ids_in_use = BTreeSet::new();
+    #[tracing::instrument]
+    pub fn set_number_of_characters(&mut self, realm_id: u8, name: &str, amount: u8) {
+        self.characters
+            .lock()
+            .unwrap()
+            .insert((realm_id, name.to_uppercase()), amount);
+    }
+
+    /// Picks the ID for a realm that is not yet registered on its connection.
+    ///
+    /// Realms get the ID assigned to their name, or `requested_realm_id` if the name has no ID yet.
+    /// Without free IDs, the least recently assigned ID of a realm that is not registered is reclaimed.
+    fn reserve_realm_id(
+        &self,
+        name: &str,
+        requested_realm_id: Option<u8>,
+        owner: Option<&str>,
+    ) -> Option<(u8, Option<RealmIdsSave>)> {
+        let mut ids = self.ids.lock().unwrap();
+        let realms = self.realms.lock().unwrap();
 
-        for realm in self.realms.lock().unwrap().as_slice() {
-            ids_in_use.insert(realm.realm_id);
-        }
+        let realm_id = match (ids.get(name), requested_realm_id) {
+            (Some(assigned), Some(requested)) if assigned != requested => {
+                error!(assigned, requested, "realm is assigned a different id");
+                return None;
+            }
+            (Some(assigned), _) => assigned,
+            (None, Some(requested)) => {
+                if ids.is_assigned(requested) {
+                    error!(requested, "realm id is assigned to another realm");
+                    return None;
+                }
 
-        for i in 0..=u8::MAX {
-            if ids_in_use.get(&i).is_none() {
-                return Some(i);
+                requested
             }
+            (None, None) => {
+                let registered = |id: u8| realms.iter().any(|a| a.realm.realm_id == id);
+                let available = (0..=u8::MAX)
+                    .find(|&id| !ids.is_assigned(id) && !registered(id))
+                    .or_else(|| {
+                        let reclaimed = ids.least_recently_assigned(|id| !registered(id))?;
+                        info!(realm_id = reclaimed, "reclaiming realm id");
+                        Some(reclaimed)
+                    });
+
+                let Some(realm_id) = available else {
+                    error!("Unable to find available realm id");
+                    return None;
+                };
+
+                realm_id
+            }
+        };
+
+        // A disconnected realm with the same name is taken back by a new connection with the same key
+        if realms.iter().any(|a| {
+            a.realm.realm_id == realm_id
+                && (a.disconnected.is_none() || a.realm.name != name || a.owner.as_deref() != owner)
+        }) {
+            error!(realm_id, "realm id is already in use");
+            return None;
         }
 
-        None
+        Some((realm_id, ids.assign(name, realm_id)))
+    }
+
+    /// Moves the ID of a realm registered on the connection to its new name.
+    fn rename_realm(&self, name: &str, realm_id: u8) -> Option<Option<RealmIdsSave>> {
+        self.ids.lock().unwrap().rename(name, realm_id).ok()
     }
 
+    /// `realm_id` is the ID of the realm already registered on the connection, if any.
+    ///
+    /// `peer_address` is the address of the reply connection and `owner` the name of its key.
+    ///
+    /// Returns once the realm ID file has been written.
+    #[allow(clippy::too_many_arguments)]
     #[tracing::instrument]
-    pub fn add_realm(
+    pub async fn add_realm(
         &mut self,
         name: String,
         address: String,
         population: Population,
         locked: bool,
-        flag: Realm_RealmFlag,
+        flags: u8,
+        specify_build: Option<Realm_RealmFlag_SpecifyBuild>,
         category: RealmCategory,
         realm_type: RealmType,
         realm_id: Option<u8>,
+        requested_realm_id: Option<u8>,
+        peer_address: PeerAddress,
+        owner: Option<String>,
     ) -> Option<u8> {
-        if let Some(realm_id) = realm_id {
-            if let Some(realm) = self
-                .realms
-                .lock()
-                .unwrap()
-                .iter_mut()
-                .find(|a| a.realm_id == realm_id)
-            {
-                realm.name = name;
-                realm.address = address;
-                realm.population = population;
-                realm.locked = locked;
-                realm.flag = flag;
-                realm.category = category;
-                realm.realm_type = realm_type;
-
-                Some(realm_id)
-            } else {
-                error!("Unable to realm with id {realm_id:?}");
-
-                None
+        let flag = Realm_RealmFlag::new(flags, specify_build.clone());
+
+        let (realm_id, save) = match realm_id {
+            Some(realm_id) => (realm_id, self.rename_realm(&name, realm_id)?),
+            None => self.reserve_realm_id(&name, requested_realm_id, owner.as_deref())?,
+        };
+
+        let mut realms = self.realms.lock().unwrap();
+        if let Some(entry) = realms.iter_mut().find(|a| a.realm.realm_id == realm_id) {
+            if entry.disconnected.is_some() {
+                info!(realm_id, "realm reconnected");
             }
+
+            entry.realm.name = name;
+            entry.realm.address = address;
+            entry.realm.population = population;
+            entry.realm.locked = locked;
+            entry.realm.flag = flag;
+            entry.realm.category = category;
+            entry.realm.realm_type = realm_type;
+            entry.flags = flags;
+            entry.specify_build = specify_build;
+            entry.last_heartbeat = Instant::now();
+            entry.disconnected = None;
+            entry.peer_address = peer_address;
+            entry.owner = owner;
         } else {
-            if let Some(realm_id) = self.first_available_realm_id() {
-                self.realms.lock().unwrap().push(Realm {
+            realms.push(RealmEntry {
+                realm: Realm {
                     realm_type,
                     locked,
                     flag,
@@ -81,31 +205,166 @@ impl RealmListImpl {
                     number_of_characters_on_realm: 0,
                     category,
                     realm_id,
-                });
+                },
+                flags,
+                specify_build,
+                last_heartbeat: Instant::now(),
+                disconnected: None,
+                peer_address,
+                owner,
+            });
+
+            info!(realm_id, "adding realm");
+        }
+        drop(realms);
 
-                info!(realm_id, "adding realm");
+        if let Some(save) = save {
+            save.write().await;
+        }
 
-                Some(realm_id)
-            } else {
-                error!("Unable to find available realm id");
+        Some(realm_id)
+    }
 
-                None
-            }
+    /// Fails if the realm does not exist or the new name is assigned a different ID.
+    ///
+    /// Returns once the realm ID file has been written.
+    #[tracing::instrument]
+    pub async fn update_realm(
+        &mut self,
+        realm_id: u8,
+        update: RealmUpdate,
+    ) -> Result<(), RegisterRealmError> {
+        let mut ids = self.ids.lock().unwrap();
+        let mut realms = self.realms.lock().unwrap();
+        let Some(entry) = realms.iter_mut().find(|a| a.realm.realm_id == realm_id) else {
+            return Err(RegisterRealmError::NotRegistered);
+        };
+
+        let save = match &update.name {
+            Some(name) => ids
+                .rename(name, realm_id)
+                .map_err(|()| RegisterRealmError::RealmIdUnavailable)?,
+            None => None,
+        };
+        drop(ids);
+
+        if let Some(name) = update.name {
+            entry.realm.name = name;
+        }
+        if let Some(address) = update.address {
+            entry.realm.address = address;
+        }
+        if let Some(population) = update.population {
+            entry.realm.population = population;
+        }
+        if let Some(locked) = update.locked {
+            entry.realm.locked = locked;
+        }
+        if let Some((flags, specify_build)) = update.flags {
+            entry.realm.flag = Realm_RealmFlag::new(flags, specify_build.clone());
+            entry.flags = flags;
+            entry.specify_build = specify_build;
         }
+        if let Some(category) = update.category {
+            entry.realm.category = category;
+        }
+        if let Some(realm_type) = update.realm_type {
+            entry.realm.realm_type = realm_type;
+        }
+        drop(realms);
+
+        if let Some(save) = save {
+            save.write().await;
+        }
+
+        Ok(())
     }
 
     #[tracing::instrument]
-    pub fn remove_realm(&mut self, realm_id: u8) {
-        if let Some((i, _)) = self
+    pub fn heartbeat(&mut self, realm_id: u8) {
+        if let Some(entry) = self
             .realms
             .lock()
             .unwrap()
-            .iter()
-            .enumerate()
-            .find(|(_, a)| a.realm_id == realm_id)
+            .iter_mut()
+            .find(|a| a.realm.realm_id == realm_id)
+        {
+            entry.last_heartbeat = Instant::now();
+        }
+    }
+
+    /// Shows the realm as offline until it is registered again.
+    ///
+    /// Returns the time of disconnection for [`Self::remove_disconnected_realm`].
+    #[tracing::instrument]
+    pub fn disconnect_realm(&mut self, realm_id: u8) -> Instant {
+        let now = Instant::now();
+
+        if let Some(entry) = self
+            .realms
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|a| a.realm.realm_id == realm_id)
         {
+            info!("realm disconnected");
+            entry.disconnected = Some(now);
+        }
+
+        now
+    }
+
+    /// Removes the realm only if it has not been registered again since `disconnected`.
+    #[tracing::instrument]
+    pub fn remove_disconnected_realm(&mut self, realm_id: u8, disconnected: Instant) {
+        let still_disconnected = self
+            .realms
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|a| a.realm.realm_id == realm_id && a.disconnected == Some(disconnected));
+
+        if still_disconnected {
+            self.remove_realm(realm_id);
+        }
+    }
+
+    /// Returns at most `limit` realms ordered by realm ID, skipping the first `offset`.
+    pub fn list_realms(&self, offset: u32, limit: u32) -> Vec<RealmStatus> {
+        let mut realms = self
+            .realms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| RealmStatus {
+                realm: entry.realm.clone(),
+                flags: entry.flags,
+                online: !entry.is_offline(self.heartbeat_timeout),
+                peer_address: entry.peer_address.clone(),
+            })
+            .collect::<Vec<_>>();
+        realms.sort_by_key(|a| a.realm.realm_id);
+
+        realms
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    #[tracing::instrument]
+    pub fn remove_realm(&mut self, realm_id: u8) {
+        let mut realms = self.realms.lock().unwrap();
+
+        if let Some(i) = realms.iter().position(|a| a.realm.realm_id == realm_id) {
             info!("removing realm");
-            self.realms.lock().unwrap().remove(i);
+            realms.remove(i);
+            drop(realms);
+
+            self.characters
+                .lock()
+                .unwrap()
+                .retain(|(id, _), _| *id != realm_id);
         }
     }
 }
@@ -113,8 +372,37 @@ impl RealmListImpl {
 impl RealmListProvider for RealmListImpl {
     fn get_realm_list(
         &mut self,
-        _message: &CMD_AUTH_LOGON_CHALLENGE_Client,
+        message: &CMD_AUTH_LOGON_CHALLENGE_Client,
     ) -> impl Future<Output = Vec<Realm>> + Send {
-        async move { self.realms.lock().unwrap().clone() }
+        let mut realms = self
+            .realms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let mut realm = entry.realm.clone();
+
+                if entry.is_offline(self.heartbeat_timeout) {
+                    realm.flag = Realm_RealmFlag::new(
+                        entry.flags | RealmFlag::OFFLINE,
+                        entry.specify_build.clone(),
+                    );
+                }
+
+                realm
+            })
+            .collect::<Vec<_>>();
+
+        let characters = self.characters.lock().unwrap();
+        let name = message.account_name.to_uppercase();
+        for realm in &mut realms {
+            realm.number_of_characters_on_realm = characters
+                .get(&(realm.realm_id, name.clone()))
+                .copied()
+                .unwrap_or(0);
+        }
+        drop(characters);
+
+        async move { realms }
     }
 }
@@ -0,0 +1,155 @@
+use crate::reply::PeerAddress;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+use warthog_messages::Permissions;
+
+/// Permissions that need an address from the admin allow list.
+const ADMIN_PERMISSIONS: Permissions = Permissions::new(
+    Permissions::USER_MANAGEMENT.as_int()
+        | Permissions::QUERIES.as_int()
+        | Permissions::REALM_ADMIN.as_int(),
+);
+
+/// Range of IP addresses, such as `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_length: u8,
+}
+
+impl Cidr {
+    /// Returns [`None`] if `prefix_length` is longer than the address.
+    ///
+    /// Ranges of IPv4 addresses mapped to IPv6, such as `::ffff:10.0.0.0/104`, become the IPv4 range.
+    pub const fn new(network: IpAddr, prefix_length: u8) -> Option<Self> {
+        if prefix_length > max_prefix_length(network) {
+            return None;
+        }
+
+        if let IpAddr::V6(v6) = network {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                if prefix_length >= 96 {
+                    return Some(Self {
+                        network: IpAddr::V4(v4),
+                        prefix_length: prefix_length - 96,
+                    });
+                }
+            }
+        }
+
+        Some(Self {
+            network,
+            prefix_length,
+        })
+    }
+
+    /// IPv4 addresses mapped to IPv6, as seen on dual stack sockets, are matched as IPv4.
+    /// IPv6 ranges that include the mapped addresses, such as `::/0`, also contain IPv4 addresses.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let (network, address) = match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => (
+                u128::from(u32::from(network)),
+                u128::from(u32::from(address)),
+            ),
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(network), u128::from(address))
+            }
+            (IpAddr::V6(network), IpAddr::V4(address)) => {
+                (u128::from(network), u128::from(address.to_ipv6_mapped()))
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) => return false,
+        };
+
+        if self.prefix_length == 0 {
+            return true;
+        }
+        let shift = max_prefix_length(self.network) - self.prefix_length;
+
+        (network ^ address) >> shift == 0
+    }
+}
+
+const fn max_prefix_length(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Parses `address/prefix_length`, or a single address without a prefix length.
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (s, None),
+        };
+
+        let network = IpAddr::from_str(address).map_err(|e| format!("invalid address: {e}"))?;
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse()
+                .map_err(|e| format!("invalid prefix length: {e}"))?,
+            None => max_prefix_length(network),
+        };
+
+        Self::new(network, prefix_length)
+            .ok_or_else(|| format!("prefix length {prefix_length} is too long for '{network}'"))
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_length)
+    }
+}
+
+/// Which addresses are allowed to connect to the reply server over TCP.
+///
+/// Unix domain sockets are protected by their file permissions instead,
+/// so every connection over them gets all permissions before keys are checked.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReplyAccess {
+    /// Empty allows every address.
+    allow: Vec<Cidr>,
+    /// Empty allows admin operations from every address that is allowed to connect.
+    admin_allow: Vec<Cidr>,
+}
+
+impl ReplyAccess {
+    pub fn new(allow: Vec<Cidr>, admin_allow: Vec<Cidr>) -> Self {
+        Self { allow, admin_allow }
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.allow.is_empty()
+    }
+
+    /// Most permissions a connection from `peer` can have, or [`None`] if it must be closed.
+    ///
+    /// Addresses that are only in the admin list are also allowed to connect.
+    /// Unix domain socket peers always get [`Permissions::ALL`].
+    pub fn permissions(&self, peer: &PeerAddress) -> Option<Permissions> {
+        let address = match peer {
+            PeerAddress::Tcp(address) => address.ip(),
+            #[cfg(unix)]
+            PeerAddress::Unix(_) => return Some(Permissions::ALL),
+        };
+
+        let in_allow = self.allow.iter().any(|a| a.contains(address));
+        let in_admin_allow = self.admin_allow.iter().any(|a| a.contains(address));
+        let allowed = self.allow.is_empty() || in_allow;
+
+        if in_admin_allow || (allowed && self.admin_allow.is_empty()) {
+            Some(Permissions::ALL)
+        } else if allowed {
+            Some(Permissions::new(
+                Permissions::ALL.as_int() & !ADMIN_PERMISSIONS.as_int(),
+            ))
+        } else {
+            None
+        }
+    }
+}
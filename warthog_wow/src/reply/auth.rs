@@ -0,0 +1,232 @@
+use crate::reply::ReplyStream;
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+use tracing::{info, warn};
+use warthog_messages::{
+    random_challenge, server_proof, verify_client_proof, AuthAccepted, ClientOpcodes, MessageError,
+    Permissions, RegisterRealmError, ServerOpcodes, MAX_KEY_NAME_LENGTH,
+};
+
+/// Pre-shared key that a world server or admin tool uses to connect to the reply server.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ReplyKey {
+    pub name: String,
+    pub key: Vec<u8>,
+    pub permissions: Permissions,
+}
+
+impl Debug for ReplyKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplyKey")
+            .field("name", &self.name)
+            .field("permissions", &self.permissions)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parses `name:permissions:key`, where permissions is a comma separated list of
+/// `session_keys`, `realm`, `users`, `queries`, `realm_admin` or `all`.
+impl FromStr for ReplyKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(name), Some(permissions), Some(key)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected 'name:permissions:key'".to_string());
+        };
+
+        if name.is_empty() || key.is_empty() {
+            return Err("name and key can not be empty".to_string());
+        }
+        if name.len() > MAX_KEY_NAME_LENGTH {
+            return Err(format!(
+                "name can not be longer than {MAX_KEY_NAME_LENGTH} bytes"
+            ));
+        }
+
+        let mut p = Permissions::NONE;
+        for permission in permissions.split(',') {
+            p = p | match permission {
+                "session_keys" => Permissions::SESSION_KEYS,
+                "realm" => Permissions::REALM,
+                "users" => Permissions::USER_MANAGEMENT,
+                "queries" => Permissions::QUERIES,
+                "realm_admin" => Permissions::REALM_ADMIN,
+                "all" => Permissions::ALL,
+                v => return Err(format!("invalid permission '{v}'")),
+            };
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            key: key.as_bytes().to_vec(),
+            permissions: p,
+        })
+    }
+}
+
+/// Connection that has passed [`authenticate`].
+#[derive(Debug)]
+pub(crate) struct Authenticated {
+    pub permissions: Permissions,
+    /// Name of the key that the connection authenticated with, [`None`] if the reply server is insecure.
+    pub key_name: Option<String>,
+}
+
+/// Challenge-response handshake at the start of a connection.
+///
+/// The permissions of the key are limited to `allowed`, which depends on the address of the connection.
+/// `version` is the negotiated protocol version.
+///
+/// Returns [`None`] if the connection should be closed.
+#[tracing::instrument(skip(keys))]
+pub(crate) async fn authenticate(
+    mut stream: &mut impl ReplyStream,
+    version: u16,
+    keys: &[ReplyKey],
+    allowed: Permissions,
+) -> Result<Option<Authenticated>, MessageError> {
+    if keys.is_empty() {
+        return Ok(Some(Authenticated {
+            permissions: allowed,
+            key_name: None,
+        }));
+    }
+
+    let (request_id, ServerOpcodes::RequestAuthChallenge) =
+        ServerOpcodes::tokio_read_with_version(&mut stream, version).await?
+    else {
+        warn!("connection did not start with auth challenge");
+        return Ok(None);
+    };
+
+    let server_challenge = random_challenge();
+    ClientOpcodes::AuthChallenge { server_challenge }
+        .tokio_write_with_version(version, request_id, &mut stream)
+        .await?;
+
+    let (
+        request_id,
+        ServerOpcodes::AuthProof {
+            name,
+            client_challenge,
+            client_proof,
+        },
+    ) = ServerOpcodes::tokio_read_with_version(&mut stream, version).await?
+    else {
+        warn!("connection did not send auth proof");
+        return Ok(None);
+    };
+
+    let key = keys.iter().find(|a| {
+        a.name == name
+            && verify_client_proof(
+                &a.key,
+                &name,
+                &server_challenge,
+                &client_challenge,
+                &client_proof,
+            )
+    });
+
+    let Some(key) = key else {
+        ClientOpcodes::AuthProofReply { accepted: None }
+            .tokio_write_with_version(version, request_id, &mut stream)
+            .await?;
+
+        warn!(name, "invalid auth proof");
+        return Ok(None);
+    };
+
+    let permissions = key.permissions & allowed;
+    let server_proof = server_proof(&key.key, &name, &server_challenge, &client_challenge)
+        .expect("verified names are never too long");
+    ClientOpcodes::AuthProofReply {
+        accepted: Some(AuthAccepted {
+            permissions,
+            server_proof,
+        }),
+    }
+    .tokio_write_with_version(version, request_id, &mut stream)
+    .await?;
+
+    info!(name, ?permissions, "authenticated connection");
+    Ok(Some(Authenticated {
+        permissions,
+        key_name: Some(name),
+    }))
+}
+
+pub(crate) const fn required_permissions(message: &ServerOpcodes) -> Permissions {
+    match message {
+        ServerOpcodes::RequestSessionKey { .. } | ServerOpcodes::SubscribeSessions => {
+            Permissions::SESSION_KEYS
+        }
+        ServerOpcodes::CharacterAmountAnswer { .. }
+        | ServerOpcodes::RegisterRealm { .. }
+        | ServerOpcodes::UpdateRealm { .. }
+        | ServerOpcodes::Heartbeat
+        | ServerOpcodes::AccountOnline { .. } => Permissions::REALM,
+        ServerOpcodes::AddUser { .. }
+        | ServerOpcodes::AddUserWithVerifier { .. }
+        | ServerOpcodes::RemoveUser { .. }
+        | ServerOpcodes::ModifyUser { .. }
+        | ServerOpcodes::RevokeSession { .. }
+        | ServerOpcodes::KickAccount { .. } => Permissions::USER_MANAGEMENT,
+        ServerOpcodes::ListAccounts { .. }
+        | ServerOpcodes::ListSessions { .. }
+        | ServerOpcodes::ListRealms { .. } => Permissions::QUERIES,
+        ServerOpcodes::RequestAuthChallenge | ServerOpcodes::AuthProof { .. } => Permissions::NONE,
+    }
+}
+
+/// Failure reply for requests that the connection is not allowed to make.
+pub(crate) fn permission_denied_reply(message: ServerOpcodes) -> Option<ClientOpcodes> {
+    Some(match message {
+        ServerOpcodes::RequestSessionKey { name } => ClientOpcodes::SessionKeyAnswer {
+            name,
+            session_key: None,
+        },
+        ServerOpcodes::RegisterRealm { .. } => ClientOpcodes::RegisterRealmReply {
+            result: Err(RegisterRealmError::PermissionDenied),
+        },
+        ServerOpcodes::UpdateRealm { .. } => ClientOpcodes::UpdateRealmReply {
+            result: Err(RegisterRealmError::PermissionDenied),
+        },
+        ServerOpcodes::SubscribeSessions => {
+            ClientOpcodes::SubscribeSessionsReply { success: false }
+        }
+        ServerOpcodes::AddUser { name, .. } | ServerOpcodes::AddUserWithVerifier { name, .. } => {
+            ClientOpcodes::AddUserReply {
+                name,
+                success: false,
+            }
+        }
+        ServerOpcodes::RemoveUser { name } => ClientOpcodes::RemoveUserReply {
+            name,
+            success: false,
+        },
+        ServerOpcodes::ModifyUser { name, .. } => ClientOpcodes::ModifyUserReply {
+            name,
+            success: false,
+        },
+        ServerOpcodes::RevokeSession { name } => ClientOpcodes::RevokeSessionReply {
+            name,
+            success: false,
+        },
+        ServerOpcodes::KickAccount { name } => ClientOpcodes::KickAccountReply {
+            name,
+            session_revoked: false,
+            disconnected: false,
+        },
+        ServerOpcodes::ListAccounts { .. } => ClientOpcodes::ListAccountsReply { accounts: None },
+        ServerOpcodes::ListSessions { .. } => ClientOpcodes::ListSessionsReply { sessions: None },
+        ServerOpcodes::ListRealms { .. } => ClientOpcodes::ListRealmsReply { realms: None },
+        ServerOpcodes::CharacterAmountAnswer { .. }
+        | ServerOpcodes::Heartbeat
+        | ServerOpcodes::AccountOnline { .. }
+        | ServerOpcodes::RequestAuthChallenge
+        | ServerOpcodes::AuthProof { .. } => return None,
+    })
+}
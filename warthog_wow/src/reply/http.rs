@@ -0,0 +1,586 @@
+use crate::realm_list::RealmListImpl;
+use crate::reply::access::ReplyAccess;
+use crate::reply::auth::required_permissions;
+use crate::reply::online::OnlineAccounts;
+use crate::reply::{
+    add_user_request, add_user_with_verifier_request, kick_account_request, list_accounts_request,
+    list_realms_request, list_sessions_request, modify_user_request, remove_user_request,
+    revoke_session_request, session_key_request, stopped, update_realm_request, PeerAddress,
+    ReplyKey,
+};
+use crate::sessions::NotifyingKeyStorage;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{error, warn};
+use warthog_lib::{CredentialProvider, KeyStorage, PASSWORD_VERIFIER_LENGTH, SALT_LENGTH};
+use warthog_messages::{
+    ClientOpcodes, MatrixCardData, Modification, Permissions, RealmFlags, ServerOpcodes,
+    MAX_LIST_LIMIT,
+};
+
+/// Everything that the HTTP gateway shares with the reply server.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpGateway<K, C> {
+    pub users: NotifyingKeyStorage<K>,
+    pub realm: RealmListImpl,
+    pub credentials: C,
+    pub online: OnlineAccounts,
+    pub access: ReplyAccess,
+    pub keys: Arc<[ReplyKey]>,
+}
+
+/// Serves JSON versions of the reply messages until `should_run` is false or the listener fails.
+///
+/// Every request is turned into a [`ServerOpcodes`] and handled by the same functions as reply connections,
+/// so both behave the same.
+pub(crate) async fn serve_http<K: KeyStorage, C: CredentialProvider>(
+    listener: TcpListener,
+    gateway: HttpGateway<K, C>,
+    should_run: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    let router = Router::new()
+        .route("/users", get(list_accounts).post(add_user))
+        .route("/users/:name", patch(modify_user).delete(remove_user))
+        .route("/users/:name/kick", post(kick_account))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:name", get(session_key).delete(revoke_session))
+        .route("/realms", get(list_realms))
+        .route("/realms/:realm_id", patch(update_realm))
+        .with_state(gateway);
+
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(stopped(should_run))
+    .await
+}
+
+impl<K: KeyStorage, C: CredentialProvider> HttpGateway<K, C> {
+    /// Permissions of the caller, which are the same as for a reply connection with the same address and key.
+    ///
+    /// Keys are sent as `Authorization: Bearer NAME:KEY`.
+    fn caller_permissions(
+        &self,
+        peer: SocketAddr,
+        headers: &HeaderMap,
+    ) -> Result<Permissions, (StatusCode, &'static str)> {
+        let Some(allowed) = self.access.permissions(&PeerAddress::Tcp(peer)) else {
+            warn!(%peer, "http request from address that is not allowed");
+            return Err((StatusCode::FORBIDDEN, "address is not allowed"));
+        };
+
+        if self.keys.is_empty() {
+            return Ok(allowed);
+        }
+
+        let (name, key) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|a| a.to_str().ok())
+            .and_then(|a| a.strip_prefix("Bearer "))
+            .and_then(|a| a.split_once(':'))
+            .unwrap_or_default();
+        // Every key is compared so that the time taken does not reveal which names exist
+        let key = self.keys.iter().fold(None, |found, a| {
+            let matches = constant_time_eq(a.name.as_bytes(), name.as_bytes())
+                & constant_time_eq(&a.key, key.as_bytes());
+            found.or(matches.then_some(a))
+        });
+
+        match key {
+            Some(key) => Ok(key.permissions & allowed),
+            None => {
+                warn!(%peer, "http request with invalid key");
+                Err((StatusCode::UNAUTHORIZED, "invalid key"))
+            }
+        }
+    }
+
+    /// Handles `message` for the caller.
+    ///
+    /// `realm_id` is the realm that [`ServerOpcodes::UpdateRealm`] changes,
+    /// since HTTP requests do not have a realm registered like reply connections.
+    async fn request(
+        &self,
+        peer: SocketAddr,
+        headers: &HeaderMap,
+        message: ServerOpcodes,
+        realm_id: Option<u8>,
+    ) -> Response {
+        let permissions = match self.caller_permissions(peer, headers) {
+            Ok(permissions) => permissions,
+            Err((status, message)) => return error(status, message),
+        };
+
+        let required = match message {
+            // Reply connections can only change their own realm, but any realm can be given here
+            ServerOpcodes::UpdateRealm { .. } => Permissions::REALM_ADMIN,
+            _ => required_permissions(&message),
+        };
+        if !permissions.contains(required) {
+            warn!(?required, ?permissions, "permission denied");
+            return error(StatusCode::FORBIDDEN, "permission denied");
+        }
+
+        let mut users = self.users.clone();
+        let mut realm = self.realm.clone();
+        let mut credentials = self.credentials.clone();
+
+        let reply = match message {
+            ServerOpcodes::RequestSessionKey { name } => {
+                session_key_request(&mut users, name).await
+            }
+            ServerOpcodes::RevokeSession { name } => revoke_session_request(&mut users, name).await,
+            ServerOpcodes::KickAccount { name } => {
+                kick_account_request(&mut users, &self.online, name).await
+            }
+            ServerOpcodes::AddUser { name, password } => {
+                add_user_request(&mut credentials, name, &password).await
+            }
+            ServerOpcodes::AddUserWithVerifier {
+                name,
+                salt,
+                password_verifier,
+            } => {
+                add_user_with_verifier_request(&mut credentials, name, salt, password_verifier)
+                    .await
+            }
+            ServerOpcodes::RemoveUser { name } => {
+                remove_user_request(&mut credentials, &mut users, name).await
+            }
+            ServerOpcodes::ModifyUser {
+                name,
+                password,
+                pin,
+                matrix_card,
+                account_flags,
+            } => {
+                modify_user_request(
+                    &mut credentials,
+                    name,
+                    password,
+                    pin,
+                    matrix_card,
+                    account_flags,
+                )
+                .await
+            }
+            ServerOpcodes::ListAccounts { offset, limit } => {
+                list_accounts_request(&mut credentials, offset, limit).await
+            }
+            ServerOpcodes::ListSessions { offset, limit } => {
+                list_sessions_request(&mut users, offset, limit).await
+            }
+            ServerOpcodes::ListRealms { offset, limit } => {
+                list_realms_request(&realm, offset, limit)
+            }
+            ServerOpcodes::UpdateRealm {
+                name,
+                address,
+                population,
+                locked,
+                flags,
+                category,
+                realm_type,
+            } => {
+                update_realm_request(
+                    &mut realm, realm_id, name, address, population, locked, flags, category,
+                    realm_type,
+                )
+                .await
+            }
+            // Only make sense on a connection that stays open
+            ServerOpcodes::CharacterAmountAnswer { .. }
+            | ServerOpcodes::RegisterRealm { .. }
+            | ServerOpcodes::Heartbeat
+            | ServerOpcodes::SubscribeSessions
+            | ServerOpcodes::AccountOnline { .. }
+            | ServerOpcodes::RequestAuthChallenge
+            | ServerOpcodes::AuthProof { .. } => {
+                return error(StatusCode::NOT_FOUND, "not available over http");
+            }
+        };
+
+        reply_json(reply)
+    }
+}
+
+/// Same fields as the reply message.
+fn reply_json(reply: ClientOpcodes) -> Response {
+    let value = match reply {
+        ClientOpcodes::SessionKeyAnswer { name, session_key } => json!({
+            "name": name,
+            "session_key": session_key.map(|a| to_hex(&a)),
+        }),
+        ClientOpcodes::AddUserReply { name, success }
+        | ClientOpcodes::RemoveUserReply { name, success }
+        | ClientOpcodes::ModifyUserReply { name, success }
+        | ClientOpcodes::RevokeSessionReply { name, success } => json!({
+            "name": name,
+            "success": success,
+        }),
+        ClientOpcodes::KickAccountReply {
+            name,
+            session_revoked,
+            disconnected,
+        } => json!({
+            "name": name,
+            "session_revoked": session_revoked,
+            "disconnected": disconnected,
+        }),
+        ClientOpcodes::UpdateRealmReply { result: Ok(()) } => json!({ "success": true }),
+        ClientOpcodes::UpdateRealmReply {
+            result: Err(reason),
+        } => json!({
+            "success": false,
+            "reason": reason.as_int(),
+        }),
+        ClientOpcodes::ListAccountsReply { accounts } => json!(accounts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| json!({
+                "name": a.name,
+                "account_flags": a.account_flags,
+                "has_pin": a.has_pin,
+                "has_matrix_card": a.has_matrix_card,
+            }))
+            .collect::<Vec<_>>()),
+        ClientOpcodes::ListSessionsReply { sessions } => json!(sessions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| json!({
+                "name": a.name,
+                "address": a.address,
+                "login_time": a.login_time,
+            }))
+            .collect::<Vec<_>>()),
+        ClientOpcodes::ListRealmsReply { realms } => json!(realms
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| json!({
+                "realm_id": a.realm_id,
+                "name": a.name,
+                "address": a.address,
+                "population": a.population,
+                "flags": a.flags,
+                "category": a.category,
+                "realm_type": a.realm_type,
+                "online": a.online,
+                "peer_address": a.peer_address,
+            }))
+            .collect::<Vec<_>>()),
+        reply => {
+            error!(?reply, "http request answered with unexpected reply");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "unexpected reply");
+        }
+    };
+
+    Json(value).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Compares digests so that inputs of different lengths take as long as equal ones.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(&b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|a| format!("{a:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.bytes().all(|a| a.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn from_hex_array<const N: usize>(s: &str) -> Option<[u8; N]> {
+    from_hex(s)?.try_into().ok()
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    #[serde(default)]
+    offset: u32,
+    #[serde(default = "max_list_limit")]
+    limit: u8,
+}
+
+const fn max_list_limit() -> u8 {
+    MAX_LIST_LIMIT
+}
+
+/// Either a password or a salt and verifier computed by the caller, as hex.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AddUser {
+    Password {
+        name: String,
+        password: String,
+    },
+    Verifier {
+        name: String,
+        salt: String,
+        password_verifier: String,
+    },
+}
+
+/// Fields that are left out are unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModifyUser {
+    password: Option<String>,
+    pin: Option<Change<u64>>,
+    matrix_card: Option<Change<MatrixCardJson>>,
+    account_flags: Option<u32>,
+}
+
+/// `"remove"` or `{"set": value}`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Change<T> {
+    Remove,
+    Set(T),
+}
+
+fn modification<T>(change: Option<Change<T>>) -> Modification<T> {
+    match change {
+        None => Modification::Unchanged,
+        Some(Change::Remove) => Modification::Removed,
+        Some(Change::Set(value)) => Modification::Set(value),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixCardJson {
+    challenge_count: u8,
+    /// Hex encoded.
+    data: String,
+}
+
+/// Fields that are left out are unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateRealm {
+    name: Option<String>,
+    address: Option<String>,
+    population: Option<f32>,
+    locked: Option<bool>,
+    flags: Option<RealmFlagsJson>,
+    category: Option<u8>,
+    realm_type: Option<u8>,
+}
+
+/// The version is only used if `flags` has `SPECIFY_BUILD` set.
+#[derive(Debug, Deserialize)]
+struct RealmFlagsJson {
+    flags: u8,
+    #[serde(default)]
+    version_major: u8,
+    #[serde(default)]
+    version_minor: u8,
+    #[serde(default)]
+    version_patch: u8,
+    #[serde(default)]
+    version_build: u16,
+}
+
+async fn list_accounts<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(page): Query<Page>,
+) -> Response {
+    let message = ServerOpcodes::ListAccounts {
+        offset: page.offset,
+        limit: page.limit,
+    };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn add_user<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(user): Json<AddUser>,
+) -> Response {
+    let message = match user {
+        AddUser::Password { name, password } => ServerOpcodes::AddUser { name, password },
+        AddUser::Verifier {
+            name,
+            salt,
+            password_verifier,
+        } => {
+            let (Some(salt), Some(password_verifier)) = (
+                from_hex_array::<{ SALT_LENGTH as usize }>(&salt),
+                from_hex_array::<{ PASSWORD_VERIFIER_LENGTH as usize }>(&password_verifier),
+            ) else {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "salt and password verifier must be 32 hex encoded bytes",
+                );
+            };
+
+            ServerOpcodes::AddUserWithVerifier {
+                name,
+                salt,
+                password_verifier,
+            }
+        }
+    };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn modify_user<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(changes): Json<ModifyUser>,
+) -> Response {
+    let matrix_card = match changes.matrix_card {
+        Some(Change::Set(MatrixCardJson {
+            challenge_count,
+            data,
+        })) => match from_hex(&data) {
+            Some(data) => Some(Change::Set(MatrixCardData {
+                challenge_count,
+                data,
+            })),
+            None => return error(StatusCode::BAD_REQUEST, "matrix card data is not hex"),
+        },
+        Some(Change::Remove) => Some(Change::Remove),
+        None => None,
+    };
+
+    let message = ServerOpcodes::ModifyUser {
+        name,
+        password: changes.password,
+        pin: modification(changes.pin),
+        matrix_card: modification(matrix_card),
+        account_flags: changes.account_flags,
+    };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn remove_user<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let message = ServerOpcodes::RemoveUser { name };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn kick_account<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let message = ServerOpcodes::KickAccount { name };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn list_sessions<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(page): Query<Page>,
+) -> Response {
+    let message = ServerOpcodes::ListSessions {
+        offset: page.offset,
+        limit: page.limit,
+    };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn session_key<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let message = ServerOpcodes::RequestSessionKey { name };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn revoke_session<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let message = ServerOpcodes::RevokeSession { name };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn list_realms<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(page): Query<Page>,
+) -> Response {
+    let message = ServerOpcodes::ListRealms {
+        offset: page.offset,
+        limit: page.limit,
+    };
+
+    gateway.request(peer, &headers, message, None).await
+}
+
+async fn update_realm<K: KeyStorage, C: CredentialProvider>(
+    State(gateway): State<HttpGateway<K, C>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(realm_id): Path<u8>,
+    Json(update): Json<UpdateRealm>,
+) -> Response {
+    let message = ServerOpcodes::UpdateRealm {
+        name: update.name,
+        address: update.address,
+        population: update.population,
+        locked: update.locked,
+        flags: update.flags.map(|a| RealmFlags {
+            flags: a.flags,
+            version_major: a.version_major,
+            version_minor: a.version_minor,
+            version_patch: a.version_patch,
+            version_build: a.version_build,
+        }),
+        category: update.category,
+        realm_type: update.realm_type,
+    };
+
+    gateway
+        .request(peer, &headers, message, Some(realm_id))
+        .await
+}
@@ -0,0 +1,188 @@
+use crate::ReplySocketOptions;
+use std::fmt::{Display, Formatter};
+use std::future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::info;
+
+/// Where a reply connection came from.
+#[derive(Debug, Clone)]
+pub(crate) enum PeerAddress {
+    Tcp(SocketAddr),
+    /// Path of the socket that was connected to, since clients of Unix domain sockets are usually unnamed.
+    #[cfg(unix)]
+    Unix(Arc<Path>),
+}
+
+impl Display for PeerAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerAddress::Tcp(address) => address.fmt(f),
+            #[cfg(unix)]
+            PeerAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Incoming {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+/// Accepts reply connections over TCP, a Unix domain socket or both.
+#[derive(Debug)]
+pub(crate) struct ReplyListener {
+    tcp: Option<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<UnixSocket>,
+}
+
+impl ReplyListener {
+    pub async fn bind(
+        address: Option<SocketAddr>,
+        socket: Option<&ReplySocketOptions>,
+    ) -> io::Result<Self> {
+        if address.is_none() && socket.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "reply server needs an address or a unix socket",
+            ));
+        }
+
+        let tcp = match address {
+            Some(address) => Some(TcpListener::bind(address).await?),
+            None => None,
+        };
+
+        #[cfg(unix)]
+        let unix = match socket {
+            Some(socket) => Some(bind_unix(socket)?),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if socket.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets are not supported on this platform",
+            ));
+        }
+
+        Ok(Self {
+            tcp,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    pub async fn accept(&self) -> io::Result<(Incoming, PeerAddress)> {
+        let tcp = async {
+            match &self.tcp {
+                Some(listener) => {
+                    let (stream, address) = listener.accept().await?;
+                    Ok((Incoming::Tcp(stream), PeerAddress::Tcp(address)))
+                }
+                None => future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            accepted = tcp => accepted,
+            accepted = self.accept_unix() => accepted,
+        }
+    }
+
+    #[cfg(unix)]
+    async fn accept_unix(&self) -> io::Result<(Incoming, PeerAddress)> {
+        match &self.unix {
+            Some(socket) => {
+                let (stream, _) = socket.listener.accept().await?;
+                Ok((
+                    Incoming::Unix(stream),
+                    PeerAddress::Unix(socket.path.clone()),
+                ))
+            }
+            None => future::pending().await,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn accept_unix(&self) -> io::Result<(Incoming, PeerAddress)> {
+        future::pending().await
+    }
+}
+
+/// Unix domain socket listener that removes its socket file when dropped.
+#[cfg(unix)]
+#[derive(Debug)]
+struct UnixSocket {
+    listener: tokio::net::UnixListener,
+    path: Arc<Path>,
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Replaces any socket left behind by a previous run, then restricts who can connect with `socket.mode`.
+///
+/// The socket is bound inside a directory only the current user can access and moved into place after
+/// its mode is set, so nobody can connect before the mode applies.
+#[cfg(unix)]
+fn bind_unix(socket: &ReplySocketOptions) -> io::Result<UnixSocket> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(&socket.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' exists and is not a socket", socket.path.display()),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let Some(file_name) = socket.path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a file path", socket.path.display()),
+        ));
+    };
+    let mut temporary_name = std::ffi::OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(format!(".{}", std::process::id()));
+    let directory = socket.path.with_file_name(temporary_name);
+
+    std::fs::DirBuilder::new().mode(0o700).create(&directory)?;
+    let temporary_path = directory.join("s");
+    let bound = tokio::net::UnixListener::bind(&temporary_path).and_then(|listener| {
+        std::fs::set_permissions(
+            &temporary_path,
+            std::fs::Permissions::from_mode(socket.mode),
+        )?;
+        std::fs::rename(&temporary_path, &socket.path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&directory);
+    let listener = bound?;
+
+    info!(
+        path = %socket.path.display(),
+        mode = %format!("{:o}", socket.mode),
+        "listening on unix socket"
+    );
+
+    Ok(UnixSocket {
+        listener,
+        path: socket.path.as_path().into(),
+    })
+}
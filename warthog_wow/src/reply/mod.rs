@@ -1,130 +1,611 @@
-use crate::realm_list::RealmListImpl;
+mod access;
+mod auth;
+mod http;
+mod listener;
+mod online;
+
+use crate::realm_list::{RealmListImpl, RealmUpdate};
+use crate::reply::auth::{
+    authenticate, permission_denied_reply, required_permissions, Authenticated,
+};
+use crate::reply::http::{serve_http, HttpGateway};
+use crate::reply::listener::{Incoming, ReplyListener};
+use crate::reply::online::OnlineAccounts;
+use crate::sessions::NotifyingKeyStorage;
+use crate::{ReplySocketOptions, ReplyTlsOptions};
+use std::fmt::Debug;
+use std::future::Future;
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{info, trace};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, WriteHalf};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::task::JoinSet;
+use tracing::{error, info, trace, warn};
 use warthog_lib::{
-    CredentialProvider, KeyStorage, Population, RealmCategory, RealmFlag, RealmType,
-    Realm_RealmFlag, Realm_RealmFlag_SpecifyBuild, Version,
+    AccountFlag, CredentialProvider, KeyStorage, MatrixCard, MatrixCardOptions, PinCode,
+    Population, RealmCategory, RealmFlag, RealmType, Realm_RealmFlag_SpecifyBuild,
+    UserModification, Version, LARGE_SAFE_PRIME_LITTLE_ENDIAN, PASSWORD_VERIFIER_LENGTH,
+    SALT_LENGTH,
 };
-use warthog_messages::{ClientOpcodes, MessageError, ServerOpcodes};
+use warthog_messages::{
+    tls, tokio_accept_version, AccountSummary, ClientOpcodes, MatrixCardData, MessageError,
+    Modification, Permissions, RealmFlags, RealmSummary, RegisterRealmError, ServerOpcodes,
+    SessionChange, SessionSummary, MAX_LIST_LIMIT, REQUEST_ID_VERSION,
+};
+
+pub use access::Cidr;
+pub(crate) use access::ReplyAccess;
+pub use auth::ReplyKey;
+pub(crate) use listener::PeerAddress;
 
-#[tracing::instrument(skip(users, realm))]
+/// Any stream that messages can be read from and written to, either plain TCP or TLS.
+pub(crate) trait ReplyStream:
+    AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static
+{
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static> ReplyStream for T {}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(users, realm, reply_keys))]
 pub(crate) async fn start_reply_server(
-    users: impl KeyStorage,
+    users: NotifyingKeyStorage<impl KeyStorage>,
     realm: RealmListImpl,
     credentials: impl CredentialProvider,
-    reply_address: SocketAddr,
+    reply_address: Option<SocketAddr>,
+    reply_socket: Option<ReplySocketOptions>,
+    reply_http_address: Option<SocketAddr>,
+    reply_access: ReplyAccess,
+    reply_keys: Vec<ReplyKey>,
+    reply_insecure: bool,
+    reply_tls: Option<ReplyTlsOptions>,
+    realm_offline_grace_period: Option<Duration>,
+    should_run: Arc<AtomicBool>,
 ) -> std::io::Result<()> {
-    let listener = TcpListener::bind(reply_address).await?;
-    info!("reply server started");
+    if reply_keys.is_empty() && !reply_insecure {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "reply server needs at least one key unless it is insecure",
+        ));
+    }
+
+    let acceptor = match reply_tls {
+        Some(tls) => Some(tls::acceptor(
+            &tls.certificate,
+            &tls.private_key,
+            tls.client_ca.as_deref(),
+        )?),
+        None => {
+            if reply_address.is_some() {
+                warn!(
+                    "reply server is not using TLS, session keys and passwords are sent in plaintext"
+                );
+            }
+            None
+        }
+    };
+
+    let listener = ReplyListener::bind(reply_address, reply_socket.as_ref()).await?;
+    info!(tls = acceptor.is_some(), "reply server started");
+
+    if reply_keys.is_empty() {
+        warn!("reply server is insecure, all connections are allowed to do everything");
+    }
+    if (reply_address.is_some() || reply_http_address.is_some()) && reply_access.is_unrestricted() {
+        warn!("no reply allow list configured, connections are accepted from any address");
+    }
+    let reply_keys: Arc<[ReplyKey]> = reply_keys.into();
+    let online = OnlineAccounts::new();
+
+    if let Some(reply_http_address) = reply_http_address {
+        // Requests and replies are not encrypted
+        if !reply_http_address.ip().to_canonical().is_loopback() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "http gateway can only listen on a loopback address",
+            ));
+        }
+
+        let listener = TcpListener::bind(reply_http_address).await?;
+        info!(%reply_http_address, "http gateway started");
+
+        let gateway = HttpGateway {
+            users: users.clone(),
+            realm: realm.clone(),
+            credentials: credentials.clone(),
+            online: online.clone(),
+            access: reply_access.clone(),
+            keys: reply_keys.clone(),
+        };
+        let should_run = should_run.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_http(listener, gateway, should_run).await {
+                error!(?e, "http gateway stopped");
+            }
+        });
+    }
+    let mut next_connection: u64 = 0;
+    // Dropping the set aborts open connections, as well as realms waiting for their grace period
+    let mut connections = JoinSet::new();
+    let stopped = stopped(should_run);
+    tokio::pin!(stopped);
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer_address) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            // Finished connections are removed so that the set does not keep growing
+            Some(_) = connections.join_next() => continue,
+            () = &mut stopped => {
+                info!("reply server stopped");
+                return Ok(());
+            }
+        };
+
+        // Closed before anything is read, so that unknown hosts can not reach the handshake
+        let Some(allowed) = reply_access.permissions(&peer_address) else {
+            warn!(%peer_address, "connection from address that is not allowed");
+            continue;
+        };
+
+        let connection = next_connection;
+        next_connection += 1;
 
         let users = users.clone();
         let mut realm = realm.clone();
         let credentials = credentials.clone();
-        tokio::spawn(async move {
+        let reply_keys = reply_keys.clone();
+        let acceptor = acceptor.clone();
+        let online = online.clone();
+        connections.spawn(async move {
             let mut realm_id = None;
 
-            let peer_address = stream.peer_addr();
-            match handle_reply(
+            // Unix domain sockets are protected by their file permissions instead of TLS
+            let stream: Box<dyn ReplyStream> = match (stream, acceptor) {
+                (Incoming::Tcp(stream), Some(acceptor)) => {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => Box::new(stream),
+                        Ok(Err(e)) => {
+                            warn!(%peer_address, ?e, "tls handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            warn!(%peer_address, "tls handshake timed out");
+                            return;
+                        }
+                    }
+                }
+                (Incoming::Tcp(stream), None) => Box::new(stream),
+                #[cfg(unix)]
+                (Incoming::Unix(stream), _) => Box::new(stream),
+            };
+
+            let result = handle_reply(
                 stream,
                 users,
                 realm.clone(),
-                credentials.clone(),
+                credentials,
+                &reply_keys,
+                &online,
+                connection,
+                peer_address.clone(),
+                allowed,
                 &mut realm_id,
             )
-            .await
-            {
+            .await;
+
+            match result {
                 Ok(_) => {}
                 Err(_) => {
-                    info!(?peer_address, realm_id, "lost connection")
+                    info!(%peer_address, realm_id, "lost connection")
                 }
             }
 
+            online.remove_connection(connection);
+
             if let Some(realm_id) = realm_id {
-                realm.remove_realm(realm_id);
+                match realm_offline_grace_period {
+                    Some(grace_period) => {
+                        let disconnected = realm.disconnect_realm(realm_id);
+                        tokio::time::sleep(grace_period).await;
+                        realm.remove_disconnected_realm(realm_id, disconnected);
+                    }
+                    None => realm.remove_realm(realm_id),
+                }
             }
         });
     }
 }
 
+/// How often `should_run` is checked.
+const SHOULD_RUN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Finishes once `should_run` has been set to `false`.
+async fn stopped(should_run: Arc<AtomicBool>) {
+    while should_run.load(Ordering::SeqCst) {
+        tokio::time::sleep(SHOULD_RUN_INTERVAL).await;
+    }
+}
+
+/// Time that a connection has for the TLS handshake, and then to negotiate the version and authenticate.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum amount of requests from a single connection that are processed at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// Replies together with the request ID they answer.
+type ReplySender = mpsc::Sender<(u32, ClientOpcodes)>;
+
+/// Request ID of messages that are not replies.
+const PUSH_REQUEST_ID: u32 = 0;
+
+/// `connection` is unique for every reply connection.
+///
+/// `allowed` limits the permissions of the connection based on its address.
+#[allow(clippy::too_many_arguments)]
 async fn handle_reply(
-    mut stream: TcpStream,
-    mut users: impl KeyStorage,
+    mut stream: impl ReplyStream,
+    mut users: NotifyingKeyStorage<impl KeyStorage>,
     mut realm: RealmListImpl,
     mut credentials: impl CredentialProvider,
+    reply_keys: &[ReplyKey],
+    online: &OnlineAccounts,
+    connection: u64,
+    peer_address: PeerAddress,
+    allowed: Permissions,
     realm_id: &mut Option<u8>,
 ) -> Result<(), MessageError> {
+    let handshake = async {
+        let version = tokio_accept_version(&mut stream).await?;
+        trace!(version, "negotiated protocol version");
+
+        let authenticated = authenticate(&mut stream, version, reply_keys, allowed).await?;
+        Ok::<_, MessageError>(authenticated.map(|authenticated| (version, authenticated)))
+    };
+    let Ok(handshake) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await else {
+        warn!(%peer_address, "handshake timed out");
+        return Ok(());
+    };
+    let Some((
+        version,
+        Authenticated {
+            permissions,
+            key_name,
+        },
+    )) = handshake?
+    else {
+        return Ok(());
+    };
+    // Replies can only be matched to their requests by ID
+    let in_order = version < REQUEST_ID_VERSION;
+
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (replies, receiver) = mpsc::channel(MAX_CONCURRENT_REQUESTS);
+    let writer_address = peer_address.clone();
+    tokio::spawn(async move {
+        // Requests fail to send their replies once the receiver is dropped
+        if let Err(e) = write_replies(writer, version, receiver).await {
+            warn!(peer_address = %writer_address, ?e, "unable to write reply");
+        }
+    });
+
+    let requests = Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS));
+    // Aborted when the connection is closed
+    let mut subscriptions = JoinSet::new();
+    let mut character_amount_requests = JoinSet::new();
+
     loop {
-        match ServerOpcodes::tokio_read(&mut stream).await {
-            Ok(message) => match message {
-                ServerOpcodes::RequestSessionKey { name } => {
-                    session_key_request(&mut stream, &mut users, name).await?;
+        let (request_id, message) =
+            match ServerOpcodes::tokio_read_with_version(&mut reader, version).await {
+                Ok(message) => message,
+                Err(MessageError::InvalidOpcode(opcode)) => {
+                    // The whole frame has been read, so the connection can keep going
+                    warn!(opcode, "unknown opcode received");
+                    continue;
                 }
-                ServerOpcodes::RegisterRealm {
+                Err(e) => {
+                    return Err(e);
+                }
+            };
+
+        if !permissions.contains(required_permissions(&message)) {
+            warn!(required = ?required_permissions(&message), ?permissions, "permission denied");
+
+            if let Some(reply) = permission_denied_reply(message) {
+                send_reply(&replies, request_id, reply).await?;
+            }
+            continue;
+        }
+
+        match message {
+            ServerOpcodes::RequestSessionKey { name } => {
+                let mut users = users.clone();
+                spawn_request(&requests, &replies, in_order, request_id, async move {
+                    session_key_request(&mut users, name).await
+                })
+                .await;
+            }
+            // Realm messages change the state of the connection, so they are handled in order
+            ServerOpcodes::CharacterAmountAnswer {
+                name,
+                amount_of_characters,
+            } => {
+                character_amount_answer(&mut realm, *realm_id, name, amount_of_characters);
+            }
+            ServerOpcodes::RegisterRealm {
+                name,
+                address,
+                population,
+                locked,
+                flags,
+                category,
+                realm_type,
+                version_major,
+                version_minor,
+                version_patch,
+                version_build,
+                realm_id: requested_realm_id,
+            } => {
+                let flags = RealmFlags {
+                    flags,
+                    version_major,
+                    version_minor,
+                    version_patch,
+                    version_build,
+                };
+
+                let reply = register_realm_request(
+                    &mut realm,
+                    realm_id,
                     name,
                     address,
-                    population,
+                    Population::from(population),
                     locked,
                     flags,
                     category,
                     realm_type,
-                    version_major,
-                    version_minor,
-                    version_patch,
-                    version_build,
-                } => {
-                    let flags = if RealmFlag::new(flags).is_specify_build() {
-                        Realm_RealmFlag::new(
-                            flags,
-                            Some(Realm_RealmFlag_SpecifyBuild {
-                                version: Version {
-                                    major: version_major,
-                                    minor: version_minor,
-                                    patch: version_patch,
-                                    build: version_build,
-                                },
-                            }),
-                        )
-                    } else {
-                        Realm_RealmFlag::new(flags, None)
-                    };
-
-                    register_realm_request(
-                        &mut stream,
-                        &mut realm,
-                        realm_id,
-                        name,
-                        address,
-                        Population::from(population),
-                        locked,
-                        flags,
-                        RealmCategory::try_from(category).unwrap(),
-                        RealmType::try_from(realm_type).unwrap(),
-                    )
-                    .await?;
+                    requested_realm_id,
+                    peer_address.clone(),
+                    key_name.clone(),
+                )
+                .await;
+                send_reply(&replies, request_id, reply).await?;
+
+                if realm_id.is_some() && character_amount_requests.is_empty() {
+                    character_amount_requests.spawn(request_character_amounts(
+                        users.subscribe(),
+                        replies.clone(),
+                    ));
+                }
+            }
+            ServerOpcodes::UpdateRealm {
+                name,
+                address,
+                population,
+                locked,
+                flags,
+                category,
+                realm_type,
+            } => {
+                let reply = update_realm_request(
+                    &mut realm, *realm_id, name, address, population, locked, flags, category,
+                    realm_type,
+                )
+                .await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::Heartbeat => {
+                heartbeat(&mut realm, *realm_id);
+            }
+            ServerOpcodes::SubscribeSessions => {
+                // Subscribed before replying so that no changes are missed after the reply
+                let changes = subscriptions.is_empty().then(|| users.subscribe());
+
+                let reply = ClientOpcodes::SubscribeSessionsReply { success: true };
+                send_reply(&replies, request_id, reply).await?;
+
+                if let Some(changes) = changes {
+                    subscriptions.spawn(push_sessions(changes, replies.clone()));
                 }
-                ServerOpcodes::AddUser { name, password } => {
-                    add_user_request(&mut stream, &mut credentials, name, &password).await?;
+            }
+            ServerOpcodes::AccountOnline {
+                name,
+                online: is_online,
+            } => {
+                // Only accounts that logged in through this auth server can be kicked through it
+                if is_online && users.get_key_for_user(&name).await.is_none() {
+                    warn!(name, "account reported online without a session");
+                } else {
+                    online.set_online(&name, is_online, connection, &replies);
                 }
-                ServerOpcodes::RemoveUser { .. } => {}
-                ServerOpcodes::ModifyUser { .. } => {}
-            },
-            Err(e) => {
-                return Err(e);
+            }
+            // Changes are made in the order they are sent and before any later request is read,
+            // only requests that do not change anything run concurrently
+            ServerOpcodes::RevokeSession { name } => {
+                let reply = revoke_session_request(&mut users, name).await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::KickAccount { name } => {
+                let reply = kick_account_request(&mut users, online, name).await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::AddUser { name, password } => {
+                let reply = add_user_request(&mut credentials, name, &password).await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::AddUserWithVerifier {
+                name,
+                salt,
+                password_verifier,
+            } => {
+                let reply =
+                    add_user_with_verifier_request(&mut credentials, name, salt, password_verifier)
+                        .await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::RemoveUser { name } => {
+                let reply = remove_user_request(&mut credentials, &mut users, name).await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::ModifyUser {
+                name,
+                password,
+                pin,
+                matrix_card,
+                account_flags,
+            } => {
+                let reply = modify_user_request(
+                    &mut credentials,
+                    name,
+                    password,
+                    pin,
+                    matrix_card,
+                    account_flags,
+                )
+                .await;
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::ListAccounts { offset, limit } => {
+                let mut credentials = credentials.clone();
+                spawn_request(&requests, &replies, in_order, request_id, async move {
+                    list_accounts_request(&mut credentials, offset, limit).await
+                })
+                .await;
+            }
+            ServerOpcodes::ListSessions { offset, limit } => {
+                let mut users = users.clone();
+                spawn_request(&requests, &replies, in_order, request_id, async move {
+                    list_sessions_request(&mut users, offset, limit).await
+                })
+                .await;
+            }
+            ServerOpcodes::ListRealms { offset, limit } => {
+                let reply = list_realms_request(&realm, offset, limit);
+                send_reply(&replies, request_id, reply).await?;
+            }
+            ServerOpcodes::RequestAuthChallenge | ServerOpcodes::AuthProof { .. } => {
+                warn!("auth handshake sent after connection was authenticated");
+                return Ok(());
             }
         }
     }
 }
 
-#[tracing::instrument]
-async fn session_key_request(
-    mut stream: &mut TcpStream,
-    users: &mut impl KeyStorage,
-    name: String,
+/// Writes replies in the order that they finish, which is not necessarily the order of the requests.
+async fn write_replies(
+    mut writer: WriteHalf<impl ReplyStream>,
+    version: u16,
+    mut receiver: mpsc::Receiver<(u32, ClientOpcodes)>,
 ) -> Result<(), MessageError> {
+    while let Some((request_id, mut reply)) = receiver.recv().await {
+        reply
+            .tokio_write_with_version(version, request_id, &mut writer)
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn send_reply(
+    replies: &ReplySender,
+    request_id: u32,
+    reply: ClientOpcodes,
+) -> Result<(), MessageError> {
+    replies
+        .send((request_id, reply))
+        .await
+        .map_err(|_| MessageError::Io(std::io::ErrorKind::BrokenPipe.into()))
+}
+
+/// Runs `request` concurrently with other requests, waiting if too many are already running.
+///
+/// If `in_order`, the reply is sent before returning instead,
+/// so that replies are sent in the same order as the requests.
+async fn spawn_request(
+    requests: &Arc<Semaphore>,
+    replies: &ReplySender,
+    in_order: bool,
+    request_id: u32,
+    request: impl Future<Output = ClientOpcodes> + Send + 'static,
+) {
+    if in_order {
+        // The connection has been closed if the writer is gone
+        let _ = replies.send((request_id, request.await)).await;
+        return;
+    }
+
+    let permit = requests
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("semaphore is never closed");
+    let replies = replies.clone();
+
+    tokio::spawn(async move {
+        let reply = request.await;
+        drop(permit);
+
+        // The connection has been closed if the writer is gone
+        let _ = replies.send((request_id, reply)).await;
+    });
+}
+
+/// Sends session changes to a subscribed connection until it is closed.
+async fn push_sessions(
+    mut changes: broadcast::Receiver<(String, SessionChange)>,
+    replies: ReplySender,
+) {
+    loop {
+        let (name, change) = match changes.recv().await {
+            Ok(change) => change,
+            Err(broadcast::error::RecvError::Lagged(amount)) => {
+                warn!(
+                    amount,
+                    "session subscriber fell behind, changes were skipped"
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let message = ClientOpcodes::SessionChanged { name, change };
+        if replies.send((PUSH_REQUEST_ID, message)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Asks the realm of the connection for the amount of characters of every account that logs in,
+/// so that later realm lists can show them.
+async fn request_character_amounts(
+    mut changes: broadcast::Receiver<(String, SessionChange)>,
+    replies: ReplySender,
+) {
+    loop {
+        let name = match changes.recv().await {
+            Ok((_, SessionChange::Revoked)) => continue,
+            Ok((name, _)) => name,
+            Err(broadcast::error::RecvError::Lagged(amount)) => {
+                warn!(
+                    amount,
+                    "logins were skipped when requesting character amounts"
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let message = ClientOpcodes::RequestCharacterAmount { name };
+        if replies.send((PUSH_REQUEST_ID, message)).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[tracing::instrument]
+async fn session_key_request(users: &mut impl KeyStorage, name: String) -> ClientOpcodes {
     trace!("got session key request");
     let session_key = users
         .get_key_for_user(&name)
@@ -134,53 +615,455 @@ async fn session_key_request(
     trace!(?session_key, "looked up key");
 
     ClientOpcodes::SessionKeyAnswer { name, session_key }
-        .tokio_write(&mut stream)
-        .await?;
-    Ok(())
 }
 
+#[tracing::instrument]
+async fn revoke_session_request(users: &mut impl KeyStorage, name: String) -> ClientOpcodes {
+    trace!("got revoke session");
+
+    let success = users.remove_key(&name).await;
+
+    ClientOpcodes::RevokeSessionReply { name, success }
+}
+
+#[tracing::instrument]
+async fn kick_account_request(
+    users: &mut impl KeyStorage,
+    online: &OnlineAccounts,
+    name: String,
+) -> ClientOpcodes {
+    trace!("got kick account");
+
+    let session_revoked = users.remove_key(&name).await;
+    let disconnected = online.kick(&name).await;
+
+    ClientOpcodes::KickAccountReply {
+        name,
+        session_revoked,
+        disconnected,
+    }
+}
+
+#[tracing::instrument]
+fn character_amount_answer(
+    realm: &mut RealmListImpl,
+    realm_id: Option<u8>,
+    name: String,
+    amount_of_characters: u8,
+) {
+    trace!("got character amount");
+
+    let Some(realm_id) = realm_id else {
+        warn!("character amount sent before realm was registered");
+        return;
+    };
+
+    realm.set_number_of_characters(realm_id, &name, amount_of_characters);
+}
+
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument]
 async fn register_realm_request(
-    mut stream: &mut TcpStream,
     realm: &mut RealmListImpl,
     realm_id: &mut Option<u8>,
     name: String,
     address: String,
     population: Population,
     locked: bool,
-    flag: Realm_RealmFlag,
-    category: RealmCategory,
-    realm_type: RealmType,
-) -> Result<(), MessageError> {
+    flags: RealmFlags,
+    category: u8,
+    realm_type: u8,
+    requested_realm_id: Option<u8>,
+    peer_address: PeerAddress,
+    key_name: Option<String>,
+) -> ClientOpcodes {
     trace!("got register realm");
 
-    *realm_id = realm.add_realm(
-        name, address, population, locked, flag, category, realm_type, *realm_id,
-    );
+    let (category, realm_type) = match validate_realm(&address, category, realm_type) {
+        Ok(valid) => valid,
+        Err(e) => {
+            warn!(%e, "invalid realm registration");
+            return ClientOpcodes::RegisterRealmReply {
+                result: Err(register_realm_error(&e)),
+            };
+        }
+    };
+
+    let (flags, specify_build) = realm_flags(flags);
+    *realm_id = realm
+        .add_realm(
+            name,
+            address,
+            population,
+            locked,
+            flags,
+            specify_build,
+            category,
+            realm_type,
+            *realm_id,
+            requested_realm_id,
+            peer_address,
+            key_name,
+        )
+        .await;
 
     ClientOpcodes::RegisterRealmReply {
-        realm_id: *realm_id,
+        result: realm_id.ok_or(RegisterRealmError::RealmIdUnavailable),
     }
-    .tokio_write(&mut stream)
-    .await?;
+}
 
-    Ok(())
+/// Caps `limit` so that the reply fits in a single message.
+fn list_limit(limit: u8) -> u32 {
+    limit.min(MAX_LIST_LIMIT).into()
 }
 
 #[tracing::instrument]
+async fn list_accounts_request(
+    credentials: &mut impl CredentialProvider,
+    offset: u32,
+    limit: u8,
+) -> ClientOpcodes {
+    trace!("got list accounts");
+
+    let accounts = credentials
+        .list_users(offset, list_limit(limit))
+        .await
+        .into_iter()
+        .map(|user| AccountSummary {
+            name: user.username,
+            account_flags: user.account_flag.as_int(),
+            has_pin: user.has_pin,
+            has_matrix_card: user.has_matrix_card,
+        })
+        .collect();
+
+    ClientOpcodes::ListAccountsReply {
+        accounts: Some(accounts),
+    }
+}
+
+#[tracing::instrument]
+async fn list_sessions_request(
+    users: &mut impl KeyStorage,
+    offset: u32,
+    limit: u8,
+) -> ClientOpcodes {
+    trace!("got list sessions");
+
+    let sessions = users
+        .list_sessions(offset, list_limit(limit))
+        .await
+        .into_iter()
+        .map(|session| SessionSummary {
+            name: session.username,
+            address: session.address.to_string(),
+            login_time: session
+                .login_time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |a| a.as_secs()),
+        })
+        .collect();
+
+    ClientOpcodes::ListSessionsReply {
+        sessions: Some(sessions),
+    }
+}
+
+#[tracing::instrument]
+fn list_realms_request(realm: &RealmListImpl, offset: u32, limit: u8) -> ClientOpcodes {
+    trace!("got list realms");
+
+    let realms = realm
+        .list_realms(offset, list_limit(limit))
+        .into_iter()
+        .map(|status| RealmSummary {
+            realm_id: status.realm.realm_id,
+            name: status.realm.name,
+            address: status.realm.address,
+            population: status.realm.population.as_int(),
+            flags: status.flags,
+            category: status.realm.category.as_int(),
+            realm_type: status.realm.realm_type.as_int(),
+            online: status.online,
+            peer_address: status.peer_address.to_string(),
+        })
+        .collect();
+
+    ClientOpcodes::ListRealmsReply {
+        realms: Some(realms),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument]
+async fn update_realm_request(
+    realm: &mut RealmListImpl,
+    realm_id: Option<u8>,
+    name: Option<String>,
+    address: Option<String>,
+    population: Option<f32>,
+    locked: Option<bool>,
+    flags: Option<RealmFlags>,
+    category: Option<u8>,
+    realm_type: Option<u8>,
+) -> ClientOpcodes {
+    trace!("got update realm");
+
+    let Some(realm_id) = realm_id else {
+        warn!("realm update sent before realm was registered");
+        return ClientOpcodes::UpdateRealmReply {
+            result: Err(RegisterRealmError::NotRegistered),
+        };
+    };
+
+    let (category, realm_type) =
+        match validate_realm_update(address.as_deref(), category, realm_type) {
+            Ok(valid) => valid,
+            Err(e) => {
+                warn!(%e, "invalid realm update");
+                return ClientOpcodes::UpdateRealmReply {
+                    result: Err(register_realm_error(&e)),
+                };
+            }
+        };
+
+    let result = realm
+        .update_realm(
+            realm_id,
+            RealmUpdate {
+                name,
+                address,
+                population: population.map(Population::from),
+                locked,
+                flags: flags.map(realm_flags),
+                category,
+                realm_type,
+            },
+        )
+        .await;
+
+    ClientOpcodes::UpdateRealmReply { result }
+}
+
+#[tracing::instrument]
+fn heartbeat(realm: &mut RealmListImpl, realm_id: Option<u8>) {
+    trace!("got heartbeat");
+
+    let Some(realm_id) = realm_id else {
+        warn!("heartbeat sent before realm was registered");
+        return;
+    };
+
+    realm.heartbeat(realm_id);
+}
+
+pub(crate) fn validate_realm(
+    address: &str,
+    category: u8,
+    realm_type: u8,
+) -> Result<(RealmCategory, RealmType), MessageError> {
+    validate_address(address)?;
+
+    Ok((realm_category(category)?, realm_type_from_int(realm_type)?))
+}
+
+/// Same as [`validate_realm`] for the fields that are changed.
+fn validate_realm_update(
+    address: Option<&str>,
+    category: Option<u8>,
+    realm_type: Option<u8>,
+) -> Result<(Option<RealmCategory>, Option<RealmType>), MessageError> {
+    if let Some(address) = address {
+        validate_address(address)?;
+    }
+
+    Ok((
+        category.map(realm_category).transpose()?,
+        realm_type.map(realm_type_from_int).transpose()?,
+    ))
+}
+
+fn realm_category(category: u8) -> Result<RealmCategory, MessageError> {
+    RealmCategory::try_from(category).map_err(|_| MessageError::InvalidRealmCategory(category))
+}
+
+fn realm_type_from_int(realm_type: u8) -> Result<RealmType, MessageError> {
+    RealmType::try_from(realm_type).map_err(|_| MessageError::InvalidRealmType(realm_type))
+}
+
+/// Clients connect to the address themselves, so it must be a `host:port` with a non zero port.
+fn validate_address(address: &str) -> Result<(), MessageError> {
+    let valid = address.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty()
+            && !host.contains(char::is_whitespace)
+            && port.parse::<u16>().is_ok_and(|port| port != 0)
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(MessageError::InvalidAddress(address.to_string()))
+    }
+}
+
+/// Reason sent to the world server when the realm fails validation.
+fn register_realm_error(e: &MessageError) -> RegisterRealmError {
+    match e {
+        MessageError::InvalidRealmCategory(_) => RegisterRealmError::InvalidCategory,
+        MessageError::InvalidRealmType(_) => RegisterRealmError::InvalidRealmType,
+        MessageError::InvalidAddress(_) => RegisterRealmError::InvalidAddress,
+        _ => RegisterRealmError::Unknown,
+    }
+}
+
+/// Only includes the version if the flags say that it is used.
+fn realm_flags(flags: RealmFlags) -> (u8, Option<Realm_RealmFlag_SpecifyBuild>) {
+    let specify_build = if RealmFlag::new(flags.flags).is_specify_build() {
+        Some(Realm_RealmFlag_SpecifyBuild {
+            version: Version {
+                major: flags.version_major,
+                minor: flags.version_minor,
+                patch: flags.version_patch,
+                build: flags.version_build,
+            },
+        })
+    } else {
+        None
+    };
+
+    (flags.flags, specify_build)
+}
+
+#[tracing::instrument(skip(password))]
 async fn add_user_request(
-    mut stream: &mut TcpStream,
     credentials: &mut impl CredentialProvider,
     name: String,
     password: &str,
-) -> Result<(), MessageError> {
+) -> ClientOpcodes {
     trace!("got add user");
 
-    let success = credentials.add_user(&name, &password).await.is_some();
+    let success = credentials.add_user(&name, password).await.is_some();
 
     ClientOpcodes::AddUserReply { name, success }
-        .tokio_write(&mut stream)
-        .await?;
+}
 
-    Ok(())
+#[tracing::instrument(skip(salt, password_verifier))]
+async fn add_user_with_verifier_request(
+    credentials: &mut impl CredentialProvider,
+    name: String,
+    salt: [u8; SALT_LENGTH as usize],
+    password_verifier: [u8; PASSWORD_VERIFIER_LENGTH as usize],
+) -> ClientOpcodes {
+    trace!("got add user with verifier");
+
+    if !valid_verifier(&salt, &password_verifier) {
+        warn!("invalid salt or password verifier");
+        return ClientOpcodes::AddUserReply {
+            name,
+            success: false,
+        };
+    }
+
+    let success = credentials
+        .add_user_with_verifier(&name, salt, password_verifier)
+        .await
+        .is_some();
+
+    ClientOpcodes::AddUserReply { name, success }
+}
+
+/// The verifier must be in `1..N` and the salt must not be all zeros,
+/// otherwise the password is either not needed to log in or no password matches.
+fn valid_verifier(
+    salt: &[u8; SALT_LENGTH as usize],
+    password_verifier: &[u8; PASSWORD_VERIFIER_LENGTH as usize],
+) -> bool {
+    // Both are little endian, so the most significant bytes are last
+    let below_prime = password_verifier
+        .iter()
+        .rev()
+        .lt(LARGE_SAFE_PRIME_LITTLE_ENDIAN.iter().rev());
+
+    salt.iter().any(|&a| a != 0) && password_verifier.iter().any(|&a| a != 0) && below_prime
+}
+
+/// Also revokes the session of the user.
+#[tracing::instrument]
+async fn remove_user_request(
+    credentials: &mut impl CredentialProvider,
+    users: &mut impl KeyStorage,
+    name: String,
+) -> ClientOpcodes {
+    trace!("got remove user");
+
+    let success = credentials.remove_user(&name).await;
+    if success {
+        users.remove_key(&name).await;
+    }
+
+    ClientOpcodes::RemoveUserReply { name, success }
+}
+
+#[tracing::instrument(skip(password))]
+async fn modify_user_request(
+    credentials: &mut impl CredentialProvider,
+    name: String,
+    password: Option<String>,
+    pin: Modification<u64>,
+    matrix_card: Modification<MatrixCardData>,
+    account_flags: Option<u32>,
+) -> ClientOpcodes {
+    trace!("got modify user");
+
+    let success = match user_modification(password, pin, matrix_card, account_flags) {
+        Some(modification) => credentials.modify_user(&name, modification).await,
+        None => {
+            warn!("invalid user modification");
+            false
+        }
+    };
+
+    ClientOpcodes::ModifyUserReply { name, success }
+}
+
+fn user_modification(
+    password: Option<String>,
+    pin: Modification<u64>,
+    matrix_card: Modification<MatrixCardData>,
+    account_flags: Option<u32>,
+) -> Option<UserModification> {
+    let pin = match pin {
+        Modification::Unchanged => None,
+        Modification::Removed => Some(None),
+        Modification::Set(pin) => Some(Some(PinCode::from_u64(pin)?)),
+    };
+
+    let matrix_card = match matrix_card {
+        Modification::Unchanged => None,
+        Modification::Removed => Some(None),
+        Modification::Set(MatrixCardData {
+            challenge_count,
+            data,
+        }) => {
+            if data.len()
+                != MatrixCard::DEFAULT_DIGIT_COUNT as usize
+                    * MatrixCard::DEFAULT_HEIGHT as usize
+                    * MatrixCard::DEFAULT_WIDTH as usize
+            {
+                return None;
+            }
+
+            Some(Some(MatrixCardOptions {
+                matrix_card: MatrixCard::from_data(data),
+                challenge_count,
+            }))
+        }
+    };
+
+    Some(UserModification {
+        password,
+        pin,
+        matrix_card,
+        account_flag: account_flags.map(AccountFlag::new),
+    })
 }
@@ -0,0 +1,81 @@
+use crate::reply::{ReplySender, PUSH_REQUEST_ID};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use warthog_messages::ClientOpcodes;
+
+#[derive(Debug)]
+struct OnlineAccount {
+    connection: u64,
+    replies: ReplySender,
+}
+
+/// Which reply connection reported each account as online, so that kicks can be forwarded to it.
+///
+/// Names are kept and sent in uppercase, the same as session changes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OnlineAccounts {
+    accounts: Arc<Mutex<HashMap<String, OnlineAccount>>>,
+}
+
+impl OnlineAccounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `connection` identifies the reply connection that `replies` belongs to.
+    #[tracing::instrument(skip(self, replies))]
+    pub fn set_online(&self, name: &str, online: bool, connection: u64, replies: &ReplySender) {
+        let key = name.to_uppercase();
+        let mut accounts = self.accounts.lock().unwrap();
+
+        if online {
+            accounts.insert(
+                key,
+                OnlineAccount {
+                    connection,
+                    replies: replies.clone(),
+                },
+            );
+        } else if accounts
+            .get(&key)
+            .is_some_and(|a| a.connection == connection)
+        {
+            accounts.remove(&key);
+        }
+    }
+
+    /// Removes every account reported by `connection`.
+    pub fn remove_connection(&self, connection: u64) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .retain(|_, a| a.connection != connection);
+    }
+
+    /// Tells the world server that reported the account as online to disconnect it.
+    ///
+    /// Returns `false` if no world server has reported the account as online.
+    #[tracing::instrument(skip(self))]
+    pub async fn kick(&self, name: &str) -> bool {
+        let name = name.to_uppercase();
+        let account = self.accounts.lock().unwrap().remove(&name);
+        let Some(account) = account else {
+            return false;
+        };
+
+        let message = ClientOpcodes::DisconnectAccount { name };
+        if account
+            .replies
+            .send((PUSH_REQUEST_ID, message))
+            .await
+            .is_err()
+        {
+            warn!("world server disconnected before account could be kicked");
+            return false;
+        }
+
+        info!("kicked account");
+        true
+    }
+}
@@ -0,0 +1,107 @@
+use std::future::Future;
+use std::net::IpAddr;
+use tokio::sync::broadcast;
+use warthog_lib::{KeyStorage, SessionInfo, SrpServer};
+use warthog_messages::SessionChange;
+
+/// Changes that a slow subscriber can fall behind on before it misses some.
+const MAX_QUEUED_CHANGES: usize = 1024;
+
+/// [`KeyStorage`] that tells subscribers about every new, refreshed and revoked session.
+///
+/// Names are sent in uppercase, the same as clients send them,
+/// so that changes for the same account match however the name was written in the request.
+#[derive(Debug, Clone)]
+pub(crate) struct NotifyingKeyStorage<K> {
+    inner: K,
+    changes: broadcast::Sender<(String, SessionChange)>,
+}
+
+impl<K: KeyStorage> NotifyingKeyStorage<K> {
+    pub fn new(inner: K) -> Self {
+        let (changes, _) = broadcast::channel(MAX_QUEUED_CHANGES);
+
+        Self { inner, changes }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, SessionChange)> {
+        self.changes.subscribe()
+    }
+
+    fn add_key_and_notify(
+        &self,
+        username: String,
+        server: SrpServer,
+        address: Option<IpAddr>,
+    ) -> impl Future<Output = ()> + Send {
+        let mut inner = self.inner.clone();
+        let changes = self.changes.clone();
+
+        async move {
+            let session_key = *server.session_key();
+            let change = if inner.get_key_for_user(&username).await.is_some() {
+                SessionChange::Refreshed { session_key }
+            } else {
+                SessionChange::Added { session_key }
+            };
+
+            match address {
+                Some(address) => {
+                    inner
+                        .add_key_with_address(username.clone(), server, address)
+                        .await
+                }
+                None => inner.add_key(username.clone(), server).await,
+            }
+
+            // Only fails if nobody is subscribed
+            let _ = changes.send((username.to_uppercase(), change));
+        }
+    }
+}
+
+impl<K: KeyStorage> KeyStorage for NotifyingKeyStorage<K> {
+    fn add_key(&mut self, username: String, server: SrpServer) -> impl Future<Output = ()> + Send {
+        self.add_key_and_notify(username, server, None)
+    }
+
+    fn add_key_with_address(
+        &mut self,
+        username: String,
+        server: SrpServer,
+        address: IpAddr,
+    ) -> impl Future<Output = ()> + Send {
+        self.add_key_and_notify(username, server, Some(address))
+    }
+
+    fn get_key_for_user(
+        &mut self,
+        username: &str,
+    ) -> impl Future<Output = Option<SrpServer>> + Send {
+        self.inner.get_key_for_user(username)
+    }
+
+    fn remove_key(&mut self, username: &str) -> impl Future<Output = bool> + Send {
+        let mut inner = self.inner.clone();
+        let changes = self.changes.clone();
+        let username = username.to_string();
+
+        async move {
+            let removed = inner.remove_key(&username).await;
+
+            if removed {
+                let _ = changes.send((username.to_uppercase(), SessionChange::Revoked));
+            }
+
+            removed
+        }
+    }
+
+    fn list_sessions(
+        &mut self,
+        offset: u32,
+        limit: u32,
+    ) -> impl Future<Output = Vec<SessionInfo>> + Send {
+        self.inner.list_sessions(offset, limit)
+    }
+}
@@ -1,43 +1,46 @@
 mod util;
 
-use crate::test::util::{add_user, register_realm, start_server, vanilla_1_12};
-use crate::ApplicationOptions;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::sync::atomic::Ordering;
-use tokio::net::TcpStream;
-use warthog_lib::{Options, Population};
-use wow_client::connect_and_authenticate;
+use crate::reply::validate_realm;
+use crate::test::util::{
+    add_user, authenticate_reply, change_password, character_amount, connect_reply, connect_retry,
+    connect_tls, free_addresses, game_options, heartbeat, http_request, read_reply, realm_online,
+    register_realm, register_realm_raw, register_realm_with_id, remove_user, rename_realm,
+    start_server, test_certificate, update_realm, vanilla_1_12, vanilla_1_12_reconnect,
+    wait_for_realms, TestServer, REQUEST_ID,
+};
+use crate::{ApplicationOptions, ReplyTlsOptions};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use warthog_lib::{
+    MatrixCard, NormalizedString, PinCode, Population, RealmFlag, Realm_RealmFlag, SrpVerifier,
+    UnknownAccountSecret, LARGE_SAFE_PRIME_LITTLE_ENDIAN, PASSWORD_VERIFIER_LENGTH, SALT_LENGTH,
+};
+use warthog_messages::{
+    tokio_request_version, ConnectionOptions, MatrixCardData, MessageError, Modification,
+    Permissions, RealmFlags, RealmRegistration, RealmUpdate, RegisterRealmError, SessionChange,
+    WarthogConnection, MAGIC, MIN_PROTOCOL_VERSION,
+};
+use wow_client::{connect_and_authenticate, reconnect, ClientError, LoginResult};
 
 #[tokio::test]
 async fn register_realms() {
-    const REPLY_PORT: u16 = 32657;
-    const GAME_PORT: u16 = REPLY_PORT + 1;
+    let server = TestServer::start().await;
 
-    const REPLY_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), REPLY_PORT);
-    const GAME_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), GAME_PORT);
-
-    const APPLICATION_OPTIONS: ApplicationOptions = ApplicationOptions {
-        reply_address: REPLY_ADDRESS,
-        use_pin: false,
-        use_matrix_card: false,
-    };
-
-    const OPTIONS: Options = Options {
-        address: GAME_ADDRESS,
-        randomize_pin_grid: false,
-        max_concurrent_users: 10000,
-    };
-
-    let (should_run, main) = start_server(OPTIONS, APPLICATION_OPTIONS).await;
-
-    let mut reply = TcpStream::connect(REPLY_ADDRESS).await.unwrap();
+    let mut reply = connect_reply(server.reply_address).await;
     add_user(&mut reply, "A".to_string(), "A".to_string()).await;
 
     {
-        let (_, realms, _) =
-            connect_and_authenticate(vanilla_1_12("A".to_string()), GAME_ADDRESS, "A", None)
-                .await
-                .unwrap();
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
 
         assert!(realms.is_empty());
     }
@@ -53,10 +56,14 @@ async fn register_realms() {
     .await;
 
     {
-        let (_, realms, _) =
-            connect_and_authenticate(vanilla_1_12("A".to_string()), GAME_ADDRESS, "A", None)
-                .await
-                .unwrap();
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
 
         match realms.as_slice() {
             [realm] => {
@@ -74,7 +81,7 @@ async fn register_realms() {
     const REALM2_NAME: &str = "Test Realm2";
     const REALM2_ADDRESS: &str = "localhost:8088";
 
-    let mut reply2 = TcpStream::connect(REPLY_ADDRESS).await.unwrap();
+    let mut reply2 = connect_reply(server.reply_address).await;
     let realm_id2 = register_realm(
         &mut reply2,
         REALM2_NAME.to_string(),
@@ -83,10 +90,14 @@ async fn register_realms() {
     .await;
 
     {
-        let (_, realms, _) =
-            connect_and_authenticate(vanilla_1_12("A".to_string()), GAME_ADDRESS, "A", None)
-                .await
-                .unwrap();
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
 
         match realms.as_slice() {
             [realm, realm2] => {
@@ -113,6 +124,2046 @@ async fn register_realms() {
         assert!(!realms.is_empty());
     }
 
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn users_added_on_start() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.users = vec![("A".to_string(), "PASSWORD".to_string())];
+    })
+    .await;
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "PASSWORD",
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(connect_and_authenticate(
+        vanilla_1_12("B".to_string()),
+        server.game_address,
+        "B",
+        None
+    )
+    .await
+    .is_err());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unknown_account_gets_fake_challenge() {
+    let server = TestServer::start_with(|options, _| {
+        options.unknown_account_secret = Some(UnknownAccountSecret::new([7; 32]));
+    })
+    .await;
+
+    match connect_and_authenticate(
+        vanilla_1_12("UNKNOWN".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    {
+        Err(ClientError::ServerReply(LoginResult::FailIncorrectPassword)) => {}
+        Err(e) => panic!("{e}"),
+        Ok(_) => panic!("unknown account logged in"),
+    }
+
+    // Reconnecting with the session of another account
+    let mut reply = connect_reply(server.reply_address).await;
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+    let (client, _, _) = connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+
+    match reconnect(
+        vanilla_1_12_reconnect("UNKNOWN".to_string()),
+        server.game_address,
+        &client,
+    )
+    .await
+    {
+        Err(ClientError::ServerReply(LoginResult::FailIncorrectPassword)) => {}
+        Err(e) => panic!("{e}"),
+        Ok(_) => panic!("unknown account reconnected"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unknown_account_asks_for_default_pin() {
+    let server = TestServer::start_with(|options, application_options| {
+        options.unknown_account_secret = Some(UnknownAccountSecret::new([7; 32]));
+        application_options.use_pin = true;
+    })
+    .await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+
+    // The client refuses challenges that ask for a PIN when it has none
+    for name in ["A", "UNKNOWN"] {
+        match connect_and_authenticate(
+            vanilla_1_12(name.to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        {
+            Err(ClientError::InvalidSecurityFlag) => {}
+            Err(e) => panic!("{name}: {e}"),
+            Ok(_) => panic!("{name} logged in without PIN"),
+        }
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn character_amount_shown_in_realm_list() {
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+    add_user(&mut reply, "B".to_string(), "B".to_string()).await;
+
+    register_realm(
+        &mut reply,
+        "Test Realm".to_string(),
+        "localhost:8085".to_string(),
+    )
+    .await;
+    character_amount(&mut reply, "A".to_string(), 3).await;
+
+    let (_, realms, _) = connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(realms[0].number_of_characters_on_realm, 3);
+
+    let (_, realms, _) = connect_and_authenticate(
+        vanilla_1_12("B".to_string()),
+        server.game_address,
+        "B",
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(realms[0].number_of_characters_on_realm, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn character_amount_requested_on_login() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.users = vec![("A".to_string(), "A".to_string())];
+    })
+    .await;
+
+    let world = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+    let mut requests = world.character_amount_requests();
+    world
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    let name = tokio::time::timeout(Duration::from_secs(10), requests.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(name, "A");
+    world.character_amount(name, 3).await.unwrap();
+
+    // Shown from the next realm list, since the first can be sent before the answer arrives
+    let mut i = 0;
+    loop {
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
+        if realms[0].number_of_characters_on_realm == 3 {
+            break;
+        }
+
+        assert_ne!(i, 20);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        i += 1;
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn modify_and_remove_users() {
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+
+    assert!(change_password(&mut reply, "A".to_string(), "NEW".to_string()).await);
+    assert!(!change_password(&mut reply, "B".to_string(), "NEW".to_string()).await);
+
+    match connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    {
+        Err(ClientError::ServerReply(LoginResult::FailIncorrectPassword)) => {}
+        _ => panic!("old password still works"),
+    }
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "NEW",
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert!(remove_user(&mut reply, "A".to_string()).await);
+    assert!(!remove_user(&mut reply, "A".to_string()).await);
+
+    match connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "NEW",
+        None,
+    )
+    .await
+    {
+        Err(ClientError::ServerReply(LoginResult::FailUnknownAccount)) => {}
+        _ => panic!("removed user still exists"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn modify_user_security() {
+    let server = TestServer::start().await;
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+    assert!(connection
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+
+    let modify = |pin, matrix_card, account_flags| {
+        connection.modify_user("A".to_string(), None, pin, matrix_card, account_flags)
+    };
+    let account = || async {
+        let accounts = connection.list_accounts(0, 10).await.unwrap().unwrap();
+        accounts.into_iter().find(|a| a.name == "A").unwrap()
+    };
+    let login = |pin: Option<u64>| {
+        connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            pin.map(|pin| PinCode::from_u64(pin).unwrap()),
+        )
+    };
+
+    // PIN set
+    assert!(
+        modify(Modification::Set(1234), Modification::Unchanged, None)
+            .await
+            .unwrap()
+    );
+    assert!(account().await.has_pin);
+    match login(None).await {
+        Err(ClientError::InvalidSecurityFlag) => {}
+        _ => panic!("PIN not required"),
+    }
+    match login(Some(4321)).await {
+        Err(ClientError::ServerReply(LoginResult::FailIncorrectPassword)) => {}
+        _ => panic!("incorrect PIN accepted"),
+    }
+    login(Some(1234)).await.unwrap();
+
+    // Account flag set, PIN unchanged
+    assert!(
+        modify(Modification::Unchanged, Modification::Unchanged, Some(0x08))
+            .await
+            .unwrap()
+    );
+    let a = account().await;
+    assert_eq!(a.account_flags, 0x08);
+    assert!(a.has_pin);
+    login(Some(1234)).await.unwrap();
+
+    // PIN removed, account flag unchanged
+    assert!(modify(Modification::Removed, Modification::Unchanged, None)
+        .await
+        .unwrap());
+    let a = account().await;
+    assert_eq!(a.account_flags, 0x08);
+    assert!(!a.has_pin);
+    login(None).await.unwrap();
+
+    // Matrix card set, which the test client does not support
+    let card = MatrixCardData {
+        challenge_count: 2,
+        data: vec![
+            0;
+            MatrixCard::DEFAULT_DIGIT_COUNT as usize
+                * MatrixCard::DEFAULT_HEIGHT as usize
+                * MatrixCard::DEFAULT_WIDTH as usize
+        ],
+    };
+    assert!(
+        modify(Modification::Unchanged, Modification::Set(card), None)
+            .await
+            .unwrap()
+    );
+    assert!(account().await.has_matrix_card);
+    match login(None).await {
+        Err(ClientError::InvalidSecurityFlag) => {}
+        _ => panic!("matrix card not required"),
+    }
+
+    // Matrix cards of the wrong size are rejected without changing anything
+    let card = MatrixCardData {
+        challenge_count: 2,
+        data: vec![0; 3],
+    };
+    assert!(
+        !modify(Modification::Removed, Modification::Set(card), Some(0))
+            .await
+            .unwrap()
+    );
+    let a = account().await;
+    assert_eq!(a.account_flags, 0x08);
+    assert!(a.has_matrix_card);
+
+    // Matrix card and account flag removed
+    assert!(
+        modify(Modification::Unchanged, Modification::Removed, Some(0))
+            .await
+            .unwrap()
+    );
+    let a = account().await;
+    assert_eq!(a.account_flags, 0);
+    assert!(!a.has_matrix_card);
+    login(None).await.unwrap();
+
+    assert!(!connection
+        .modify_user(
+            "B".to_string(),
+            None,
+            Modification::Set(1234),
+            Modification::Unchanged,
+            None
+        )
+        .await
+        .unwrap());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reply_keys_limit_permissions() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_keys = vec![
+            "world:session_keys,realm:WORLD_KEY".parse().unwrap(),
+            "admin:all:ADMIN_KEY".parse().unwrap(),
+        ];
+    })
+    .await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    assert_eq!(
+        authenticate_reply(&mut reply, "world".to_string(), b"WRONG_KEY").await,
+        None
+    );
+
+    let mut world = connect_reply(server.reply_address).await;
+    assert_eq!(
+        authenticate_reply(&mut world, "world".to_string(), b"WORLD_KEY").await,
+        Some(Permissions::SESSION_KEYS | Permissions::REALM)
+    );
+    register_realm(
+        &mut world,
+        "Test Realm".to_string(),
+        "localhost:8085".to_string(),
+    )
+    .await;
+
+    warthog_messages::ServerOpcodes::AddUser {
+        name: "A".to_string(),
+        password: "A".to_string(),
+    }
+    .tokio_write(REQUEST_ID, &mut world)
+    .await
+    .unwrap();
+    match read_reply(&mut world, REQUEST_ID).await {
+        warthog_messages::ClientOpcodes::AddUserReply { success, .. } => assert!(!success),
+        _ => panic!(),
+    }
+
+    let mut admin = connect_reply(server.reply_address).await;
+    assert_eq!(
+        authenticate_reply(&mut admin, "admin".to_string(), b"ADMIN_KEY").await,
+        Some(Permissions::ALL)
+    );
+    add_user(&mut admin, "A".to_string(), "A".to_string()).await;
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reply_keys_required_unless_insecure() {
+    let [reply_address, game_address] = free_addresses();
+
+    let application_options = ApplicationOptions {
+        reply_address: Some(reply_address),
+        ..Default::default()
+    };
+
+    let (should_run, main) = start_server(game_options(game_address), application_options).await;
+
+    // Stops by itself since the reply server refuses to start
+    tokio::time::timeout(Duration::from_secs(5), main)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(tokio::net::TcpStream::connect(reply_address).await.is_err());
+
     should_run.store(false, Ordering::SeqCst);
-    main.await.unwrap();
+}
+
+#[tokio::test]
+async fn reply_over_tls() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_tls = Some(ReplyTlsOptions {
+            certificate: test_certificate("server.pem"),
+            private_key: test_certificate("server.key"),
+            client_ca: Some(test_certificate("ca.pem")),
+        });
+    })
+    .await;
+
+    // The server only notices the missing client certificate after the client has finished the handshake,
+    // so the error can also come from the version request
+    assert!(
+        connect_tls(server.reply_address, false).await.is_err(),
+        "connection without client certificate was accepted"
+    );
+
+    let mut reply = connect_tls(server.reply_address, true).await.unwrap();
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+    register_realm(
+        &mut reply,
+        "Test Realm".to_string(),
+        "localhost:8085".to_string(),
+    )
+    .await;
+
+    let (_, realms, _) = connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(realms.len(), 1);
+    assert_eq!(realms[0].name, "Test Realm");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unknown_opcode_keeps_connection() {
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+
+    // Size of 7, opcode 0xFF, request ID 0 and two bytes of body
+    reply
+        .write_all(&[7, 0, 0, 0, 0xFF, 0, 0, 0, 0, 1, 2])
+        .await
+        .unwrap();
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+
+    assert!(matches!(
+        warthog_messages::ServerOpcodes::AddUser {
+            name: "A".repeat(256),
+            password: "A".to_string(),
+        }
+        .tokio_write(REQUEST_ID, &mut reply)
+        .await,
+        Err(warthog_messages::MessageError::StringTooLong(256))
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn pipelined_requests() {
+    const REQUESTS: u32 = 32;
+
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+
+    // Send everything before reading any replies
+    for request_id in 0..REQUESTS {
+        warthog_messages::ServerOpcodes::AddUser {
+            name: format!("USER{request_id}"),
+            password: "PASSWORD".to_string(),
+        }
+        .tokio_write(request_id, &mut reply)
+        .await
+        .unwrap();
+    }
+
+    let mut replies = std::collections::HashSet::new();
+    for _ in 0..REQUESTS {
+        let (request_id, message) = warthog_messages::ClientOpcodes::tokio_read(&mut reply)
+            .await
+            .unwrap();
+
+        match message {
+            warthog_messages::ClientOpcodes::AddUserReply { name, success } => {
+                assert!(success);
+                assert_eq!(name, format!("USER{request_id}"));
+            }
+            _ => panic!(),
+        }
+        assert!(replies.insert(request_id));
+    }
+
+    connect_and_authenticate(
+        vanilla_1_12("USER7".to_string()),
+        server.game_address,
+        "PASSWORD",
+        None,
+    )
+    .await
+    .unwrap();
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn user_changes_applied_in_order() {
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+
+    // Sent without waiting, so the change only works if the user has been added first
+    let mut requests = [
+        warthog_messages::ServerOpcodes::AddUser {
+            name: "A".to_string(),
+            password: "OLD".to_string(),
+        },
+        warthog_messages::ServerOpcodes::ModifyUser {
+            name: "A".to_string(),
+            password: Some("NEW".to_string()),
+            pin: Modification::Unchanged,
+            matrix_card: Modification::Unchanged,
+            account_flags: None,
+        },
+    ];
+    for (request_id, request) in (1..).zip(requests.iter_mut()) {
+        request.tokio_write(request_id, &mut reply).await.unwrap();
+    }
+
+    for request_id in 1..=2 {
+        match warthog_messages::ClientOpcodes::tokio_read(&mut reply)
+            .await
+            .unwrap()
+        {
+            (id, warthog_messages::ClientOpcodes::AddUserReply { success, .. })
+            | (id, warthog_messages::ClientOpcodes::ModifyUserReply { success, .. }) => {
+                assert_eq!(id, request_id);
+                assert!(success);
+            }
+            _ => panic!(),
+        }
+    }
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "NEW",
+        None,
+    )
+    .await
+    .unwrap();
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn requests_without_request_ids() {
+    const VERSION: u16 = MIN_PROTOCOL_VERSION;
+    const NAMES: [&str; 3] = ["A", "B", "C"];
+
+    let server = TestServer::start().await;
+
+    let mut reply = connect_retry(server.reply_address).await;
+    let mut request = MAGIC.to_vec();
+    request.extend_from_slice(&VERSION.to_le_bytes());
+    request.extend_from_slice(&VERSION.to_le_bytes());
+    reply.write_all(&request).await.unwrap();
+
+    let mut version = [0_u8; 2];
+    reply.read_exact(&mut version).await.unwrap();
+    assert_eq!(u16::from_le_bytes(version), VERSION);
+
+    // Replies can only be told apart by their order
+    for name in NAMES {
+        warthog_messages::ServerOpcodes::AddUser {
+            name: name.to_string(),
+            password: name.to_string(),
+        }
+        .tokio_write_with_version(VERSION, 0, &mut reply)
+        .await
+        .unwrap();
+    }
+    warthog_messages::ServerOpcodes::ListAccounts {
+        offset: 0,
+        limit: 10,
+    }
+    .tokio_write_with_version(VERSION, 0, &mut reply)
+    .await
+    .unwrap();
+
+    for expected in NAMES {
+        match warthog_messages::ClientOpcodes::tokio_read_with_version(&mut reply, VERSION)
+            .await
+            .unwrap()
+        {
+            (0, warthog_messages::ClientOpcodes::AddUserReply { name, success }) => {
+                assert!(success);
+                assert_eq!(name, expected);
+            }
+            _ => panic!(),
+        }
+    }
+    match warthog_messages::ClientOpcodes::tokio_read_with_version(&mut reply, VERSION)
+        .await
+        .unwrap()
+    {
+        (
+            0,
+            warthog_messages::ClientOpcodes::ListAccountsReply {
+                accounts: Some(accounts),
+            },
+        ) => assert_eq!(accounts.len(), NAMES.len()),
+        _ => panic!(),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn realm_updates_and_heartbeats() {
+    const HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(200);
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.realm_heartbeat_timeout = Some(HEARTBEAT_TIMEOUT);
+    })
+    .await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    add_user(&mut reply, "A".to_string(), "A".to_string()).await;
+
+    assert_eq!(
+        update_realm(&mut reply, Some(100.0), None).await,
+        Err(RegisterRealmError::NotRegistered)
+    );
+
+    register_realm(
+        &mut reply,
+        "Test Realm".to_string(),
+        "localhost:8085".to_string(),
+    )
+    .await;
+
+    assert_eq!(
+        update_realm(&mut reply, Some(400.0), Some(true)).await,
+        Ok(())
+    );
+
+    {
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
+
+        match realms.as_slice() {
+            [realm] => {
+                assert_eq!(realm.population, Population::from(400.0));
+                assert_eq!(realm.locked, true);
+                assert_eq!(realm.name, "Test Realm");
+            }
+            _ => panic!(),
+        }
+    }
+
+    wait_for_realms(&mut reply, |realms| !realms[0].online).await;
+
+    // Stays offline until the next heartbeat
+    {
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
+
+        match realms.as_slice() {
+            [realm] => assert_eq!(realm.flag, Realm_RealmFlag::new(RealmFlag::OFFLINE, None)),
+            _ => panic!(),
+        }
+    }
+
+    assert!(heartbeat(&mut reply).await);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn disconnected_realm_kept_during_grace_period() {
+    const REALM_NAME: &str = "Test Realm";
+    const REALM_ADDRESS: &str = "localhost:8085";
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_keys = vec![
+            "world:realm:WORLD_KEY".parse().unwrap(),
+            "other:realm:OTHER_KEY".parse().unwrap(),
+            "admin:all:ADMIN_KEY".parse().unwrap(),
+        ];
+        // Does not end during the test, and is cancelled when the server stops
+        application_options.realm_offline_grace_period = Some(Duration::from_secs(60 * 60));
+    })
+    .await;
+
+    let mut admin = connect_reply(server.reply_address).await;
+    authenticate_reply(&mut admin, "admin".to_string(), b"ADMIN_KEY")
+        .await
+        .unwrap();
+    add_user(&mut admin, "A".to_string(), "A".to_string()).await;
+
+    let mut world = connect_reply(server.reply_address).await;
+    authenticate_reply(&mut world, "world".to_string(), b"WORLD_KEY")
+        .await
+        .unwrap();
+    let realm_id = register_realm(
+        &mut world,
+        REALM_NAME.to_string(),
+        REALM_ADDRESS.to_string(),
+    )
+    .await;
+
+    drop(world);
+    wait_for_realms(&mut admin, |realms| !realms[0].online).await;
+
+    {
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
+
+        match realms.as_slice() {
+            [realm] => {
+                assert_eq!(realm.realm_id, realm_id);
+                assert_eq!(realm.flag, Realm_RealmFlag::new(RealmFlag::OFFLINE, None));
+            }
+            _ => panic!(),
+        }
+    }
+
+    // Only the key that registered the realm can take it back
+    let mut other = connect_reply(server.reply_address).await;
+    authenticate_reply(&mut other, "other".to_string(), b"OTHER_KEY")
+        .await
+        .unwrap();
+    assert_eq!(
+        register_realm_with_id(
+            &mut other,
+            REALM_NAME.to_string(),
+            REALM_ADDRESS.to_string(),
+            None,
+        )
+        .await,
+        Err(RegisterRealmError::RealmIdUnavailable)
+    );
+
+    let mut world = connect_reply(server.reply_address).await;
+    authenticate_reply(&mut world, "world".to_string(), b"WORLD_KEY")
+        .await
+        .unwrap();
+    assert_eq!(
+        register_realm(
+            &mut world,
+            REALM_NAME.to_string(),
+            REALM_ADDRESS.to_string(),
+        )
+        .await,
+        realm_id
+    );
+    assert!(realm_online(&mut admin).await);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn disconnected_realm_removed_after_grace_period() {
+    const GRACE_PERIOD: Duration = Duration::from_millis(100);
+    const REALM_NAME: &str = "Test Realm";
+    const REALM_ADDRESS: &str = "localhost:8085";
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.realm_offline_grace_period = Some(GRACE_PERIOD);
+    })
+    .await;
+
+    let mut admin = connect_reply(server.reply_address).await;
+    add_user(&mut admin, "A".to_string(), "A".to_string()).await;
+
+    let mut world = connect_reply(server.reply_address).await;
+    let realm_id = register_realm(
+        &mut world,
+        REALM_NAME.to_string(),
+        REALM_ADDRESS.to_string(),
+    )
+    .await;
+
+    // The realm might already have been removed if this machine is slow
+    drop(world);
+    wait_for_realms(&mut admin, |realms| realms.iter().all(|a| !a.online)).await;
+
+    let mut world = connect_reply(server.reply_address).await;
+    assert_eq!(
+        register_realm(
+            &mut world,
+            REALM_NAME.to_string(),
+            REALM_ADDRESS.to_string(),
+        )
+        .await,
+        realm_id
+    );
+
+    // The grace period of the first disconnection must not remove the realm after it has come back.
+    // A slow machine can only make this pass without checking anything, not fail
+    tokio::time::sleep(GRACE_PERIOD * 2).await;
+    assert!(realm_online(&mut admin).await);
+
+    drop(world);
+    wait_for_realms(&mut admin, |realms| realms.is_empty()).await;
+
+    {
+        let (_, realms, _) = connect_and_authenticate(
+            vanilla_1_12("A".to_string()),
+            server.game_address,
+            "A",
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(realms.is_empty());
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn realm_ids_persisted() {
+    let realm_id_file =
+        std::env::temp_dir().join(format!("warthog_realm_ids_{}", std::process::id()));
+    std::fs::write(&realm_id_file, "# configured\n7 Configured Realm\n").unwrap();
+
+    let start = || {
+        TestServer::start_with(|_, application_options| {
+            application_options.realm_id_file = Some(realm_id_file.clone());
+        })
+    };
+
+    let server = start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    let mut reply2 = connect_reply(server.reply_address).await;
+    let mut reply3 = connect_reply(server.reply_address).await;
+
+    assert_eq!(
+        register_realm(
+            &mut reply,
+            "Configured Realm".to_string(),
+            "localhost:8085".to_string()
+        )
+        .await,
+        7
+    );
+    assert_eq!(
+        register_realm_with_id(
+            &mut reply2,
+            "Other".to_string(),
+            "localhost:8086".to_string(),
+            Some(7)
+        )
+        .await,
+        Err(RegisterRealmError::RealmIdUnavailable)
+    );
+    assert_eq!(
+        register_realm_with_id(
+            &mut reply2,
+            "Other".to_string(),
+            "localhost:8086".to_string(),
+            Some(3)
+        )
+        .await,
+        Ok(3)
+    );
+    assert_eq!(
+        register_realm(
+            &mut reply3,
+            "Third".to_string(),
+            "localhost:8087".to_string()
+        )
+        .await,
+        0
+    );
+
+    server.stop().await;
+
+    let contents = std::fs::read_to_string(&realm_id_file).unwrap();
+    assert_eq!(contents, "0 Third\n3 Other\n7 Configured Realm\n");
+
+    let server = start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    let mut reply2 = connect_reply(server.reply_address).await;
+
+    // Registered in a different order, but the IDs stay the same
+    assert_eq!(
+        register_realm(
+            &mut reply,
+            "Other".to_string(),
+            "localhost:8086".to_string()
+        )
+        .await,
+        3
+    );
+    assert_eq!(
+        register_realm(
+            &mut reply2,
+            "Third".to_string(),
+            "localhost:8087".to_string()
+        )
+        .await,
+        0
+    );
+
+    server.stop().await;
+
+    std::fs::remove_file(&realm_id_file).unwrap();
+}
+
+#[tokio::test]
+async fn realm_ids_reclaimed_and_renamed() {
+    let realm_id_file =
+        std::env::temp_dir().join(format!("warthog_realm_ids_renamed_{}", std::process::id()));
+    let all_assigned = (0..=u8::MAX)
+        .map(|id| format!("{id} Realm {id}\n"))
+        .collect::<String>();
+    std::fs::write(&realm_id_file, all_assigned).unwrap();
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.realm_id_file = Some(realm_id_file.clone());
+    })
+    .await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+    let mut reply2 = connect_reply(server.reply_address).await;
+
+    assert_eq!(
+        register_realm(
+            &mut reply,
+            "Realm 0".to_string(),
+            "localhost:8085".to_string()
+        )
+        .await,
+        0
+    );
+    // 0 is registered, so 1 is the least recently assigned
+    assert_eq!(
+        register_realm(&mut reply2, "New".to_string(), "localhost:8086".to_string()).await,
+        1
+    );
+
+    assert_eq!(
+        rename_realm(&mut reply, "Renamed".to_string()).await,
+        Ok(())
+    );
+    assert_eq!(
+        rename_realm(&mut reply2, "Realm 2".to_string()).await,
+        Err(RegisterRealmError::RealmIdUnavailable)
+    );
+    assert_eq!(
+        register_realm(
+            &mut reply2,
+            "Registered Again".to_string(),
+            "localhost:8086".to_string()
+        )
+        .await,
+        1
+    );
+
+    server.stop().await;
+
+    let contents = std::fs::read_to_string(&realm_id_file).unwrap();
+    let lines = contents.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 256);
+    assert_eq!(lines[0], "0 Renamed");
+    assert_eq!(lines[1], "1 Registered Again");
+    assert_eq!(lines[2], "2 Realm 2");
+
+    std::fs::remove_file(&realm_id_file).unwrap();
+}
+
+#[tokio::test]
+async fn warthog_connection_reconnects() {
+    let server = TestServer::start().await;
+
+    // Forwards to the reply server so that the connection can be cut
+    let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_address = proxy.local_addr().unwrap();
+    let (connections, mut proxied) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let connection = tokio::spawn(async move {
+                let mut reply = tokio::net::TcpStream::connect(server.reply_address)
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut stream, &mut reply).await;
+            });
+            connections.send(connection).unwrap();
+        }
+    });
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: proxy_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+
+    assert!(connection
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+    let realm_id = connection
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (_, realms, _) = connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(realms.len(), 1);
+    assert!(connection
+        .session_key("A".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    assert_eq!(
+        connection
+            .update_realm(RealmUpdate {
+                name: Some("Renamed Realm".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        Ok(())
+    );
+
+    proxied.recv().await.unwrap().abort();
+
+    // The connection has been lost once the proxy accepts the reconnection,
+    // so requests from now on wait for the realm to be registered again
+    proxied.recv().await.unwrap();
+    assert!(connection
+        .session_key("A".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    let (_, realms, _) = connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    match realms.as_slice() {
+        [realm] => {
+            assert_eq!(realm.realm_id, realm_id);
+            assert_eq!(realm.name, "Renamed Realm");
+        }
+        _ => panic!(),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn warthog_connection_stops_when_registration_rejected() {
+    let server = TestServer::start().await;
+
+    // Forwards to the reply server while `forward` is set, and refuses connections otherwise
+    let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_address = proxy.local_addr().unwrap();
+    let forward = Arc::new(AtomicBool::new(true));
+    let (connections, mut proxied) = tokio::sync::mpsc::unbounded_channel();
+    let forward_inner = forward.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            if !forward_inner.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            let connection = tokio::spawn(async move {
+                let mut reply = tokio::net::TcpStream::connect(server.reply_address)
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut stream, &mut reply).await;
+            });
+            connections.send(connection).unwrap();
+        }
+    });
+
+    let options = |address: String| ConnectionOptions {
+        address,
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    };
+    let registration = RealmRegistration {
+        name: "Test Realm".to_string(),
+        address: "localhost:8085".to_string(),
+        population: 200.0,
+        locked: false,
+        flags: RealmFlags::default(),
+        category: 0,
+        realm_type: 0,
+        realm_id: None,
+    };
+
+    let connection = WarthogConnection::connect(options(proxy_address.to_string()))
+        .await
+        .unwrap();
+    let realm_id = connection
+        .register_realm(registration.clone())
+        .await
+        .unwrap()
+        .unwrap();
+
+    forward.store(false, Ordering::SeqCst);
+    proxied.recv().await.unwrap().abort();
+
+    // Takes back the realm once the auth server has noticed the disconnect
+    let other = WarthogConnection::connect(options(server.reply_address.to_string()))
+        .await
+        .unwrap();
+    let mut i = 0;
+    while other
+        .register_realm(registration.clone())
+        .await
+        .unwrap()
+        .is_err()
+    {
+        assert_ne!(i, 20);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        i += 1;
+    }
+
+    // Registering again is rejected, so the connection stops instead of reconnecting forever
+    forward.store(true, Ordering::SeqCst);
+    assert!(matches!(
+        connection.session_key("A".to_string()).await,
+        Err(MessageError::ConnectionClosed)
+    ));
+
+    match other.list_realms(0, 10).await.unwrap().unwrap().as_slice() {
+        [realm] => assert_eq!(realm.realm_id, realm_id),
+        realms => panic!("{realms:?}"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn invalid_realm_rejected() {
+    const REALM_NAME: &str = "Test Realm";
+    const REALM_ADDRESS: &str = "localhost:8085";
+
+    let server = TestServer::start().await;
+
+    let mut reply = connect_reply(server.reply_address).await;
+
+    assert_eq!(
+        register_realm_raw(
+            &mut reply,
+            REALM_NAME.to_string(),
+            REALM_ADDRESS.to_string(),
+            u8::MAX,
+            0,
+            None
+        )
+        .await,
+        Err(RegisterRealmError::InvalidCategory)
+    );
+    assert_eq!(
+        register_realm_raw(
+            &mut reply,
+            REALM_NAME.to_string(),
+            REALM_ADDRESS.to_string(),
+            0,
+            u8::MAX,
+            None
+        )
+        .await,
+        Err(RegisterRealmError::InvalidRealmType)
+    );
+
+    for address in [
+        "localhost",
+        "localhost:",
+        ":8085",
+        "localhost:0",
+        "localhost:99999",
+    ] {
+        assert_eq!(
+            register_realm_with_id(
+                &mut reply,
+                REALM_NAME.to_string(),
+                address.to_string(),
+                None
+            )
+            .await,
+            Err(RegisterRealmError::InvalidAddress)
+        );
+    }
+
+    // The connection is still usable after the failures
+    register_realm(
+        &mut reply,
+        REALM_NAME.to_string(),
+        REALM_ADDRESS.to_string(),
+    )
+    .await;
+
+    server.stop().await;
+}
+
+#[test]
+fn invalid_realm_category_is_error() {
+    assert!(matches!(
+        validate_realm("localhost:8085", u8::MAX, 0),
+        Err(MessageError::InvalidRealmCategory(u8::MAX))
+    ));
+}
+
+#[test]
+fn invalid_realm_type_is_error() {
+    assert!(matches!(
+        validate_realm("localhost:8085", 0, u8::MAX),
+        Err(MessageError::InvalidRealmType(u8::MAX))
+    ));
+}
+
+#[test]
+fn invalid_address_is_error() {
+    match validate_realm("localhost:0", 0, 0) {
+        Err(MessageError::InvalidAddress(address)) => assert_eq!(address, "localhost:0"),
+        r => panic!("{r:?}"),
+    }
+}
+
+#[tokio::test]
+async fn sessions_pushed_to_subscribers() {
+    let server = TestServer::start().await;
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+    assert!(connection
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+
+    let mut sessions = connection.subscribe_sessions().await.unwrap().unwrap();
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    let session_key = match sessions.recv().await.unwrap() {
+        (name, SessionChange::Added { session_key }) if name == "A" => session_key,
+        change => panic!("{change:?}"),
+    };
+    assert_eq!(
+        connection.session_key("A".to_string()).await.unwrap(),
+        Some(session_key)
+    );
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    match sessions.recv().await.unwrap() {
+        (name, SessionChange::Refreshed { session_key: new }) if name == "A" => {
+            assert_ne!(new, session_key);
+        }
+        change => panic!("{change:?}"),
+    }
+
+    assert!(connection.remove_user("A".to_string()).await.unwrap());
+    match sessions.recv().await.unwrap() {
+        (name, SessionChange::Revoked) if name == "A" => {}
+        change => panic!("{change:?}"),
+    }
+    assert_eq!(connection.session_key("A".to_string()).await.unwrap(), None);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn session_changes_use_uppercase_names() {
+    let server = TestServer::start().await;
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+    assert!(connection
+        .add_user("a".to_string(), "A".to_string())
+        .await
+        .unwrap());
+
+    let mut sessions = connection.subscribe_sessions().await.unwrap().unwrap();
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    match sessions.recv().await.unwrap() {
+        (name, SessionChange::Added { .. }) if name == "A" => {}
+        change => panic!("{change:?}"),
+    }
+
+    assert!(connection.revoke_session("a".to_string()).await.unwrap());
+    match sessions.recv().await.unwrap() {
+        (name, SessionChange::Revoked) if name == "A" => {}
+        change => panic!("{change:?}"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn kick_forwarded_to_world_server() {
+    let server = TestServer::start().await;
+
+    let connection_options = || ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    };
+    let world = WarthogConnection::connect(connection_options())
+        .await
+        .unwrap();
+    let admin = WarthogConnection::connect(connection_options())
+        .await
+        .unwrap();
+    assert!(admin
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+
+    let mut disconnects = world.disconnects();
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    world.account_online("a".to_string(), true).await.unwrap();
+    world.account_online("B".to_string(), true).await.unwrap();
+    // Replies are sent in order for a connection, so the accounts have been reported when this returns
+    assert!(world.session_key("A".to_string()).await.unwrap().is_some());
+
+    // Only accounts with a session can be reported as online
+    assert_eq!(
+        admin.kick_account("B".to_string()).await.unwrap(),
+        (false, false)
+    );
+
+    assert_eq!(
+        admin.kick_account("A".to_string()).await.unwrap(),
+        (true, true)
+    );
+    assert_eq!(disconnects.recv().await.unwrap(), "A");
+    assert_eq!(world.session_key("A".to_string()).await.unwrap(), None);
+    assert_eq!(
+        admin.kick_account("A".to_string()).await.unwrap(),
+        (false, false)
+    );
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(admin.revoke_session("A".to_string()).await.unwrap());
+    assert!(!admin.revoke_session("A".to_string()).await.unwrap());
+    assert_eq!(world.session_key("A".to_string()).await.unwrap(), None);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn add_user_with_verifier() {
+    let server = TestServer::start().await;
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+
+    let verifier = SrpVerifier::from_username_and_password(
+        NormalizedString::new("A").unwrap(),
+        NormalizedString::new("PASSWORD").unwrap(),
+    );
+    // Verifiers that are zero or not below the prime, and all zero salts, are rejected
+    for (salt, password_verifier) in [
+        (*verifier.salt(), [0; PASSWORD_VERIFIER_LENGTH as usize]),
+        (*verifier.salt(), [0xFF; PASSWORD_VERIFIER_LENGTH as usize]),
+        (*verifier.salt(), LARGE_SAFE_PRIME_LITTLE_ENDIAN),
+        ([0; SALT_LENGTH as usize], *verifier.password_verifier()),
+    ] {
+        assert!(!connection
+            .add_user_with_verifier("A".to_string(), salt, password_verifier)
+            .await
+            .unwrap());
+    }
+
+    assert!(connection
+        .add_user_with_verifier(
+            "A".to_string(),
+            *verifier.salt(),
+            *verifier.password_verifier()
+        )
+        .await
+        .unwrap());
+    assert!(!connection
+        .add_user_with_verifier(
+            "A".to_string(),
+            *verifier.salt(),
+            *verifier.password_verifier()
+        )
+        .await
+        .unwrap());
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "PASSWORD",
+        None,
+    )
+    .await
+    .unwrap();
+    match connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    {
+        Err(ClientError::ServerReply(LoginResult::FailIncorrectPassword)) => {}
+        _ => panic!("wrong password accepted"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn admin_queries() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_keys = vec![
+            "world:session_keys,realm,users:WORLD_KEY".parse().unwrap(),
+            "admin:queries:ADMIN_KEY".parse().unwrap(),
+        ];
+    })
+    .await;
+
+    let connection_options = |name: &str, key: &[u8]| ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: Some((name.to_string(), key.to_vec())),
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    };
+    let world = WarthogConnection::connect(connection_options("world", b"WORLD_KEY"))
+        .await
+        .unwrap();
+    let admin = WarthogConnection::connect(connection_options("admin", b"ADMIN_KEY"))
+        .await
+        .unwrap();
+
+    assert_eq!(world.list_accounts(0, 10).await.unwrap(), None);
+    assert_eq!(world.list_sessions(0, 10).await.unwrap(), None);
+    assert_eq!(world.list_realms(0, 10).await.unwrap(), None);
+
+    for name in ["A", "B", "C"] {
+        assert!(world
+            .add_user(name.to_string(), name.to_string())
+            .await
+            .unwrap());
+    }
+    let realm_id = world
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let first = admin.list_accounts(0, 2).await.unwrap().unwrap();
+    let second = admin.list_accounts(2, 2).await.unwrap().unwrap();
+    let names = first
+        .iter()
+        .chain(&second)
+        .map(|a| a.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["A", "B", "C"]);
+    assert!(!first[0].has_pin && !first[0].has_matrix_card);
+
+    assert_eq!(admin.list_sessions(0, 10).await.unwrap(), Some(Vec::new()));
+    connect_and_authenticate(
+        vanilla_1_12("B".to_string()),
+        server.game_address,
+        "B",
+        None,
+    )
+    .await
+    .unwrap();
+    match admin
+        .list_sessions(0, 10)
+        .await
+        .unwrap()
+        .unwrap()
+        .as_slice()
+    {
+        [session] => {
+            assert_eq!(session.name, "B");
+            assert_eq!(session.address, "127.0.0.1");
+            assert_ne!(session.login_time, 0);
+        }
+        sessions => panic!("{sessions:?}"),
+    }
+
+    match admin.list_realms(0, 10).await.unwrap().unwrap().as_slice() {
+        [realm] => {
+            assert_eq!(realm.realm_id, realm_id);
+            assert_eq!(realm.name, "Test Realm");
+            assert_eq!(realm.address, "localhost:8085");
+            assert!(realm.online);
+            assert!(realm.peer_address.starts_with("127.0.0.1:"));
+        }
+        realms => panic!("{realms:?}"),
+    }
+    assert_eq!(admin.list_realms(1, 10).await.unwrap(), Some(Vec::new()));
+
+    server.stop().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn reply_over_unix_socket() {
+    use crate::ReplySocketOptions;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("warthog_reply_{}.sock", std::process::id()));
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_address = None;
+        application_options.reply_socket = Some(ReplySocketOptions {
+            path: path.clone(),
+            mode: 0o600,
+        });
+    })
+    .await;
+
+    // Only the game port is waited for
+    let mut i = 0;
+    while !path.exists() {
+        assert_ne!(i, 20);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        i += 1;
+    }
+
+    let connection = WarthogConnection::connect(ConnectionOptions {
+        address: format!("unix:{}", path.display()),
+        key: None,
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+
+    assert!(connection
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+
+    // Permissions are set before the first connection is accepted
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    connection
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(connection
+        .session_key("A".to_string())
+        .await
+        .unwrap()
+        .is_some());
+
+    match connection
+        .list_realms(0, 10)
+        .await
+        .unwrap()
+        .unwrap()
+        .as_slice()
+    {
+        [realm] => assert_eq!(realm.peer_address, format!("unix:{}", path.display())),
+        realms => panic!("{realms:?}"),
+    }
+
+    server.stop().await;
+
+    // The socket is removed when the reply server stops, which can be after the auth server
+    let mut i = 0;
+    while path.exists() {
+        assert_ne!(i, 100);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        i += 1;
+    }
+    // The directory the socket was bound in is gone as soon as the socket is in place
+    let id = std::process::id();
+    assert!(!path
+        .with_file_name(format!(".warthog_reply_{id}.sock.{id}"))
+        .exists());
+}
+
+#[test]
+fn cidr_matching() {
+    use crate::Cidr;
+
+    let lan: Cidr = "10.0.0.0/8".parse().unwrap();
+    assert!(lan.contains("10.1.2.3".parse().unwrap()));
+    assert!(lan.contains("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(!lan.contains("11.0.0.1".parse().unwrap()));
+    assert!(!lan.contains("::1".parse().unwrap()));
+    assert_eq!(lan.to_string(), "10.0.0.0/8");
+
+    let single: Cidr = "::1".parse().unwrap();
+    assert_eq!(single.to_string(), "::1/128");
+    assert!(single.contains("::1".parse().unwrap()));
+    assert!(!single.contains("::2".parse().unwrap()));
+
+    let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(everything.contains("192.168.0.1".parse().unwrap()));
+
+    let mapped: Cidr = "::ffff:10.0.0.0/104".parse().unwrap();
+    assert_eq!(mapped, lan);
+    assert!(mapped.contains("10.1.2.3".parse().unwrap()));
+    let mapped_single: Cidr = "::ffff:127.0.0.1".parse().unwrap();
+    assert_eq!(mapped_single.to_string(), "127.0.0.1/32");
+
+    let all_v6: Cidr = "::/0".parse().unwrap();
+    assert!(all_v6.contains("192.168.0.1".parse().unwrap()));
+
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0/8".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/".parse::<Cidr>().is_err());
+}
+
+#[tokio::test]
+async fn reply_allow_list() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_allow = vec!["127.0.0.1/32".parse().unwrap()];
+        application_options.reply_admin_allow = vec!["10.0.0.0/8".parse().unwrap()];
+        application_options.reply_keys = vec!["world:all:WORLD_KEY".parse().unwrap()];
+    })
+    .await;
+
+    let world = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: Some(("world".to_string(), b"WORLD_KEY".to_vec())),
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+
+    // Localhost is not in the admin list, so the key is limited to world server permissions
+    assert!(!world
+        .add_user("A".to_string(), "A".to_string())
+        .await
+        .unwrap());
+    assert_eq!(world.list_accounts(0, 10).await.unwrap(), None);
+    assert!(world
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .is_ok());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reply_connection_not_in_allow_list() {
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_allow = vec!["10.0.0.0/8".parse().unwrap()];
+    })
+    .await;
+
+    // Closed without answering the version request
+    let mut reply = connect_retry(server.reply_address).await;
+    assert!(tokio_request_version(&mut reply).await.is_err());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn http_gateway() {
+    let [http_address] = free_addresses();
+
+    let server = TestServer::start_with(|_, application_options| {
+        application_options.reply_http_address = Some(http_address);
+        application_options.reply_keys = vec![
+            "world:realm:WORLD_KEY".parse().unwrap(),
+            "panel:users,queries,realm_admin:PANEL_KEY".parse().unwrap(),
+        ];
+        application_options.reply_insecure = false;
+    })
+    .await;
+
+    const PANEL: Option<&str> = Some("panel:PANEL_KEY");
+
+    let (status, _) = http_request(http_address, "GET", "/users", None, None).await;
+    assert_eq!(status, 401);
+    let (status, _) = http_request(http_address, "GET", "/users", Some("panel:WRONG"), None).await;
+    assert_eq!(status, 401);
+
+    let (status, reply) = http_request(
+        http_address,
+        "POST",
+        "/users",
+        PANEL,
+        Some(json!({ "name": "A", "password": "A" })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(reply, json!({ "name": "A", "success": true }));
+
+    let (_, reply) = http_request(
+        http_address,
+        "PATCH",
+        "/users/A",
+        PANEL,
+        Some(json!({ "account_flags": 8 })),
+    )
+    .await;
+    assert_eq!(reply["success"], true);
+
+    let (status, reply) = http_request(http_address, "GET", "/users", PANEL, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        reply,
+        json!([{ "name": "A", "account_flags": 8, "has_pin": false, "has_matrix_card": false }])
+    );
+
+    connect_and_authenticate(
+        vanilla_1_12("A".to_string()),
+        server.game_address,
+        "A",
+        None,
+    )
+    .await
+    .unwrap();
+
+    // The panel key does not have the session keys permission
+    let (status, _) = http_request(http_address, "GET", "/sessions/A", PANEL, None).await;
+    assert_eq!(status, 403);
+    let (_, reply) = http_request(http_address, "GET", "/sessions", PANEL, None).await;
+    assert_eq!(reply[0]["name"], "A");
+    assert_eq!(reply[0]["address"], "127.0.0.1");
+
+    let world = WarthogConnection::connect(ConnectionOptions {
+        address: server.reply_address.to_string(),
+        key: Some(("world".to_string(), b"WORLD_KEY".to_vec())),
+        tls: None,
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(10),
+        handshake_timeout: Duration::from_secs(10),
+    })
+    .await
+    .unwrap();
+    let realm_id = world
+        .register_realm(RealmRegistration {
+            name: "Test Realm".to_string(),
+            address: "localhost:8085".to_string(),
+            population: 200.0,
+            locked: false,
+            flags: RealmFlags::default(),
+            category: 0,
+            realm_type: 0,
+            realm_id: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Realm servers can only change their own realm
+    let (status, _) = http_request(
+        http_address,
+        "PATCH",
+        &format!("/realms/{realm_id}"),
+        Some("world:WORLD_KEY"),
+        Some(json!({ "name": "Renamed" })),
+    )
+    .await;
+    assert_eq!(status, 403);
+    let (status, reply) = http_request(
+        http_address,
+        "PATCH",
+        &format!("/realms/{realm_id}"),
+        PANEL,
+        Some(json!({ "name": "Renamed", "locked": true })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(reply, json!({ "success": true }));
+    let (_, reply) = http_request(
+        http_address,
+        "PATCH",
+        &format!("/realms/{}", realm_id.wrapping_add(1)),
+        PANEL,
+        Some(json!({ "name": "Missing" })),
+    )
+    .await;
+    assert_eq!(
+        reply,
+        json!({
+            "success": false,
+            "reason": RegisterRealmError::NotRegistered.as_int(),
+        })
+    );
+
+    let (_, reply) = http_request(http_address, "GET", "/realms", PANEL, None).await;
+    assert_eq!(reply[0]["realm_id"], realm_id);
+    assert_eq!(reply[0]["name"], "Renamed");
+    assert_eq!(reply[0]["online"], true);
+
+    // Same result as over the reply protocol
+    let (_, reply) = http_request(http_address, "DELETE", "/users/A", PANEL, None).await;
+    assert_eq!(reply, json!({ "name": "A", "success": true }));
+    let (_, reply) = http_request(http_address, "DELETE", "/users/A", PANEL, None).await;
+    assert_eq!(reply, json!({ "name": "A", "success": false }));
+
+    server.stop().await;
 }
@@ -1,14 +1,70 @@
 use crate::{lib_main, ApplicationOptions};
-use std::net::Ipv4Addr;
-use std::sync::atomic::AtomicBool;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::task::JoinHandle;
-use warthog_lib::{CMD_AUTH_LOGON_CHALLENGE_Client, Options, Version};
-use warthog_messages::ClientOpcodes;
+use warthog_lib::{
+    CMD_AUTH_LOGON_CHALLENGE_Client, CMD_AUTH_RECONNECT_CHALLENGE_Client, Options, Version,
+};
+use warthog_messages::tls::{client::TlsStream, ServerName};
+use warthog_messages::{
+    client_proof, random_challenge, tokio_request_version, verify_server_proof, ClientOpcodes,
+    Modification, Permissions, RealmSummary, RegisterRealmError, MAX_LIST_LIMIT, PROTOCOL_VERSION,
+};
 use wow_client::{Locale, Os, Platform, ProtocolVersion};
 
+/// Addresses on localhost with ports that are not in use, so that tests can run in parallel.
+///
+/// All ports are bound at the same time so that they are different from each other.
+pub fn free_addresses<const N: usize>() -> [SocketAddr; N] {
+    let listeners: [std::net::TcpListener; N] =
+        std::array::from_fn(|_| std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap());
+
+    listeners.map(|a| a.local_addr().unwrap())
+}
+
+pub fn game_options(address: SocketAddr) -> Options {
+    Options {
+        address,
+        randomize_pin_grid: false,
+        max_concurrent_users: 10000,
+        max_concurrent_crypto_operations: 4,
+        unknown_account_secret: None,
+    }
+}
+
+/// The reply listeners are started after the game port that [`start_server`] waits for.
+pub async fn connect_retry(address: SocketAddr) -> TcpStream {
+    let mut i = 0;
+    loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => return stream,
+            Err(e) => {
+                assert_ne!(i, 20, "{e}");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Request ID used by the helpers that wait for their reply before returning.
+pub const REQUEST_ID: u32 = 1;
+
+pub async fn read_reply(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    expected_request_id: u32,
+) -> ClientOpcodes {
+    let (request_id, reply) = ClientOpcodes::tokio_read(stream).await.unwrap();
+    assert_eq!(request_id, expected_request_id);
+
+    reply
+}
+
 pub fn vanilla_1_12(account_name: String) -> CMD_AUTH_LOGON_CHALLENGE_Client {
     CMD_AUTH_LOGON_CHALLENGE_Client {
         protocol_version: ProtocolVersion::Three,
@@ -27,39 +83,203 @@ pub fn vanilla_1_12(account_name: String) -> CMD_AUTH_LOGON_CHALLENGE_Client {
     }
 }
 
-pub async fn register_realm(mut stream: &mut TcpStream, name: String, address: String) -> u8 {
+pub fn vanilla_1_12_reconnect(account_name: String) -> CMD_AUTH_RECONNECT_CHALLENGE_Client {
+    let c = vanilla_1_12(account_name);
+
+    CMD_AUTH_RECONNECT_CHALLENGE_Client {
+        protocol_version: c.protocol_version,
+        version: c.version,
+        platform: c.platform,
+        os: c.os,
+        locale: c.locale,
+        utc_timezone_offset: c.utc_timezone_offset,
+        client_ip_address: c.client_ip_address,
+        account_name: c.account_name,
+    }
+}
+
+pub async fn register_realm(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    name: String,
+    address: String,
+) -> u8 {
+    register_realm_with_id(stream, name, address, None)
+        .await
+        .unwrap()
+}
+
+pub async fn register_realm_with_id(
+    mut stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    name: String,
+    address: String,
+    realm_id: Option<u8>,
+) -> Result<u8, RegisterRealmError> {
+    register_realm_raw(stream, name, address, 0, 0, realm_id).await
+}
+
+/// Registers without checking that `category` and `realm_type` are valid.
+pub async fn register_realm_raw(
+    mut stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    name: String,
+    address: String,
+    category: u8,
+    realm_type: u8,
+    realm_id: Option<u8>,
+) -> Result<u8, RegisterRealmError> {
     warthog_messages::ServerOpcodes::RegisterRealm {
         name,
         address,
         population: 200.0,
         locked: false,
         flags: 0,
-        category: 0,
-        realm_type: 0,
+        category,
+        realm_type,
         version_major: 0,
         version_minor: 0,
         version_patch: 0,
         version_build: 0,
+        realm_id,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::RegisterRealmReply { result } => result,
+        _ => panic!(),
     }
-    .tokio_write(&mut stream)
+}
+
+pub async fn character_amount(mut stream: &mut TcpStream, name: String, amount: u8) {
+    warthog_messages::ServerOpcodes::CharacterAmountAnswer {
+        name: name.clone(),
+        amount_of_characters: amount,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
     .await
     .unwrap();
 
-    match ClientOpcodes::tokio_read(&mut stream).await.unwrap() {
-        ClientOpcodes::RegisterRealmReply { realm_id } => realm_id.unwrap(),
+    // There is no reply, so wait for another request to make sure the amount has been stored
+    warthog_messages::ServerOpcodes::RequestSessionKey { name }
+        .tokio_write(REQUEST_ID, &mut stream)
+        .await
+        .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::SessionKeyAnswer { .. } => {}
         _ => panic!(),
     }
 }
 
-pub async fn add_user(mut stream: &mut TcpStream, name: String, password: String) {
+pub async fn update_realm(
+    mut stream: &mut TcpStream,
+    population: Option<f32>,
+    locked: Option<bool>,
+) -> Result<(), RegisterRealmError> {
+    warthog_messages::ServerOpcodes::UpdateRealm {
+        name: None,
+        address: None,
+        population,
+        locked,
+        flags: None,
+        category: None,
+        realm_type: None,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::UpdateRealmReply { result } => result,
+        _ => panic!(),
+    }
+}
+
+pub async fn rename_realm(
+    mut stream: &mut TcpStream,
+    name: String,
+) -> Result<(), RegisterRealmError> {
+    warthog_messages::ServerOpcodes::UpdateRealm {
+        name: Some(name),
+        address: None,
+        population: None,
+        locked: None,
+        flags: None,
+        category: None,
+        realm_type: None,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::UpdateRealmReply { result } => result,
+        _ => panic!(),
+    }
+}
+
+pub async fn list_realms(mut stream: &mut TcpStream) -> Vec<RealmSummary> {
+    warthog_messages::ServerOpcodes::ListRealms {
+        offset: 0,
+        limit: MAX_LIST_LIMIT,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::ListRealmsReply {
+            realms: Some(realms),
+        } => realms,
+        _ => panic!(),
+    }
+}
+
+/// Returns whether the only registered realm is online.
+pub async fn realm_online(stream: &mut TcpStream) -> bool {
+    match list_realms(stream).await.as_slice() {
+        [realm] => realm.online,
+        _ => panic!(),
+    }
+}
+
+/// Lists the realms until `done` returns `true`, for changes that happen in the background.
+pub async fn wait_for_realms(stream: &mut TcpStream, done: impl Fn(&[RealmSummary]) -> bool) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !done(&list_realms(stream).await) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// Returns whether the realm is online right after the heartbeat.
+///
+/// Heartbeats have no reply, but realm messages are handled in order,
+/// so the heartbeat has been handled when the realm list is answered.
+pub async fn heartbeat(mut stream: &mut TcpStream) -> bool {
+    warthog_messages::ServerOpcodes::Heartbeat
+        .tokio_write(REQUEST_ID, &mut stream)
+        .await
+        .unwrap();
+
+    realm_online(stream).await
+}
+
+pub async fn add_user(
+    mut stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    name: String,
+    password: String,
+) {
     let original_name = name.clone();
 
     warthog_messages::ServerOpcodes::AddUser { name, password }
-        .tokio_write(&mut stream)
+        .tokio_write(REQUEST_ID, &mut stream)
         .await
         .unwrap();
 
-    match ClientOpcodes::tokio_read(&mut stream).await.unwrap() {
+    match read_reply(&mut stream, REQUEST_ID).await {
         ClientOpcodes::AddUserReply { name, success } => {
             assert_eq!(name, original_name);
             assert!(success);
@@ -89,3 +309,196 @@ pub async fn start_server(
 
     (should_run, main)
 }
+
+/// Server on free ports that is stopped with [`Self::stop`].
+pub struct TestServer {
+    pub game_address: SocketAddr,
+    pub reply_address: SocketAddr,
+    should_run: Arc<AtomicBool>,
+    main: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Insecure reply server, with the default options otherwise.
+    pub async fn start() -> Self {
+        Self::start_with(|_, _| {}).await
+    }
+
+    /// `configure` is called with the game options and an insecure reply server on the free ports.
+    pub async fn start_with(configure: impl FnOnce(&mut Options, &mut ApplicationOptions)) -> Self {
+        let [reply_address, game_address] = free_addresses();
+
+        let mut options = game_options(game_address);
+        let mut application_options = ApplicationOptions {
+            reply_address: Some(reply_address),
+            reply_insecure: true,
+            ..Default::default()
+        };
+        configure(&mut options, &mut application_options);
+
+        let (should_run, main) = start_server(options, application_options).await;
+
+        Self {
+            game_address,
+            reply_address,
+            should_run,
+            main,
+        }
+    }
+
+    pub async fn stop(self) {
+        self.should_run.store(false, Ordering::SeqCst);
+        self.main.await.unwrap();
+    }
+}
+
+pub async fn remove_user(mut stream: &mut TcpStream, name: String) -> bool {
+    let original_name = name.clone();
+
+    warthog_messages::ServerOpcodes::RemoveUser { name }
+        .tokio_write(REQUEST_ID, &mut stream)
+        .await
+        .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::RemoveUserReply { name, success } => {
+            assert_eq!(name, original_name);
+            success
+        }
+        _ => panic!(),
+    }
+}
+
+pub async fn change_password(mut stream: &mut TcpStream, name: String, password: String) -> bool {
+    let original_name = name.clone();
+
+    warthog_messages::ServerOpcodes::ModifyUser {
+        name,
+        password: Some(password),
+        pin: Modification::Unchanged,
+        matrix_card: Modification::Unchanged,
+        account_flags: None,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::ModifyUserReply { name, success } => {
+            assert_eq!(name, original_name);
+            success
+        }
+        _ => panic!(),
+    }
+}
+
+pub async fn authenticate_reply(
+    mut stream: &mut TcpStream,
+    name: String,
+    key: &[u8],
+) -> Option<Permissions> {
+    warthog_messages::ServerOpcodes::RequestAuthChallenge
+        .tokio_write(REQUEST_ID, &mut stream)
+        .await
+        .unwrap();
+
+    let server_challenge = match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::AuthChallenge { server_challenge } => server_challenge,
+        _ => panic!(),
+    };
+
+    let client_challenge = random_challenge();
+    warthog_messages::ServerOpcodes::AuthProof {
+        client_proof: client_proof(key, &name, &server_challenge, &client_challenge).unwrap(),
+        name: name.clone(),
+        client_challenge,
+    }
+    .tokio_write(REQUEST_ID, &mut stream)
+    .await
+    .unwrap();
+
+    match read_reply(&mut stream, REQUEST_ID).await {
+        ClientOpcodes::AuthProofReply { accepted } => accepted.map(|accepted| {
+            assert!(verify_server_proof(
+                key,
+                &name,
+                &server_challenge,
+                &client_challenge,
+                &accepted.server_proof,
+            ));
+            accepted.permissions
+        }),
+        _ => panic!(),
+    }
+}
+
+pub fn test_certificate(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/test/certs")
+        .join(name)
+}
+
+pub async fn connect_tls(
+    address: SocketAddr,
+    client_identity: bool,
+) -> std::io::Result<TlsStream<TcpStream>> {
+    let certificate = test_certificate("client.pem");
+    let private_key = test_certificate("client.key");
+    let connector = warthog_messages::tls::connector(
+        &test_certificate("ca.pem"),
+        client_identity.then_some((certificate.as_path(), private_key.as_path())),
+    )
+    .unwrap();
+
+    let stream = connect_retry(address).await;
+    let mut stream = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await?;
+
+    tokio_request_version(&mut stream)
+        .await
+        .map_err(std::io::Error::other)?;
+
+    Ok(stream)
+}
+
+pub async fn connect_reply(address: SocketAddr) -> TcpStream {
+    let mut stream = connect_retry(address).await;
+    assert_eq!(
+        tokio_request_version(&mut stream).await.unwrap(),
+        PROTOCOL_VERSION
+    );
+
+    stream
+}
+
+/// Sends a single request to the HTTP gateway, `key` is `NAME:KEY`.
+///
+/// Returns the status code and the JSON body.
+pub async fn http_request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    key: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (u16, serde_json::Value) {
+    let mut stream = connect_retry(address).await;
+
+    let body = body.map(|a| a.to_string()).unwrap_or_default();
+    let authorization = key
+        .map(|a| format!("Authorization: Bearer {a}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+        Content-Type: application/json\r\n{authorization}Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+    (status, serde_json::from_str(body).unwrap())
+}
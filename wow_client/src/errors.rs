@@ -18,6 +18,7 @@ pub enum ClientError {
     InvalidSecurityFlag,
     MatchProofs(MatchProofsError),
     InvalidFieldSet,
+    InvalidTransfer,
 }
 
 impl ClientError {
@@ -40,6 +41,7 @@ impl Display for ClientError {
             ClientError::InvalidSecurityFlag => write!(f, "invalid security flag"),
             ClientError::MatchProofs(e) => e.fmt(f),
             ClientError::InvalidFieldSet => write!(f, "invalid field set"),
+            ClientError::InvalidTransfer => write!(f, "invalid message during file transfer"),
         }
     }
 }
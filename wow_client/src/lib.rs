@@ -1,6 +1,6 @@
 mod errors;
 
-use crate::errors::ClientError;
+pub use crate::errors::ClientError;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use wow_login_messages::helper::tokio_expect_server_message_protocol;
@@ -10,9 +10,10 @@ use wow_srp::normalized_string::NormalizedString;
 use wow_srp::{PublicKey, LARGE_SAFE_PRIME_LENGTH};
 
 pub use wow_login_messages::all::{
-    CMD_AUTH_LOGON_CHALLENGE_Client, Locale, Os, Platform, ProtocolVersion, Version,
+    CMD_AUTH_LOGON_CHALLENGE_Client, CMD_AUTH_RECONNECT_CHALLENGE_Client, Locale, Os, Platform,
+    ProtocolVersion, Version,
 };
-pub use wow_login_messages::version_8::Realm;
+pub use wow_login_messages::version_8::{LoginResult, Realm};
 use wow_srp::pin::PinCode;
 
 pub async fn connect_and_authenticate(
@@ -233,6 +234,17 @@ pub async fn connect_and_authenticate(
         }
     };
 
+    let realms = request_realm_list(&mut stream, protocol_version).await?;
+
+    Ok((client, realms, stream))
+}
+
+pub async fn request_realm_list(
+    mut stream: &mut TcpStream,
+    protocol_version: ProtocolVersion,
+) -> Result<Vec<Realm>, ClientError> {
+    use wow_login_messages::version_8::{CMD_REALM_LIST_Client, CMD_REALM_LIST_Server};
+
     CMD_REALM_LIST_Client {}
         .tokio_write_protocol(&mut stream, protocol_version)
         .await?;
@@ -243,5 +255,108 @@ pub async fn connect_and_authenticate(
     )
     .await?;
 
-    Ok((client, s.realms, stream))
+    Ok(s.realms)
+}
+
+pub async fn reconnect(
+    message: CMD_AUTH_RECONNECT_CHALLENGE_Client,
+    address: SocketAddr,
+    client: &SrpClient,
+) -> Result<(Vec<Realm>, TcpStream), ClientError> {
+    use wow_login_messages::version_8::{
+        CMD_AUTH_RECONNECT_CHALLENGE_Server, CMD_AUTH_RECONNECT_PROOF_Client,
+        CMD_AUTH_RECONNECT_PROOF_Server,
+    };
+
+    let protocol_version = message.protocol_version;
+
+    let mut stream = TcpStream::connect(address).await?;
+
+    message
+        .tokio_write_protocol(&mut stream, protocol_version)
+        .await?;
+
+    let s = tokio_expect_server_message_protocol::<CMD_AUTH_RECONNECT_CHALLENGE_Server, _>(
+        &mut stream,
+        protocol_version,
+    )
+    .await?;
+
+    let challenge_data = match s {
+        CMD_AUTH_RECONNECT_CHALLENGE_Server::Success { challenge_data, .. } => challenge_data,
+        CMD_AUTH_RECONNECT_CHALLENGE_Server::FailUnknownAccount => {
+            return Err(ClientError::reply(LoginResult::FailUnknownAccount));
+        }
+        _ => return Err(ClientError::reply(LoginResult::FailUnknown0)),
+    };
+
+    let values = client.calculate_reconnect_values(challenge_data);
+
+    CMD_AUTH_RECONNECT_PROOF_Client {
+        proof_data: values.challenge_data,
+        client_proof: values.client_proof,
+        client_checksum: wow_srp::integrity::reconnect_integrity_check(&values.challenge_data),
+    }
+    .tokio_write_protocol(&mut stream, protocol_version)
+    .await?;
+
+    let s = tokio_expect_server_message_protocol::<CMD_AUTH_RECONNECT_PROOF_Server, _>(
+        &mut stream,
+        protocol_version,
+    )
+    .await?;
+
+    if s.result != LoginResult::Success {
+        return Err(ClientError::reply(s.result));
+    }
+
+    let realms = request_realm_list(&mut stream, protocol_version).await?;
+
+    Ok((realms, stream))
+}
+
+/// Downloads the patch offered by the server.
+///
+/// Returns the file data and the MD5 hash reported by the server.
+pub async fn download_patch(
+    message: CMD_AUTH_LOGON_CHALLENGE_Client,
+    address: SocketAddr,
+) -> Result<(Vec<u8>, [u8; 16]), ClientError> {
+    use wow_login_messages::version_8::opcodes::ServerOpcodeMessage;
+    use wow_login_messages::version_8::{CMD_AUTH_LOGON_CHALLENGE_Server, CMD_XFER_ACCEPT};
+    use wow_login_messages::Message;
+
+    let protocol_version = message.protocol_version;
+
+    let mut stream = TcpStream::connect(address).await?;
+
+    message
+        .tokio_write_protocol(&mut stream, protocol_version)
+        .await?;
+
+    let s = tokio_expect_server_message_protocol::<CMD_AUTH_LOGON_CHALLENGE_Server, _>(
+        &mut stream,
+        protocol_version,
+    )
+    .await?;
+    if !matches!(s, CMD_AUTH_LOGON_CHALLENGE_Server::LoginDownloadFile) {
+        return Err(ClientError::InvalidTransfer);
+    }
+
+    let (file_size, file_md5) = match ServerOpcodeMessage::tokio_read(&mut stream).await? {
+        ServerOpcodeMessage::CMD_XFER_INITIATE(s) => (s.file_size, s.file_md5),
+        _ => return Err(ClientError::InvalidTransfer),
+    };
+
+    CMD_XFER_ACCEPT {}.tokio_write(&mut stream).await?;
+
+    let mut data = Vec::new();
+    while (data.len() as u64) < file_size {
+        match ServerOpcodeMessage::tokio_read(&mut stream).await? {
+            ServerOpcodeMessage::CMD_XFER_DATA(s) => data.extend_from_slice(&s.data),
+            _ => return Err(ClientError::InvalidTransfer),
+        }
+    }
+
+    Ok((data, file_md5))
 }